@@ -0,0 +1,232 @@
+//! Many concurrent high-output sessions against a real `forge serve`.
+//!
+//! Reports how long the sessions take to drain their output and how the list
+//! and history endpoints respond meanwhile. Tune with `BENCH_SESSIONS` and
+//! `BENCH_MB` (output per session):
+//!
+//! ```bash
+//! BENCH_SESSIONS=64 BENCH_MB=16 cargo bench --bench sessions
+//! ```
+
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::time::{Duration, Instant};
+
+struct Server {
+    child: Child,
+    home: PathBuf,
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+        let _ = std::fs::remove_dir_all(&self.home);
+    }
+}
+
+fn env_or(name: &str, default: usize) -> usize {
+    std::env::var(name)
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(default)
+}
+
+fn free_port() -> u16 {
+    std::net::TcpListener::bind("127.0.0.1:0")
+        .and_then(|l| l.local_addr())
+        .map(|a| a.port())
+        .expect("no free port")
+}
+
+/// Start `forge serve` in a scratch HOME whose tool floods the PTY.
+fn start_server(port: u16, bytes_per_session: usize) -> Server {
+    let home = std::env::temp_dir().join(format!("forge-bench-{}", std::process::id()));
+    let config_dir = home.join(".config/lineforge");
+    std::fs::create_dir_all(&config_dir).unwrap();
+    // The XDG dirs take precedence over HOME, so they are redirected too;
+    // otherwise a server already running for this user would be found
+    let xdg = [
+        ("XDG_CONFIG_HOME", home.join(".config")),
+        ("XDG_DATA_HOME", home.join(".local/share")),
+        ("XDG_STATE_HOME", home.join(".local/state")),
+        ("XDG_CACHE_HOME", home.join(".cache")),
+        ("XDG_RUNTIME_DIR", home.join("run")),
+    ];
+    for (_, dir) in &xdg {
+        std::fs::create_dir_all(dir).unwrap();
+    }
+    std::fs::set_permissions(
+        home.join("run"),
+        std::os::unix::fs::PermissionsExt::from_mode(0o700),
+    )
+    .unwrap();
+
+    let tool = home.join("flood.sh");
+    std::fs::write(
+        &tool,
+        format!("#!/bin/sh\nhead -c {bytes_per_session} /dev/urandom | base64\n"),
+    )
+    .unwrap();
+    std::fs::set_permissions(&tool, std::os::unix::fs::PermissionsExt::from_mode(0o755)).unwrap();
+
+    std::fs::write(
+        config_dir.join("config.toml"),
+        format!(
+            "port = {port}\nbind = \"127.0.0.1\"\ntool_path = \"{}\"\n",
+            tool.display()
+        ),
+    )
+    .unwrap();
+
+    let child = Command::new(env!("CARGO_BIN_EXE_forge"))
+        .arg("serve")
+        .env("HOME", &home)
+        .envs(xdg)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .expect("failed to start forge serve");
+    Server { child, home }
+}
+
+async fn wait_ready(client: &reqwest::Client, base: &str) {
+    for _ in 0..100 {
+        if client.get(format!("{base}/health")).send().await.is_ok() {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    panic!("forge serve did not start");
+}
+
+async fn running(client: &reqwest::Client, base: &str) -> usize {
+    let resp = client
+        .get(format!("{base}/api/sessions?status=running&limit=1"))
+        .send()
+        .await
+        .unwrap();
+    resp.headers()
+        .get("x-total-count")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(0)
+}
+
+fn percentile(sorted: &[Duration], p: f64) -> Duration {
+    if sorted.is_empty() {
+        return Duration::ZERO;
+    }
+    sorted[((sorted.len() - 1) as f64 * p).round() as usize]
+}
+
+fn report(name: &str, samples: &mut [Duration]) {
+    samples.sort();
+    println!(
+        "{name:<10} n={:<6} p50={:>9.2?} p99={:>9.2?} max={:>9.2?}",
+        samples.len(),
+        percentile(samples, 0.5),
+        percentile(samples, 0.99),
+        samples.last().copied().unwrap_or_default(),
+    );
+}
+
+fn session_bytes(home: &Path) -> u64 {
+    let Ok(dirs) = std::fs::read_dir(home.join(".local/share/lineforge/sessions")) else {
+        return 0;
+    };
+    dirs.flatten()
+        .filter_map(|d| std::fs::read_dir(d.path()).ok())
+        .flatten()
+        .flatten()
+        .filter(|f| f.file_name().to_string_lossy().starts_with("output.log"))
+        .filter_map(|f| f.metadata().ok())
+        .map(|m| m.len())
+        .sum()
+}
+
+#[tokio::main]
+async fn main() {
+    let sessions = env_or("BENCH_SESSIONS", 32);
+    let mb = env_or("BENCH_MB", 4);
+    let port = free_port();
+    let server = start_server(port, mb * 1024 * 1024 * 3 / 4);
+    let base = format!("http://127.0.0.1:{port}");
+    let client = reqwest::Client::new();
+    wait_ready(&client, &base).await;
+
+    println!("{sessions} sessions x {mb} MB of output");
+    let ids = std::sync::Arc::new(std::sync::Mutex::new(Vec::<String>::new()));
+    let done = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
+
+    // Probe the API for the whole run, while the sessions flood their PTYs
+    let probe = tokio::spawn({
+        let (client, base, ids, done) = (client.clone(), base.clone(), ids.clone(), done.clone());
+        async move {
+            let mut list = Vec::new();
+            let mut history = Vec::new();
+            let mut i = 0;
+            while !done.load(std::sync::atomic::Ordering::Relaxed) {
+                let t = Instant::now();
+                client
+                    .get(format!("{base}/api/sessions"))
+                    .send()
+                    .await
+                    .unwrap();
+                list.push(t.elapsed());
+
+                let id = {
+                    let ids = ids.lock().unwrap();
+                    (!ids.is_empty()).then(|| ids[i % ids.len()].clone())
+                };
+                if let Some(id) = id {
+                    let t = Instant::now();
+                    client
+                        .get(format!("{base}/api/sessions/{id}/logs/history?tail=50"))
+                        .send()
+                        .await
+                        .unwrap();
+                    history.push(t.elapsed());
+                }
+                i += 1;
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+            (list, history)
+        }
+    });
+
+    let start = Instant::now();
+    let mut create = Vec::new();
+    for _ in 0..sessions {
+        let t = Instant::now();
+        let resp = client
+            .post(format!("{base}/api/sessions"))
+            .json(&serde_json::json!({ "working_dir": "/tmp" }))
+            .send()
+            .await
+            .unwrap();
+        create.push(t.elapsed());
+        let meta: serde_json::Value = resp.json().await.unwrap();
+        ids.lock()
+            .unwrap()
+            .push(meta["id"].as_str().unwrap().to_string());
+    }
+    while running(&client, &base).await > 0 {
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    let elapsed = start.elapsed();
+    done.store(true, std::sync::atomic::Ordering::Relaxed);
+    let (mut list, mut history) = probe.await.unwrap();
+
+    // Give the log writers a moment to flush before measuring disk output
+    tokio::time::sleep(Duration::from_millis(1500)).await;
+    let written = session_bytes(&server.home) as f64 / (1024.0 * 1024.0);
+
+    println!(
+        "drain      {elapsed:.2?}, {written:.1} MB written ({:.1} MB/s)",
+        written / elapsed.as_secs_f64()
+    );
+    report("create", &mut create);
+    report("list", &mut list);
+    report("history", &mut history);
+}
@@ -1,9 +1,9 @@
 use std::path::PathBuf;
 
 use anyhow::Result;
-use clap::{CommandFactory, Parser, Subcommand};
+use clap::{Args, CommandFactory, Parser, Subcommand};
 
-use crate::config::Config;
+use crate::config::{Config, IoPriority, ResourceLimits};
 
 #[derive(Parser)]
 #[command(name = "forge", version, about = "Lineforge - AI session manager")]
@@ -27,6 +27,24 @@ pub enum Command {
         /// Path to config file
         #[arg(long)]
         config: Option<PathBuf>,
+
+        /// Run in the background, logging to server.log
+        #[arg(long)]
+        daemon: bool,
+    },
+
+    /// Stop the running server
+    #[command(name = "stop-server")]
+    StopServer,
+
+    /// Restart the running server with the same arguments
+    #[command(name = "restart-server")]
+    RestartServer,
+
+    /// Run the server under the user's service manager (systemd or launchd)
+    Service {
+        #[command(subcommand)]
+        action: ServiceAction,
     },
 
     /// Create a new session and attach
@@ -47,6 +65,12 @@ pub enum Command {
         #[arg(long)]
         no_iterm: bool,
 
+        #[command(flatten)]
+        env: EnvArgs,
+
+        #[command(flatten)]
+        limits: LimitArgs,
+
         /// Extra arguments passed to the CLI tool
         #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
         extra_args: Vec<String>,
@@ -71,6 +95,12 @@ pub enum Command {
         #[arg(long)]
         no_iterm: bool,
 
+        #[command(flatten)]
+        env: EnvArgs,
+
+        #[command(flatten)]
+        limits: LimitArgs,
+
         /// Extra arguments passed to the CLI tool
         #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
         extra_args: Vec<String>,
@@ -82,8 +112,14 @@ pub enum Command {
         id: String,
     },
 
-    /// List all sessions
-    List,
+    /// List sessions
+    List(ListArgs),
+
+    /// Print a session's output without attaching
+    Logs(LogsArgs),
+
+    /// Search the output of all sessions
+    Search(SearchArgs),
 
     /// Stop a session
     Kill {
@@ -93,6 +129,193 @@ pub enum Command {
 
     /// Open interactive settings
     Settings,
+
+    /// Check the environment for common setup problems
+    Doctor {
+        /// Print the report as JSON
+        #[arg(long)]
+        json: bool,
+
+        /// Path to config file
+        #[arg(long)]
+        config: Option<PathBuf>,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum ServiceAction {
+    /// Write a systemd user unit (a launchd agent on macOS) and enable it
+    Install {
+        /// Config file the service passes to `forge serve`
+        #[arg(long)]
+        config: Option<PathBuf>,
+
+        /// Only write the file
+        #[arg(long)]
+        no_enable: bool,
+    },
+
+    /// Disable the service and remove its file
+    Uninstall,
+}
+
+/// Session environment flags shared by `new` and `new-session`
+#[derive(Args)]
+pub struct EnvArgs {
+    /// Set an environment variable for the session (repeatable)
+    #[arg(long = "env", short = 'e', value_name = "KEY=VALUE", value_parser = parse_env_var)]
+    pub vars: Vec<(String, String)>,
+
+    /// Load environment variables from a file (repeatable)
+    #[arg(long = "env-file", value_name = "PATH")]
+    pub files: Vec<PathBuf>,
+
+    /// Load the project's .env and .envrc
+    #[arg(long)]
+    pub dotenv: bool,
+}
+
+/// Resource limit flags shared by `new` and `new-session`; unset ones come
+/// from the config
+#[derive(Args)]
+pub struct LimitArgs {
+    /// Memory limit for the session's processes, in MB
+    #[arg(long, value_name = "MB")]
+    pub memory_mb: Option<u64>,
+
+    /// CPU limit, in percent of one core
+    #[arg(long, value_name = "PERCENT")]
+    pub cpu_percent: Option<u32>,
+
+    /// Maximum number of processes and threads
+    #[arg(long, value_name = "N")]
+    pub max_pids: Option<u64>,
+
+    /// Scheduling niceness, from -20 to 19
+    #[arg(long, allow_negative_numbers = true)]
+    pub nice: Option<i32>,
+
+    /// I/O priority: low or idle
+    #[arg(long, value_parser = parse_io_priority)]
+    pub ionice: Option<IoPriority>,
+}
+
+impl LimitArgs {
+    pub fn limits(&self) -> ResourceLimits {
+        ResourceLimits {
+            memory_mb: self.memory_mb,
+            cpu_percent: self.cpu_percent,
+            max_pids: self.max_pids,
+            nice: self.nice,
+            ionice: self.ionice,
+        }
+    }
+}
+
+/// Filters and output options for `list`
+#[derive(Args)]
+pub struct ListArgs {
+    /// Only sessions with this status (running, stopped or errored)
+    #[arg(long)]
+    pub status: Option<String>,
+
+    /// Only sessions using this tool (claude or codex)
+    #[arg(long)]
+    pub tool: Option<String>,
+
+    /// Only sessions in this directory or below it
+    #[arg(long)]
+    pub cwd: Option<PathBuf>,
+
+    /// Only sessions created since a timestamp or age (e.g. 30m, 2h, 7d)
+    #[arg(long)]
+    pub since: Option<String>,
+
+    /// Only sessions carrying this tag
+    #[arg(long)]
+    pub tag: Option<String>,
+
+    /// Show at most this many sessions
+    #[arg(long)]
+    pub limit: Option<usize>,
+
+    /// Print sessions as JSON
+    #[arg(long, conflicts_with = "format")]
+    pub json: bool,
+
+    /// Print each session through a template, e.g. "{short_id}\t{name}\t{activity}"
+    #[arg(long)]
+    pub format: Option<String>,
+}
+
+/// Options for `logs`
+#[derive(Args)]
+pub struct LogsArgs {
+    /// Session ID (UUID, prefix or name)
+    pub id: String,
+
+    /// Keep printing new output until the session exits
+    #[arg(long, short = 'f')]
+    pub follow: bool,
+
+    /// Only the last N lines
+    #[arg(long, short = 'n', value_name = "N")]
+    pub tail: Option<usize>,
+
+    /// Only output since a timestamp or age (e.g. 30m, 2h)
+    #[arg(long)]
+    pub since: Option<String>,
+
+    /// Remove escape sequences (the default when stdout isn't a terminal)
+    #[arg(long, conflicts_with = "raw")]
+    pub strip_ansi: bool,
+
+    /// Print output exactly as the tool wrote it, even when piped
+    #[arg(long)]
+    pub raw: bool,
+}
+
+/// Options for `search`
+#[derive(Args)]
+pub struct SearchArgs {
+    /// Words to look for; a line matches when it contains all of them
+    #[arg(required = true)]
+    pub query: Vec<String>,
+
+    /// Only sessions with this name or UUID prefix
+    #[arg(long)]
+    pub session: Option<String>,
+
+    /// Only output since a timestamp or age (e.g. 30m, 2h, 7d)
+    #[arg(long)]
+    pub since: Option<String>,
+
+    /// Only output before a timestamp or age
+    #[arg(long)]
+    pub until: Option<String>,
+
+    /// Show at most this many matches
+    #[arg(long)]
+    pub limit: Option<usize>,
+
+    /// Print matches as JSON
+    #[arg(long)]
+    pub json: bool,
+}
+
+fn parse_env_var(s: &str) -> Result<(String, String), String> {
+    s.split_once('=')
+        .filter(|(k, _)| !k.is_empty())
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .ok_or_else(|| format!("expected KEY=VALUE, got '{s}'"))
+}
+
+fn parse_io_priority(s: &str) -> Result<IoPriority, String> {
+    match s {
+        "low" => Ok(IoPriority::Low),
+        "idle" => Ok(IoPriority::Idle),
+        other => Err(format!("expected low or idle, got '{other}'")),
+    }
 }
 
 pub async fn dispatch(cli: Cli) -> Result<()> {
@@ -103,8 +326,17 @@ pub async fn dispatch(cli: Cli) -> Result<()> {
             return Ok(());
         }
     };
+    // The server sets up its own logging from the config
+    if !matches!(command, Command::Serve { daemon: false, .. }) {
+        crate::telemetry::init_cli();
+    }
     match command {
-        Command::Serve { port, bind, config } => {
+        Command::Serve {
+            port,
+            bind,
+            config,
+            daemon,
+        } => {
             let mut cfg = Config::load(config.as_ref())?;
             if let Some(p) = port {
                 cfg.port = p;
@@ -113,19 +345,49 @@ pub async fn dispatch(cli: Cli) -> Result<()> {
                 cfg.bind = b;
             }
             cfg.ensure_dirs()?;
-            crate::server::start(cfg).await?;
+            if daemon {
+                let args: Vec<String> = std::env::args()
+                    .skip(1)
+                    .filter(|a| a != "--daemon")
+                    .collect();
+                crate::server::daemon::daemonize(&cfg, &args).await?;
+            } else {
+                let _telemetry = crate::telemetry::init_server(&cfg.logging)?;
+                crate::server::start(cfg).await?;
+            }
+        }
+        Command::StopServer => {
+            crate::server::daemon::stop(&Config::load(None)?).await?;
         }
+        Command::RestartServer => {
+            crate::server::daemon::restart(&Config::load(None)?).await?;
+        }
+        Command::Service { action } => match action {
+            ServiceAction::Install { config, no_enable } => {
+                super::service::install(config.as_ref(), !no_enable)?;
+            }
+            ServiceAction::Uninstall => super::service::uninstall()?,
+        },
         Command::New {
             label,
             cwd,
             tool,
             no_iterm: _,
+            env,
+            limits,
             extra_args,
         } => {
             let cfg = Config::load(None)?;
-            let id =
-                crate::session::manager::create_session_cli(&cfg, label, cwd, tool, extra_args)
-                    .await?;
+            let id = crate::session::manager::create_session_cli(
+                &cfg,
+                label,
+                cwd,
+                tool,
+                extra_args,
+                &env,
+                &limits.limits(),
+            )
+            .await?;
             crate::session::manager::attach_session_cli(&id.to_string()).await?;
         }
         Command::NewSession {
@@ -133,19 +395,34 @@ pub async fn dispatch(cli: Cli) -> Result<()> {
             cwd,
             tool,
             no_iterm: _,
+            env,
+            limits,
             extra_args,
         } => {
             let cfg = Config::load(None)?;
-            let id =
-                crate::session::manager::create_session_cli(&cfg, label, cwd, tool, extra_args)
-                    .await?;
+            let id = crate::session::manager::create_session_cli(
+                &cfg,
+                label,
+                cwd,
+                tool,
+                extra_args,
+                &env,
+                &limits.limits(),
+            )
+            .await?;
             println!("Created session: {id}");
         }
         Command::Attach { id } => {
             crate::session::manager::attach_session_cli(&id).await?;
         }
-        Command::List => {
-            crate::session::manager::list_sessions_cli().await?;
+        Command::List(args) => {
+            crate::session::manager::list_sessions_cli(&args).await?;
+        }
+        Command::Logs(args) => {
+            crate::session::manager::logs_session_cli(&args).await?;
+        }
+        Command::Search(args) => {
+            crate::session::manager::search_cli(&args).await?;
         }
         Command::Kill { id } => {
             crate::session::manager::kill_session_cli(&id).await?;
@@ -153,6 +430,19 @@ pub async fn dispatch(cli: Cli) -> Result<()> {
         Command::Settings => {
             super::settings::run()?;
         }
+        Command::Doctor { json, config } => {
+            let path = config.clone().unwrap_or_else(Config::config_path);
+            let cfg = Config::load(config.as_ref());
+            let report = crate::doctor::run(cfg, &path, crate::doctor::Origin::Cli).await;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&report)?);
+            } else {
+                crate::doctor::print_report(&report);
+            }
+            if report.status == crate::doctor::CheckStatus::Fail {
+                std::process::exit(1);
+            }
+        }
     }
     Ok(())
 }
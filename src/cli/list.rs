@@ -0,0 +1,125 @@
+use chrono::{DateTime, TimeDelta, Utc};
+
+use crate::session::model::{SessionStatus, SessionSummary};
+
+const COLUMNS: [&str; 8] = [
+    "ID", "NAME", "TOOL", "STATUS", "ACTIVITY", "CREATED", "DURATION", "CWD",
+];
+
+/// Print sessions as an aligned table.
+pub fn print_table(sessions: &[SessionSummary], now: DateTime<Utc>) {
+    let rows: Vec<[String; 8]> = sessions
+        .iter()
+        .map(|s| {
+            [
+                short_id(s),
+                s.meta.name.clone(),
+                s.meta.tool.to_string(),
+                status_label(&s.meta.status).to_string(),
+                s.activity.to_string(),
+                format!("{} ago", format_age(now - s.meta.created_at)),
+                format_age(duration(s, now)),
+                display_path(&s.meta.working_dir),
+            ]
+        })
+        .collect();
+
+    let mut widths = COLUMNS.map(str::len);
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    print_row(&COLUMNS.map(String::from), &widths);
+    for row in &rows {
+        print_row(row, &widths);
+    }
+}
+
+fn print_row(cells: &[String; 8], widths: &[usize; 8]) {
+    let line: Vec<String> = cells
+        .iter()
+        .zip(widths)
+        .map(|(cell, width)| format!("{cell:<width$}"))
+        .collect();
+    println!("{}", line.join("  ").trim_end());
+}
+
+/// Render a session through a `--format` template. Placeholders are
+/// `{id}`, `{short_id}`, `{name}`, `{tool}`, `{status}`, `{activity}`,
+/// `{cwd}`, `{created}`, `{age}`, `{duration}` and `{pid}`; `\t` and `\n`
+/// are unescaped.
+pub fn render_format(template: &str, s: &SessionSummary, now: DateTime<Utc>) -> String {
+    let fields = [
+        ("{id}", s.meta.id.to_string()),
+        ("{short_id}", short_id(s)),
+        ("{name}", s.meta.name.clone()),
+        ("{tool}", s.meta.tool.to_string()),
+        ("{status}", s.meta.status.to_string()),
+        ("{activity}", s.activity.to_string()),
+        ("{cwd}", s.meta.working_dir.display().to_string()),
+        ("{created}", s.meta.created_at.to_rfc3339()),
+        ("{age}", format_age(now - s.meta.created_at)),
+        ("{duration}", format_age(duration(s, now))),
+        (
+            "{pid}",
+            s.meta.pid.map(|p| p.to_string()).unwrap_or_default(),
+        ),
+    ];
+    let mut out = template.replace("\\t", "\t").replace("\\n", "\n");
+    for (placeholder, value) in fields {
+        out = out.replace(placeholder, &value);
+    }
+    out
+}
+
+fn short_id(s: &SessionSummary) -> String {
+    s.meta.id.to_string()[..8].to_string()
+}
+
+fn status_label(status: &SessionStatus) -> &str {
+    match status {
+        SessionStatus::Running => "running",
+        SessionStatus::Stopped => "stopped",
+        SessionStatus::Errored(_) => "errored",
+    }
+}
+
+/// How long the session has been (or was) running.
+fn duration(s: &SessionSummary, now: DateTime<Utc>) -> TimeDelta {
+    let end = match s.meta.status {
+        SessionStatus::Running => now,
+        _ => s.meta.updated_at,
+    };
+    end - s.meta.created_at
+}
+
+/// Compact duration such as `45s`, `12m`, `3h 05m` or `2d 4h`.
+pub fn format_age(age: TimeDelta) -> String {
+    let secs = age.num_seconds().max(0);
+    let (days, hours, mins) = (secs / 86_400, secs % 86_400 / 3600, secs % 3600 / 60);
+    if days > 0 {
+        format!("{days}d {hours}h")
+    } else if hours > 0 {
+        format!("{hours}h {mins:02}m")
+    } else if mins > 0 {
+        format!("{mins}m")
+    } else {
+        format!("{secs}s")
+    }
+}
+
+/// Working dir with the home directory shortened to `~`.
+fn display_path(path: &std::path::Path) -> String {
+    if let Some(home) = dirs::home_dir()
+        && let Ok(rest) = path.strip_prefix(&home)
+    {
+        return if rest.as_os_str().is_empty() {
+            "~".into()
+        } else {
+            format!("~/{}", rest.display())
+        };
+    }
+    path.display().to_string()
+}
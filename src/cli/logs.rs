@@ -0,0 +1,73 @@
+use std::io::Write;
+
+use crate::session::ansi::strip_ansi;
+
+/// Writes session output to stdout, optionally as plain text.
+///
+/// Escape sequences can straddle chunks, so when stripping, output is held
+/// back until a complete line is available.
+pub struct LogPrinter {
+    strip: bool,
+    pending: Vec<u8>,
+}
+
+impl LogPrinter {
+    pub fn new(strip: bool) -> Self {
+        Self {
+            strip,
+            pending: Vec::new(),
+        }
+    }
+
+    pub fn write(&mut self, data: &[u8]) -> std::io::Result<()> {
+        let mut out = std::io::stdout().lock();
+        if !self.strip {
+            out.write_all(data)?;
+            return out.flush();
+        }
+
+        // A newline never falls inside a UTF-8 character, so whole lines decode cleanly
+        self.pending.extend_from_slice(data);
+        if let Some(end) = self.pending.iter().rposition(|b| *b == b'\n') {
+            let lines: Vec<u8> = self.pending.drain(..=end).collect();
+            out.write_all(strip_ansi(&String::from_utf8_lossy(&lines)).as_bytes())?;
+            out.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Print whatever is left of an unterminated last line.
+    pub fn finish(&mut self) -> std::io::Result<()> {
+        if self.strip && !self.pending.is_empty() {
+            let rest = std::mem::take(&mut self.pending);
+            let mut out = std::io::stdout().lock();
+            writeln!(out, "{}", strip_ansi(&String::from_utf8_lossy(&rest)))?;
+            out.flush()?;
+        }
+        Ok(())
+    }
+}
+
+/// Split complete server-sent events off the front of `buf`, returning
+/// `(event, data)` pairs.
+pub fn drain_sse_events(buf: &mut Vec<u8>) -> Vec<(String, String)> {
+    let mut events = Vec::new();
+    while let Some(end) = buf.windows(2).position(|w| w == b"\n\n") {
+        let block: Vec<u8> = buf.drain(..end + 2).collect();
+        let block = String::from_utf8_lossy(&block);
+        let mut event = String::from("message");
+        let mut data = Vec::new();
+        for line in block.lines() {
+            if let Some(name) = line.strip_prefix("event:") {
+                event = name.trim().to_string();
+            } else if let Some(value) = line.strip_prefix("data:") {
+                data.push(value.strip_prefix(' ').unwrap_or(value));
+            }
+        }
+        // Keep-alive comments carry no data
+        if !data.is_empty() {
+            events.push((event, data.join("\n")));
+        }
+    }
+    events
+}
@@ -1,2 +1,5 @@
 pub mod commands;
+pub mod list;
+pub mod logs;
+pub mod service;
 pub mod settings;
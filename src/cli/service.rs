@@ -0,0 +1,265 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+
+use crate::config::Config;
+use crate::server::runtime::RuntimeState;
+
+const SYSTEMD_UNIT: &str = "lineforge.service";
+const LAUNCHD_LABEL: &str = "com.lineforge.forge";
+
+/// Variables copied from the installing shell into the service, so tools
+/// are found the same way as from a terminal.
+const INHERITED_ENV: &[&str] = &["PATH", "SHELL", "LANG", "LC_ALL"];
+
+fn systemd_unit_path() -> PathBuf {
+    dirs::config_dir()
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join("systemd/user")
+        .join(SYSTEMD_UNIT)
+}
+
+fn launchd_plist_path() -> PathBuf {
+    dirs::home_dir()
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join("Library/LaunchAgents")
+        .join(format!("{LAUNCHD_LABEL}.plist"))
+}
+
+fn launchd_target() -> String {
+    format!("gui/{}/{LAUNCHD_LABEL}", unsafe { libc::getuid() })
+}
+
+/// `forge service install`: write a systemd user unit (a launchd agent on
+/// macOS) that runs `forge serve`, and enable it.
+pub fn install(config_path: Option<&PathBuf>, enable: bool) -> Result<()> {
+    let config = Config::load(config_path)?;
+    let exe = std::env::current_exe()?;
+    let mut args = vec!["serve".to_string()];
+    if let Some(path) = config_path {
+        let path = std::fs::canonicalize(path)
+            .with_context(|| format!("Config not found: {}", path.display()))?;
+        args.extend(["--config".into(), path.display().to_string()]);
+    }
+    let env: Vec<(&str, String)> = INHERITED_ENV
+        .iter()
+        .filter_map(|&key| Some((key, std::env::var(key).ok()?)))
+        .collect();
+    // Enough for the shutdown policy to run before the service manager
+    // kills what's left
+    let stop_timeout = config.shutdown_timeout_secs * 2 + 10;
+
+    let launchd = cfg!(target_os = "macos");
+    let (path, contents) = if launchd {
+        let plist = launchd_plist(&exe, &args, &env, stop_timeout);
+        (launchd_plist_path(), plist)
+    } else {
+        let unit = systemd_unit(&exe, &args, &env, stop_timeout);
+        (systemd_unit_path(), unit)
+    };
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    std::fs::write(&path, contents)
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+    println!("Wrote {}", path.display());
+    if !enable {
+        return Ok(());
+    }
+
+    // A server started by hand would hold the port
+    let running = RuntimeState::read().filter(|s| s.service.is_none());
+    if launchd {
+        if running.is_none() {
+            let domain = format!("gui/{}", unsafe { libc::getuid() });
+            run(
+                "launchctl",
+                &["bootstrap", &domain, &path.display().to_string()],
+            )?;
+        }
+    } else {
+        run("systemctl", &["--user", "daemon-reload"])?;
+        let now = running.is_none().then_some("--now");
+        let args: Vec<&str> = ["--user", "enable"]
+            .into_iter()
+            .chain(now)
+            .chain([SYSTEMD_UNIT])
+            .collect();
+        run("systemctl", &args)?;
+    }
+    match running {
+        Some(state) => println!(
+            "A server is already running (pid {}). Stop it with `forge stop-server`, then start the service with `{}`.",
+            state.pid,
+            if launchd {
+                format!(
+                    "launchctl bootstrap gui/{} {}",
+                    unsafe { libc::getuid() },
+                    path.display()
+                )
+            } else {
+                format!("systemctl --user start {SYSTEMD_UNIT}")
+            }
+        ),
+        None => println!("Service enabled and started"),
+    }
+    if !launchd {
+        println!(
+            "User services stop when you log out; `loginctl enable-linger` keeps the server running."
+        );
+    }
+    Ok(())
+}
+
+/// `forge service uninstall`
+pub fn uninstall() -> Result<()> {
+    let path = if cfg!(target_os = "macos") {
+        let path = launchd_plist_path();
+        if path.exists() {
+            let _ = run("launchctl", &["bootout", &launchd_target()]);
+        }
+        path
+    } else {
+        let path = systemd_unit_path();
+        if path.exists() {
+            let _ = run("systemctl", &["--user", "disable", "--now", SYSTEMD_UNIT]);
+        }
+        path
+    };
+    match std::fs::remove_file(&path) {
+        Ok(()) => println!("Removed {}", path.display()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            println!("No service installed");
+            return Ok(());
+        }
+        Err(e) => return Err(e).with_context(|| format!("Failed to remove {}", path.display())),
+    }
+    if !cfg!(target_os = "macos") {
+        run("systemctl", &["--user", "daemon-reload"])?;
+    }
+    Ok(())
+}
+
+/// Restart a server the service manager runs, through the service manager.
+pub fn restart(service: &str) -> Result<()> {
+    match service {
+        "systemd" => run("systemctl", &["--user", "restart", SYSTEMD_UNIT])?,
+        "launchd" => run("launchctl", &["kickstart", "-k", &launchd_target()])?,
+        other => anyhow::bail!("Unknown service manager: {other}"),
+    }
+    println!("Server restarted by {service}");
+    Ok(())
+}
+
+fn run(program: &str, args: &[&str]) -> Result<()> {
+    let status = std::process::Command::new(program)
+        .args(args)
+        .status()
+        .with_context(|| format!("Failed to run {program}"))?;
+    anyhow::ensure!(
+        status.success(),
+        "`{program} {}` failed ({status})",
+        args.join(" ")
+    );
+    Ok(())
+}
+
+fn systemd_unit(exe: &Path, args: &[String], env: &[(&str, String)], stop_timeout: u64) -> String {
+    let exec = std::iter::once(exe.display().to_string())
+        .chain(args.iter().cloned())
+        .map(|arg| systemd_quote(&arg).replace('$', "$$"))
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    let mut unit = String::from(
+        "[Unit]\n\
+         Description=Lineforge AI session manager\n\
+         After=network-online.target\n\
+         Wants=network-online.target\n\
+         \n\
+         [Service]\n\
+         Type=simple\n",
+    );
+    unit.push_str(&format!("ExecStart={exec}\n"));
+    unit.push_str("Restart=on-failure\nRestartSec=2\n");
+    for (key, value) in env {
+        unit.push_str(&format!(
+            "Environment={}\n",
+            systemd_quote(&format!("{key}={value}"))
+        ));
+    }
+    unit.push_str("Environment=LINEFORGE_SERVICE=systemd\n");
+    // Lets the server create a cgroup per session for resource limits
+    unit.push_str("Delegate=yes\n");
+    // SIGTERM goes to the server only, which applies the shutdown policy
+    unit.push_str("KillMode=mixed\n");
+    unit.push_str(&format!("TimeoutStopSec={stop_timeout}\n"));
+    unit.push_str("\n[Install]\nWantedBy=default.target\n");
+    unit
+}
+
+fn systemd_quote(value: &str) -> String {
+    let escaped = value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('%', "%%");
+    format!("\"{escaped}\"")
+}
+
+fn launchd_plist(exe: &Path, args: &[String], env: &[(&str, String)], stop_timeout: u64) -> String {
+    let log = xml_escape(&Config::server_log_path().display().to_string());
+    let program = std::iter::once(exe.display().to_string())
+        .chain(args.iter().cloned())
+        .map(|arg| format!("        <string>{}</string>\n", xml_escape(&arg)))
+        .collect::<String>();
+    let env = env
+        .iter()
+        .map(|(key, value)| (*key, value.as_str()))
+        .chain([("LINEFORGE_SERVICE", "launchd")])
+        .map(|(key, value)| {
+            format!(
+                "        <key>{key}</key>\n        <string>{}</string>\n",
+                xml_escape(value)
+            )
+        })
+        .collect::<String>();
+
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+    <key>Label</key>
+    <string>{LAUNCHD_LABEL}</string>
+    <key>ProgramArguments</key>
+    <array>
+{program}    </array>
+    <key>EnvironmentVariables</key>
+    <dict>
+{env}    </dict>
+    <key>RunAtLoad</key>
+    <true/>
+    <key>KeepAlive</key>
+    <dict>
+        <key>SuccessfulExit</key>
+        <false/>
+    </dict>
+    <key>ExitTimeOut</key>
+    <integer>{stop_timeout}</integer>
+    <key>StandardOutPath</key>
+    <string>{log}</string>
+    <key>StandardErrorPath</key>
+    <string>{log}</string>
+</dict>
+</plist>
+"#
+    )
+}
+
+fn xml_escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
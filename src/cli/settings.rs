@@ -13,6 +13,7 @@ use ratatui::{
 };
 
 use crate::config::Config;
+use crate::session::writer::LogCompression;
 
 enum SettingValue {
     Bool(bool),
@@ -80,6 +81,17 @@ impl App {
                     }
                 },
             },
+            SettingItem {
+                id: "approval",
+                label: "Approval Policy",
+                description: "Answer permission prompts using [approval] rules in config.toml",
+                value: SettingValue::Bool(config.approval.enabled),
+                apply: |c, v| {
+                    if let SettingValue::Bool(on) = v {
+                        c.approval.enabled = *on;
+                    }
+                },
+            },
             SettingItem {
                 id: "iterm",
                 label: "iTerm Integration",
@@ -102,6 +114,28 @@ impl App {
                     }
                 },
             },
+            SettingItem {
+                id: "tls_self_signed",
+                label: "Self-signed HTTPS",
+                description: "Serve HTTPS with a generated certificate unless tls_cert is set (requires restart)",
+                value: SettingValue::Bool(config.tls_self_signed),
+                apply: |c, v| {
+                    if let SettingValue::Bool(on) = v {
+                        c.tls_self_signed = *on;
+                    }
+                },
+            },
+            SettingItem {
+                id: "auto_start",
+                label: "Auto-start Server",
+                description: "Start a background server when a CLI command finds none running",
+                value: SettingValue::Bool(config.auto_start),
+                apply: |c, v| {
+                    if let SettingValue::Bool(on) = v {
+                        c.auto_start = *on;
+                    }
+                },
+            },
             SettingItem {
                 id: "log_retention",
                 label: "Log Retention (days)",
@@ -114,13 +148,79 @@ impl App {
                 },
             },
             SettingItem {
-                id: "max_log_lines",
-                label: "Max Log Lines",
-                description: "Maximum lines stored per session log",
-                value: SettingValue::Number(config.max_log_lines as u64, 100, 1_000_000),
+                id: "log_buffer_mb",
+                label: "Log Buffer (MB)",
+                description: "In-memory output kept per session for the web UI and reconnects",
+                value: SettingValue::Number(config.log_buffer_mb as u64, 1, 1024),
+                apply: |c, v| {
+                    if let SettingValue::Number(n, _, _) = v {
+                        c.log_buffer_mb = *n as u32;
+                    }
+                },
+            },
+            SettingItem {
+                id: "log_rotate_mb",
+                label: "Log Rotate Size (MB)",
+                description: "Rotate a session's output.log when it reaches this size",
+                value: SettingValue::Number(config.log_rotate_mb as u64, 1, 4096),
+                apply: |c, v| {
+                    if let SettingValue::Number(n, _, _) = v {
+                        c.log_rotate_mb = *n as u32;
+                    }
+                },
+            },
+            SettingItem {
+                id: "log_keep",
+                label: "Rotated Logs Kept",
+                description: "Older rotated segments of output.log are deleted",
+                value: SettingValue::Number(config.log_keep as u64, 0, 100),
+                apply: |c, v| {
+                    if let SettingValue::Number(n, _, _) = v {
+                        c.log_keep = *n as u32;
+                    }
+                },
+            },
+            SettingItem {
+                id: "log_compression",
+                label: "Log Compression",
+                description: "How rotated segments of output.log are compressed",
+                value: SettingValue::Choice(
+                    match config.log_compression {
+                        LogCompression::None => 0,
+                        LogCompression::Gzip => 1,
+                        LogCompression::Zstd => 2,
+                    },
+                    vec!["none", "gzip", "zstd"],
+                ),
+                apply: |c, v| {
+                    if let SettingValue::Choice(idx, _) = v {
+                        c.log_compression = match idx {
+                            0 => LogCompression::None,
+                            2 => LogCompression::Zstd,
+                            _ => LogCompression::Gzip,
+                        };
+                    }
+                },
+            },
+            SettingItem {
+                id: "client_buffer_mb",
+                label: "Client Buffer (MB)",
+                description: "Output a client may fall behind by before it is resynced",
+                value: SettingValue::Number(config.client_buffer_mb as u64, 1, 256),
+                apply: |c, v| {
+                    if let SettingValue::Number(n, _, _) = v {
+                        c.client_buffer_mb = *n as u32;
+                    }
+                },
+            },
+            SettingItem {
+                id: "output_coalesce_ms",
+                label: "Output Coalesce (ms)",
+                description: "Output arriving within this window is sent to clients as one chunk",
+                value: SettingValue::Number(config.output_coalesce_ms, 0, 1000),
                 apply: |c, v| {
                     if let SettingValue::Number(n, _, _) = v {
-                        c.max_log_lines = *n as usize;
+                        c.output_coalesce_ms = *n;
                     }
                 },
             },
@@ -1,8 +1,16 @@
+use std::collections::BTreeMap;
 use std::path::PathBuf;
 
 use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
 
+use crate::error::ForgeError;
+use crate::policy::ApprovalConfig;
+use crate::search::SearchIndex;
+use crate::session::feed::FeedConfig;
+use crate::session::model::ToolKind;
+use crate::session::writer::{LogCompression, RotationPolicy};
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Config {
     #[serde(default = "default_port")]
@@ -18,10 +26,261 @@ pub struct Config {
     pub iterm_enabled: bool,
     #[serde(default = "default_log_retention")]
     pub log_retention_days: u32,
-    #[serde(default = "default_max_log_lines")]
-    pub max_log_lines: usize,
+    /// In-memory output buffer per session, in MB
+    #[serde(default = "default_log_buffer_mb")]
+    pub log_buffer_mb: u32,
+    /// Rotate `output.log` when it reaches this size, in MB
+    #[serde(default = "default_log_rotate_mb")]
+    pub log_rotate_mb: u32,
+    /// Rotated segments kept per session
+    #[serde(default = "default_log_keep")]
+    pub log_keep: u32,
+    #[serde(default)]
+    pub log_compression: LogCompression,
+    /// Output a web or attach client may fall behind by before it is
+    /// resynced, in MB
+    #[serde(default = "default_client_buffer_mb")]
+    pub client_buffer_mb: u32,
+    /// Output arriving within this window is sent to clients as one chunk
+    #[serde(default = "default_output_coalesce_ms")]
+    pub output_coalesce_ms: u64,
+    /// Memory the search index may use, in MB; the oldest output is
+    /// dropped from it beyond that
+    #[serde(default = "default_search_index_mb")]
+    pub search_index_mb: u32,
+    /// PEM certificate chain; the server speaks HTTPS when this and
+    /// `tls_key` are set
+    pub tls_cert: Option<PathBuf>,
+    pub tls_key: Option<PathBuf>,
+    /// Without `tls_cert`/`tls_key`, serve HTTPS with a certificate
+    /// generated on first run
+    #[serde(default)]
+    pub tls_self_signed: bool,
+    /// SHA-256 fingerprint the CLI pins instead of reading `tls_cert`
+    pub tls_fingerprint: Option<String>,
+    /// Also listen for plain HTTP on this port and redirect it to HTTPS
+    pub tls_redirect_port: Option<u16>,
+    /// Serve Prometheus metrics at `/metrics`
+    #[serde(default)]
+    pub metrics: bool,
+    /// How often to sample each session's processes, in seconds (0 turns
+    /// resource monitoring off)
+    #[serde(default = "default_resource_sample_secs")]
+    pub resource_sample_secs: u64,
+    /// Start a background server when a CLI command finds none running
+    #[serde(default)]
+    pub auto_start: bool,
+    /// What happens to running sessions when the server shuts down
+    #[serde(default)]
+    pub shutdown_policy: ShutdownPolicy,
+    /// How long shutdown waits for sessions to exit, in seconds
+    #[serde(default = "default_shutdown_timeout_secs")]
+    pub shutdown_timeout_secs: u64,
     #[serde(default)]
     pub yolo_mode: bool,
+    #[serde(default)]
+    pub approval: ApprovalConfig,
+    /// The server's own logs and traces
+    #[serde(default)]
+    pub logging: LoggingConfig,
+    /// Resource limits for every session, unless the tool or session sets
+    /// its own
+    #[serde(default, skip_serializing_if = "ResourceLimits::is_empty")]
+    pub limits: ResourceLimits,
+    #[serde(default)]
+    pub launch_mode: LaunchMode,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub tools: BTreeMap<String, ToolConfig>,
+}
+
+/// How tool binaries are found and started.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum LaunchMode {
+    /// Exec the tool with the server's own environment
+    #[default]
+    Direct,
+    /// Run the tool through `$SHELL -l -c`, so the login profile sets PATH
+    LoginShell,
+    /// Exec the tool with a login shell's environment, resolved once and cached
+    LoginEnv,
+}
+
+/// How `forge serve` logs (`[logging]`). Session output is configured by
+/// the `log_*` settings instead.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LoggingConfig {
+    #[serde(default)]
+    pub format: LogFormat,
+    /// Filter in `RUST_LOG` syntax, used when `RUST_LOG` isn't set
+    #[serde(default = "default_log_level")]
+    pub level: String,
+    /// Also write to a daily-rotated file under `logs/` in the data dir
+    #[serde(default)]
+    pub file: bool,
+    /// Rotated log files kept
+    #[serde(default = "default_log_files")]
+    pub keep_files: usize,
+    /// OTLP/HTTP collector to send spans to, e.g. `http://localhost:4318`
+    pub otlp_endpoint: Option<String>,
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        Self {
+            format: LogFormat::default(),
+            level: default_log_level(),
+            file: false,
+            keep_files: default_log_files(),
+            otlp_endpoint: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum LogFormat {
+    #[default]
+    Text,
+    /// One JSON object per line, with the fields of the enclosing spans
+    Json,
+}
+
+/// What the server does with running sessions when it shuts down. The
+/// server holds their terminals, so none outlive it.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ShutdownPolicy {
+    /// Send each tool SIGTERM and wait for it to exit
+    #[default]
+    Stop,
+    /// Wait for the tools to exit on their own, then stop what's left
+    Wait,
+}
+
+/// Per-tool settings, keyed by tool name (`[tools.claude]`, `[tools.codex]`).
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ToolConfig {
+    /// Extra environment variables for sessions of this tool
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+    /// Env files loaded before `env`, relative to the session's working dir
+    #[serde(default)]
+    pub env_files: Vec<PathBuf>,
+    /// Load the project's `.env` and `.envrc` from the working dir
+    #[serde(default)]
+    pub load_dotenv: bool,
+    /// Overrides the global `launch_mode` for this tool
+    pub launch_mode: Option<LaunchMode>,
+    /// Overrides the global `limits` for this tool, field by field
+    #[serde(default, skip_serializing_if = "ResourceLimits::is_empty")]
+    pub limits: ResourceLimits,
+}
+
+/// Limits on a session's process tree, applied when it is spawned. Memory,
+/// CPU and process limits need a cgroup v2 subtree; without one memory is
+/// limited per process with `RLIMIT_DATA` and the others aren't enforced.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct ResourceLimits {
+    /// Memory for the whole tree, in MB
+    pub memory_mb: Option<u64>,
+    /// CPU time as a percentage of one core (`200` is two cores)
+    pub cpu_percent: Option<u32>,
+    /// Processes and threads in the tree
+    pub max_pids: Option<u64>,
+    /// Scheduling niceness, from -20 (highest priority) to 19
+    pub nice: Option<i32>,
+    /// I/O scheduling class (Linux)
+    pub ionice: Option<IoPriority>,
+}
+
+impl ResourceLimits {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// These limits, with those left unset taken from `fallback`.
+    pub fn or(&self, fallback: &ResourceLimits) -> ResourceLimits {
+        ResourceLimits {
+            memory_mb: self.memory_mb.or(fallback.memory_mb),
+            cpu_percent: self.cpu_percent.or(fallback.cpu_percent),
+            max_pids: self.max_pids.or(fallback.max_pids),
+            nice: self.nice.or(fallback.nice),
+            ionice: self.ionice.or(fallback.ionice),
+        }
+    }
+
+    /// Check the values, naming the first bad one.
+    pub fn validate(&self) -> Result<(), ForgeError> {
+        let positive = [
+            ("limits.memory_mb", self.memory_mb),
+            ("limits.cpu_percent", self.cpu_percent.map(u64::from)),
+            ("limits.max_pids", self.max_pids),
+        ];
+        if let Some((field, _)) = positive.iter().find(|(_, value)| *value == Some(0)) {
+            return Err(ForgeError::validation(field, "must be at least 1"));
+        }
+        if let Some(nice) = self.nice
+            && !(-20..=19).contains(&nice)
+        {
+            return Err(ForgeError::validation(
+                "limits.nice",
+                format!("{nice} is outside -20..19"),
+            ));
+        }
+        if let Some(nice) = self.nice
+            && !crate::session::limits::nice_allowed(nice)
+        {
+            return Err(ForgeError::validation(
+                "limits.nice",
+                format!(
+                    "{nice} raises the priority above the server's own, which needs CAP_SYS_NICE or a higher RLIMIT_NICE"
+                ),
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for ResourceLimits {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut parts = Vec::new();
+        if let Some(mb) = self.memory_mb {
+            parts.push(format!("memory {mb} MB"));
+        }
+        if let Some(percent) = self.cpu_percent {
+            parts.push(format!("CPU {percent}%"));
+        }
+        if let Some(pids) = self.max_pids {
+            parts.push(format!("{pids} processes"));
+        }
+        if let Some(nice) = self.nice {
+            parts.push(format!("nice {nice}"));
+        }
+        if let Some(ionice) = self.ionice {
+            parts.push(format!("ionice {ionice}"));
+        }
+        write!(f, "{}", parts.join(", "))
+    }
+}
+
+/// I/O priority for `ionice`.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum IoPriority {
+    /// Best effort, at the lowest level
+    Low,
+    /// Only when no other process wants the disk
+    Idle,
+}
+
+impl std::fmt::Display for IoPriority {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IoPriority::Low => write!(f, "low"),
+            IoPriority::Idle => write!(f, "idle"),
+        }
+    }
 }
 
 fn default_port() -> u16 {
@@ -81,8 +340,35 @@ fn default_true() -> bool {
 fn default_log_retention() -> u32 {
     7
 }
-fn default_max_log_lines() -> usize {
-    10_000
+fn default_log_buffer_mb() -> u32 {
+    8
+}
+fn default_log_rotate_mb() -> u32 {
+    64
+}
+fn default_log_keep() -> u32 {
+    5
+}
+fn default_log_level() -> String {
+    "info".into()
+}
+fn default_log_files() -> usize {
+    7
+}
+fn default_client_buffer_mb() -> u32 {
+    4
+}
+fn default_output_coalesce_ms() -> u64 {
+    16
+}
+fn default_search_index_mb() -> u32 {
+    256
+}
+fn default_shutdown_timeout_secs() -> u64 {
+    30
+}
+fn default_resource_sample_secs() -> u64 {
+    2
 }
 
 impl Default for Config {
@@ -95,8 +381,29 @@ impl Default for Config {
             default_dirs: Vec::new(),
             iterm_enabled: true,
             log_retention_days: default_log_retention(),
-            max_log_lines: default_max_log_lines(),
+            log_buffer_mb: default_log_buffer_mb(),
+            log_rotate_mb: default_log_rotate_mb(),
+            log_keep: default_log_keep(),
+            log_compression: LogCompression::default(),
+            client_buffer_mb: default_client_buffer_mb(),
+            output_coalesce_ms: default_output_coalesce_ms(),
+            search_index_mb: default_search_index_mb(),
+            tls_cert: None,
+            tls_key: None,
+            tls_self_signed: false,
+            tls_fingerprint: None,
+            tls_redirect_port: None,
+            metrics: false,
+            resource_sample_secs: default_resource_sample_secs(),
+            auto_start: false,
+            shutdown_policy: ShutdownPolicy::default(),
+            shutdown_timeout_secs: default_shutdown_timeout_secs(),
             yolo_mode: false,
+            approval: ApprovalConfig::default(),
+            logging: LoggingConfig::default(),
+            limits: ResourceLimits::default(),
+            launch_mode: LaunchMode::default(),
+            tools: BTreeMap::new(),
         }
     }
 }
@@ -114,10 +421,129 @@ impl Config {
             .join("lineforge")
     }
 
+    /// Byte budget of each session's in-memory output buffer.
+    pub fn log_buffer_bytes(&self) -> usize {
+        self.log_buffer_mb as usize * 1024 * 1024
+    }
+
+    pub fn search_index(&self) -> SearchIndex {
+        SearchIndex::new(
+            self.search_index_mb.max(1) as usize * 1024 * 1024,
+            chrono::TimeDelta::days(self.log_retention_days.into()),
+        )
+    }
+
+    pub fn feed_config(&self) -> FeedConfig {
+        FeedConfig {
+            buffer_bytes: self.client_buffer_mb.max(1) as usize * 1024 * 1024,
+            coalesce: std::time::Duration::from_millis(self.output_coalesce_ms),
+        }
+    }
+
+    pub fn log_rotation(&self) -> RotationPolicy {
+        RotationPolicy {
+            max_bytes: self.log_rotate_mb.max(1) as u64 * 1024 * 1024,
+            keep: self.log_keep,
+            compression: self.log_compression,
+        }
+    }
+
+    /// Certificate and key the server uses, if TLS is enabled.
+    pub fn tls_files(&self) -> Option<(PathBuf, PathBuf)> {
+        match (&self.tls_cert, &self.tls_key) {
+            (Some(cert), Some(key)) => Some((cert.clone(), key.clone())),
+            _ if self.tls_self_signed => Some(crate::tls::self_signed_paths()),
+            _ => None,
+        }
+    }
+
+    /// Base URL of the server, e.g. `https://100.64.0.1:42067`. HTTPS when
+    /// this config serves it, or pins the certificate of a server that does.
+    pub fn server_url(&self) -> String {
+        let scheme = if self.tls_files().is_some() || self.tls_fingerprint.is_some() {
+            "https"
+        } else {
+            "http"
+        };
+        format!(
+            "{scheme}://{}:{}",
+            resolve_bind_address(&self.bind),
+            self.port
+        )
+    }
+
     pub fn sessions_dir() -> PathBuf {
         Self::data_dir().join("sessions")
     }
 
+    /// SQLite database holding session metadata, tags and events.
+    pub fn store_path() -> PathBuf {
+        Self::data_dir().join("sessions.db")
+    }
+
+    /// Private per-user dir for the running server's socket and state: the
+    /// user's runtime dir, or a dir under /tmp without one.
+    pub fn runtime_dir() -> PathBuf {
+        dirs::runtime_dir()
+            .map(|d| d.join("lineforge"))
+            .unwrap_or_else(|| {
+                std::env::temp_dir().join(format!("lineforge-{}", unsafe { libc::getuid() }))
+            })
+    }
+
+    /// `runtime_dir`, created if missing, once it is known to be private:
+    /// a directory owned by this user with mode 0700. Under /tmp another
+    /// user could have created it first, and their state file or socket
+    /// must not be trusted.
+    pub fn private_runtime_dir() -> Result<PathBuf> {
+        use std::os::unix::fs::{DirBuilderExt, MetadataExt};
+
+        let dir = Self::runtime_dir();
+        std::fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(&dir)
+            .with_context(|| format!("Failed to create {}", dir.display()))?;
+        let meta = std::fs::symlink_metadata(&dir)
+            .with_context(|| format!("Failed to inspect {}", dir.display()))?;
+        let uid = unsafe { libc::getuid() };
+        if !meta.is_dir() || meta.uid() != uid {
+            anyhow::bail!(
+                "{} isn't a directory owned by you (uid {uid}); remove it or set XDG_RUNTIME_DIR",
+                dir.display()
+            );
+        }
+        if meta.mode() & 0o777 != 0o700 {
+            anyhow::bail!(
+                "{} has mode {:o}, other users may have put files in it; remove it or set its mode to 700",
+                dir.display(),
+                meta.mode() & 0o777
+            );
+        }
+        Ok(dir)
+    }
+
+    /// Unix socket the server also answers on, for local CLI commands.
+    pub fn control_socket() -> PathBuf {
+        Self::runtime_dir().join("control.sock")
+    }
+
+    /// Where a running server records its pid and listen addresses.
+    pub fn runtime_state_path() -> PathBuf {
+        Self::runtime_dir().join("server.json")
+    }
+
+    /// Pid of the running server, for scripts and service managers.
+    pub fn pid_file() -> PathBuf {
+        Self::runtime_dir().join("server.pid")
+    }
+
+    /// Output of a server started in the background by the CLI or
+    /// `forge serve --daemon`.
+    pub fn server_log_path() -> PathBuf {
+        Self::data_dir().join("server.log")
+    }
+
     pub fn config_path() -> PathBuf {
         Self::config_dir().join("config.toml")
     }
@@ -150,6 +576,25 @@ impl Config {
         Ok(())
     }
 
+    /// Settings for `tool`, or defaults if it has no `[tools.<name>]` table.
+    pub fn tool_config(&self, tool: &ToolKind) -> ToolConfig {
+        self.tools
+            .get(tool.command_name())
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Limits for a session of `tool`: the tool's, then the global ones.
+    pub fn limits(&self, tool: &ToolKind) -> ResourceLimits {
+        self.tool_config(tool).limits.or(&self.limits)
+    }
+
+    pub fn launch_mode(&self, tool: &ToolKind) -> LaunchMode {
+        self.tool_config(tool)
+            .launch_mode
+            .unwrap_or(self.launch_mode)
+    }
+
     pub fn ensure_dirs(&self) -> Result<()> {
         std::fs::create_dir_all(Self::config_dir())?;
         std::fs::create_dir_all(Self::data_dir())?;
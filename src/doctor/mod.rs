@@ -0,0 +1,427 @@
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use serde::Serialize;
+
+use crate::config::{Config, LaunchMode, resolve_bind_address};
+use crate::server::runtime::RuntimeState;
+use crate::session::model::ToolKind;
+
+const VERSION_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum CheckStatus {
+    Pass,
+    Warn,
+    Fail,
+}
+
+impl CheckStatus {
+    pub fn label(&self) -> &str {
+        match self {
+            CheckStatus::Pass => "PASS",
+            CheckStatus::Warn => "WARN",
+            CheckStatus::Fail => "FAIL",
+        }
+    }
+
+    pub fn css_class(&self) -> &str {
+        match self {
+            CheckStatus::Pass => "running",
+            CheckStatus::Warn => "approval",
+            CheckStatus::Fail => "errored",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Check {
+    pub name: String,
+    pub status: CheckStatus,
+    pub message: String,
+    /// What to do about a warning or failure
+    pub hint: Option<String>,
+}
+
+impl Check {
+    fn pass(name: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            status: CheckStatus::Pass,
+            message: message.into(),
+            hint: None,
+        }
+    }
+
+    fn warn(name: impl Into<String>, message: impl Into<String>, hint: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            status: CheckStatus::Warn,
+            message: message.into(),
+            hint: Some(hint.into()),
+        }
+    }
+
+    fn fail(name: impl Into<String>, message: impl Into<String>, hint: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            status: CheckStatus::Fail,
+            message: message.into(),
+            hint: Some(hint.into()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Report {
+    pub version: &'static str,
+    pub status: CheckStatus,
+    pub checks: Vec<Check>,
+}
+
+/// Where the checks run from, which changes what "port in use" means.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Origin {
+    Cli,
+    Server,
+}
+
+/// Run every check. `config` is the result of loading `config_path`; on
+/// failure the remaining checks use the default config.
+pub async fn run(config: anyhow::Result<Config>, config_path: &Path, origin: Origin) -> Report {
+    let mut checks = Vec::new();
+
+    let config = match config {
+        Ok(c) => {
+            checks.push(Check::pass(
+                "config",
+                format!("Loaded {}", config_path.display()),
+            ));
+            c
+        }
+        Err(e) => {
+            checks.push(Check::fail(
+                "config",
+                format!("{e:#}"),
+                format!("Fix or remove {} to regenerate it", config_path.display()),
+            ));
+            Config::default()
+        }
+    };
+
+    let default_tool = config.default_tool.parse::<ToolKind>();
+    if let Err(ref e) = default_tool {
+        checks.push(Check::fail(
+            "default_tool",
+            e.clone(),
+            "Set default_tool to \"claude\" or \"codex\"",
+        ));
+    }
+
+    for tool in ToolKind::ALL {
+        let required = default_tool.as_ref().is_ok_and(|d| *d == tool);
+        checks.push(check_tool(&config, &tool, required).await);
+    }
+    let path = {
+        let config = config.clone();
+        tokio::task::spawn_blocking(move || check_path(&config)).await
+    };
+    checks
+        .push(path.unwrap_or_else(|e| Check::fail("path", e.to_string(), "Retry `forge doctor`")));
+
+    let bind = resolve_bind_address(&config.bind);
+    checks.push(check_tailscale(&config, &bind));
+    checks.push(check_tls(&config));
+    checks.push(check_limits(&config));
+    checks.push(check_writable_dir(
+        "socket_dir",
+        &crate::session::manager::sock_dir(),
+    ));
+    checks.push(check_writable_dir("data_dir", &Config::sessions_dir()));
+
+    let server_up = match origin {
+        Origin::Server => true,
+        Origin::Cli => server_healthy(&config).await,
+    };
+    checks.push(check_port(&bind, config.port, origin, server_up).await);
+    checks.push(check_control_socket());
+    let server_url = RuntimeState::read().map_or_else(|| config.server_url(), |s| s.url);
+    checks.push(match (origin, server_up) {
+        (Origin::Server, _) => Check::pass("server", "Answering requests"),
+        (Origin::Cli, true) => Check::pass("server", format!("Reachable at {server_url}")),
+        (Origin::Cli, false) => Check::warn(
+            "server",
+            format!("Not reachable at {server_url}"),
+            "Start it with `forge serve`",
+        ),
+    });
+
+    let status = checks
+        .iter()
+        .map(|c| c.status)
+        .max()
+        .unwrap_or(CheckStatus::Pass);
+
+    Report {
+        version: env!("CARGO_PKG_VERSION"),
+        status,
+        checks,
+    }
+}
+
+async fn check_tool(config: &Config, tool: &ToolKind, required: bool) -> Check {
+    let name = format!("tool:{tool}");
+    let path = {
+        let config = config.clone();
+        let tool = tool.clone();
+        tokio::task::spawn_blocking(move || {
+            let path = crate::session::pty::resolve_tool_path(&config, &tool)?;
+            // The login modes start the tool with the login shell's
+            // environment, which its interpreter (nvm, volta) may need
+            let env = match config.launch_mode(&tool) {
+                LaunchMode::Direct => None,
+                LaunchMode::LoginShell | LaunchMode::LoginEnv => {
+                    Some(crate::session::pty::login_environment()?.clone())
+                }
+            };
+            anyhow::Ok((path, env))
+        })
+        .await
+    };
+
+    let (path, env) = match path {
+        Ok(Ok(p)) => p,
+        Ok(Err(e)) => {
+            let hint = format!(
+                "Install {tool}, set tool_path, or set launch_mode = \"login_env\" if it's only on your login PATH"
+            );
+            return if required {
+                Check::fail(name, e.to_string(), hint)
+            } else {
+                Check::warn(name, e.to_string(), hint)
+            };
+        }
+        Err(e) => return Check::fail(name, e.to_string(), "Retry `forge doctor`"),
+    };
+
+    let mut command = tokio::process::Command::new(&path);
+    command.arg("--version").stdin(std::process::Stdio::null());
+    if let Some(env) = env {
+        command.envs(env);
+    }
+    let version = tokio::time::timeout(VERSION_TIMEOUT, command.output()).await;
+
+    match version {
+        Ok(Ok(out)) if out.status.success() => {
+            let version = String::from_utf8_lossy(&out.stdout);
+            let version = version.lines().next().unwrap_or_default().trim();
+            Check::pass(name, format!("{version} ({path})"))
+        }
+        Ok(Ok(out)) => Check::fail(
+            name,
+            format!(
+                "`{path} --version` exited with {}: {}",
+                out.status,
+                String::from_utf8_lossy(&out.stderr).trim()
+            ),
+            format!("Reinstall {tool} or check its dependencies (e.g. node)"),
+        ),
+        Ok(Err(e)) => Check::fail(
+            name,
+            format!("Failed to run {path}: {e}"),
+            "Check the binary's permissions and interpreter",
+        ),
+        Err(_) => Check::warn(
+            name,
+            format!("`{path} --version` timed out"),
+            format!("Run `{path} --version` manually to see what it's waiting for"),
+        ),
+    }
+}
+
+fn check_tls(config: &Config) -> Check {
+    let Some((cert, key)) = config.tls_files() else {
+        return Check::pass("tls", "Disabled, serving plain HTTP");
+    };
+    if config.tls_cert.is_none() && !cert.exists() {
+        return Check::pass(
+            "tls",
+            "A self-signed certificate is generated on first start",
+        );
+    }
+    match crate::tls::server_config(&cert, &key) {
+        Ok(_) => Check::pass("tls", format!("Serving HTTPS with {}", cert.display())),
+        Err(e) => Check::fail(
+            "tls",
+            format!("{e:#}"),
+            "Point tls_cert and tls_key at a PEM certificate chain and its private key",
+        ),
+    }
+}
+
+fn check_limits(config: &Config) -> Check {
+    let mut configured = false;
+    for tool in ToolKind::ALL {
+        let limits = config.limits(&tool);
+        if let Err(e) = limits.validate() {
+            return Check::fail(
+                "limits",
+                format!("{tool}: {e}"),
+                "Fix the value under [limits] or [tools.<name>.limits]",
+            );
+        }
+        configured |= !limits.is_empty();
+    }
+    if !configured {
+        return Check::pass("limits", "No session limits configured");
+    }
+    if crate::session::limits::cgroup_v2_mounted() {
+        Check::pass(
+            "limits",
+            "cgroup v2 is available; sessions get a cgroup if the server's is delegated",
+        )
+    } else {
+        Check::warn(
+            "limits",
+            "cgroup v2 isn't available: memory is limited per process, CPU and process limits aren't enforced",
+            "Run the server with cgroup v2 (systemd units from `forge service install` delegate one)",
+        )
+    }
+}
+
+fn check_path(config: &Config) -> Check {
+    let server_path = std::env::var("PATH").unwrap_or_default();
+    if server_path.is_empty() {
+        return Check::fail(
+            "path",
+            "PATH is empty",
+            "Set PATH in the environment `forge serve` runs in, or use launch_mode = \"login_env\"",
+        );
+    }
+
+    // Tools only found via the login shell won't start in direct mode
+    let missing: Vec<&str> = ToolKind::ALL
+        .iter()
+        .filter(|t| config.launch_mode(t) == LaunchMode::Direct && config.tool_path.is_none())
+        .filter(|t| crate::session::pty::search_path(t.command_name(), &server_path).is_none())
+        .filter(|t| {
+            crate::session::pty::login_environment()
+                .ok()
+                .and_then(|env| env.get("PATH"))
+                .and_then(|p| crate::session::pty::search_path(t.command_name(), p))
+                .is_some()
+        })
+        .map(|t| t.command_name())
+        .collect();
+
+    if missing.is_empty() {
+        Check::pass("path", server_path)
+    } else {
+        Check::warn(
+            "path",
+            format!(
+                "{} only found on your login shell's PATH (server PATH: {server_path})",
+                missing.join(", ")
+            ),
+            "Set launch_mode = \"login_env\" or \"login_shell\" in config.toml",
+        )
+    }
+}
+
+fn check_tailscale(config: &Config, ip: &str) -> Check {
+    if config.bind != "tailscale" {
+        return Check::pass(
+            "tailscale",
+            format!("Not used (bind = \"{}\")", config.bind),
+        );
+    }
+    if ip == "127.0.0.1" {
+        Check::warn(
+            "tailscale",
+            "Tailscale unavailable, falling back to 127.0.0.1",
+            "Install and log in to Tailscale (`tailscale up`), or set bind = \"127.0.0.1\"",
+        )
+    } else {
+        Check::pass("tailscale", format!("Binding to Tailscale IP {ip}"))
+    }
+}
+
+fn check_writable_dir(name: &str, dir: &PathBuf) -> Check {
+    let probe = dir.join(format!(".doctor-{}", std::process::id()));
+    let result = std::fs::create_dir_all(dir)
+        .and_then(|_| std::fs::write(&probe, b"ok"))
+        .and_then(|_| std::fs::remove_file(&probe));
+    match result {
+        Ok(()) => Check::pass(name, format!("{} is writable", dir.display())),
+        Err(e) => Check::fail(
+            name,
+            format!("{} is not writable: {e}", dir.display()),
+            format!("Fix ownership/permissions of {}", dir.display()),
+        ),
+    }
+}
+
+async fn check_port(bind: &str, port: u16, origin: Origin, server_up: bool) -> Check {
+    if origin == Origin::Server {
+        return Check::pass("port", format!("Listening on {bind}:{port}"));
+    }
+    match tokio::net::TcpListener::bind((bind, port)).await {
+        Ok(_) => Check::pass("port", format!("{bind}:{port} is available")),
+        Err(_) if server_up => Check::pass("port", format!("{bind}:{port} is in use by Lineforge")),
+        Err(e) => Check::fail(
+            "port",
+            format!("Cannot bind {bind}:{port}: {e}"),
+            "Stop the process using the port, or change `port` in config.toml",
+        ),
+    }
+}
+
+fn check_control_socket() -> Check {
+    if let Err(e) = Config::private_runtime_dir() {
+        return Check::fail(
+            "control_socket",
+            format!("{e:#}"),
+            "Local commands and `forge serve` refuse to use it until it is fixed",
+        );
+    }
+    let path = Config::control_socket();
+    if std::os::unix::net::UnixStream::connect(&path).is_ok() {
+        Check::pass("control_socket", format!("Listening on {}", path.display()))
+    } else {
+        Check::warn(
+            "control_socket",
+            format!("Nothing listening on {}", path.display()),
+            "Local commands fall back to TCP until `forge serve` is running",
+        )
+    }
+}
+
+async fn server_healthy(config: &Config) -> bool {
+    match crate::session::manager::Api::discover(config) {
+        Ok(api) => api.healthy().await,
+        Err(_) => false,
+    }
+}
+
+/// Print a human-readable report.
+pub fn print_report(report: &Report) {
+    println!("Lineforge v{} doctor\n", report.version);
+    for check in &report.checks {
+        println!(
+            "[{}] {:<14} {}",
+            check.status.label(),
+            check.name,
+            check.message
+        );
+        if let Some(ref hint) = check.hint {
+            println!("       {:<14} → {hint}", "");
+        }
+    }
+    println!();
+    match report.status {
+        CheckStatus::Pass => println!("All checks passed"),
+        CheckStatus::Warn => println!("Some checks have warnings"),
+        CheckStatus::Fail => println!("Some checks failed"),
+    }
+}
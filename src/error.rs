@@ -1,3 +1,7 @@
+use axum::Json;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -5,9 +9,39 @@ pub enum ForgeError {
     #[error("Session not found: {0}")]
     SessionNotFound(uuid::Uuid),
 
+    #[error("No session matching '{0}'")]
+    NoSessionMatch(String),
+
+    #[error("{} sessions match '{query}', be more specific", candidates.len())]
+    AmbiguousSession {
+        query: String,
+        /// `(id, name)` of every matching session
+        candidates: Vec<(uuid::Uuid, String)>,
+    },
+
     #[error("Session already stopped: {0}")]
     SessionAlreadyStopped(uuid::Uuid),
 
+    #[error("No permission prompt pending in session: {0}")]
+    NoPendingPrompt(uuid::Uuid),
+
+    #[error("The pending prompt in session {0} changed before it was answered")]
+    PromptChanged(uuid::Uuid),
+
+    #[error("Invalid prompt option {0} (prompt has {1} options)")]
+    InvalidPromptOption(usize, usize),
+
+    #[error(
+        "Tool `{tool}` not found in PATH ({searched}). Set tool_path, or launch_mode = \"login_shell\" if it is installed via nvm/volta/npm"
+    )]
+    ToolNotFound { tool: String, searched: String },
+
+    #[error("Invalid {field}: {message}")]
+    Validation { field: String, message: String },
+
+    #[error("Unavailable: {0}")]
+    Unavailable(String),
+
     #[error("PTY error: {0}")]
     Pty(String),
 
@@ -23,4 +57,117 @@ pub enum ForgeError {
 
     #[error("iTerm2 error: {0}")]
     Iterm(String),
+
+    #[error("{0}")]
+    Internal(String),
+}
+
+impl ForgeError {
+    pub fn validation(field: &str, message: impl Into<String>) -> Self {
+        ForgeError::Validation {
+            field: field.to_string(),
+            message: message.into(),
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ForgeError::SessionNotFound(_) | ForgeError::NoSessionMatch(_) => StatusCode::NOT_FOUND,
+            ForgeError::AmbiguousSession { .. }
+            | ForgeError::SessionAlreadyStopped(_)
+            | ForgeError::NoPendingPrompt(_)
+            | ForgeError::PromptChanged(_) => StatusCode::CONFLICT,
+            ForgeError::InvalidPromptOption(..)
+            | ForgeError::ToolNotFound { .. }
+            | ForgeError::Validation { .. } => StatusCode::UNPROCESSABLE_ENTITY,
+            ForgeError::Unavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+            ForgeError::Pty(_)
+            | ForgeError::Config(_)
+            | ForgeError::Io(_)
+            | ForgeError::Serde(_)
+            | ForgeError::Iterm(_)
+            | ForgeError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// Stable machine-readable error code
+    pub fn code(&self) -> &'static str {
+        match self {
+            ForgeError::SessionNotFound(_) | ForgeError::NoSessionMatch(_) => "session_not_found",
+            ForgeError::AmbiguousSession { .. } => "ambiguous_session",
+            ForgeError::SessionAlreadyStopped(_) => "session_stopped",
+            ForgeError::NoPendingPrompt(_) => "no_pending_prompt",
+            ForgeError::PromptChanged(_) => "prompt_changed",
+            ForgeError::InvalidPromptOption(..) => "invalid_prompt_option",
+            ForgeError::ToolNotFound { .. } => "tool_not_found",
+            ForgeError::Validation { .. } => "validation_error",
+            ForgeError::Unavailable(_) => "unavailable",
+            ForgeError::Pty(_) => "pty_error",
+            ForgeError::Config(_) => "config_error",
+            ForgeError::Io(_) => "io_error",
+            ForgeError::Serde(_) => "serialization_error",
+            ForgeError::Iterm(_) => "iterm_error",
+            ForgeError::Internal(_) => "internal_error",
+        }
+    }
+
+    fn details(&self) -> Option<serde_json::Value> {
+        let details = match self {
+            ForgeError::SessionNotFound(id)
+            | ForgeError::SessionAlreadyStopped(id)
+            | ForgeError::NoPendingPrompt(id)
+            | ForgeError::PromptChanged(id) => serde_json::json!({ "id": id }),
+            ForgeError::NoSessionMatch(query) => serde_json::json!({ "query": query }),
+            ForgeError::AmbiguousSession { query, candidates } => {
+                let candidates: Vec<_> = candidates
+                    .iter()
+                    .map(|(id, name)| serde_json::json!({ "id": id, "name": name }))
+                    .collect();
+                serde_json::json!({ "query": query, "candidates": candidates })
+            }
+            ForgeError::InvalidPromptOption(option, count) => {
+                serde_json::json!({ "option": option, "options": count })
+            }
+            ForgeError::ToolNotFound { tool, searched } => {
+                serde_json::json!({ "tool": tool, "searched_path": searched })
+            }
+            ForgeError::Validation { field, .. } => serde_json::json!({ "field": field }),
+            _ => return None,
+        };
+        Some(details)
+    }
+}
+
+/// Body of every API error response.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ErrorBody {
+    pub code: String,
+    pub message: String,
+    #[serde(default)]
+    pub details: Option<serde_json::Value>,
+}
+
+impl IntoResponse for ForgeError {
+    fn into_response(self) -> Response {
+        let status = self.status();
+        if status.is_server_error() {
+            tracing::error!("{self}");
+        }
+        let body = ErrorBody {
+            code: self.code().to_string(),
+            message: self.to_string(),
+            details: self.details(),
+        };
+        (status, Json(body)).into_response()
+    }
+}
+
+/// Recover the `ForgeError` behind an `anyhow::Error`, if there is one.
+impl From<anyhow::Error> for ForgeError {
+    fn from(e: anyhow::Error) -> Self {
+        match e.downcast::<ForgeError>() {
+            Ok(forge) => forge,
+            Err(e) => ForgeError::Internal(format!("{e:#}")),
+        }
+    }
 }
@@ -1,22 +1,22 @@
 mod cli;
 mod config;
+mod doctor;
 mod error;
 mod iterm;
+mod metrics;
+mod policy;
+mod search;
 mod server;
 mod session;
+mod store;
+mod telemetry;
+mod tls;
 
 use anyhow::Result;
 use clap::Parser;
-use tracing_subscriber::EnvFilter;
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    tracing_subscriber::fmt()
-        .with_env_filter(
-            EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")),
-        )
-        .init();
-
     let cli = cli::commands::Cli::parse();
     cli::commands::dispatch(cli).await
 }
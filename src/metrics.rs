@@ -0,0 +1,287 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use crate::session::model::SessionMeta;
+
+/// Upper bounds of the request latency buckets, in seconds.
+const LATENCY_BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// How a client follows a session's output.
+#[derive(Debug, Clone, Copy)]
+pub enum Transport {
+    /// The web UI and `forge logs -f`
+    Sse,
+    /// `forge attach`
+    Attach,
+}
+
+impl Transport {
+    const ALL: [Transport; 2] = [Transport::Sse, Transport::Attach];
+
+    fn label(self) -> &'static str {
+        match self {
+            Transport::Sse => "sse",
+            Transport::Attach => "attach",
+        }
+    }
+}
+
+/// Server-wide counters for `/metrics`. Session gauges come from the store
+/// and the live sessions when the metrics are rendered.
+pub struct Metrics {
+    started_at: SystemTime,
+    spawns: Mutex<BTreeMap<String, u64>>,
+    spawn_failures: Mutex<BTreeMap<String, u64>>,
+    clients: [AtomicI64; 2],
+    resyncs: [AtomicU64; 2],
+    requests: Mutex<BTreeMap<(String, String, u16), Histogram>>,
+}
+
+#[derive(Default)]
+struct Histogram {
+    buckets: [u64; LATENCY_BUCKETS.len()],
+    count: u64,
+    sum: f64,
+}
+
+/// Byte counts of one session, updated by its actor and PTY writer.
+#[derive(Default)]
+pub struct SessionCounters {
+    /// Read from the PTY
+    pub output_bytes: AtomicU64,
+    /// Written to the PTY
+    pub input_bytes: AtomicU64,
+    /// Held in the in-memory output buffer
+    pub buffer_bytes: AtomicU64,
+}
+
+/// Counts a connected client until dropped.
+pub struct ClientGuard {
+    metrics: Arc<Metrics>,
+    transport: Transport,
+}
+
+impl Drop for ClientGuard {
+    fn drop(&mut self) {
+        self.metrics.clients[self.transport as usize].fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self {
+            started_at: SystemTime::now(),
+            spawns: Mutex::default(),
+            spawn_failures: Mutex::default(),
+            clients: Default::default(),
+            resyncs: Default::default(),
+            requests: Mutex::default(),
+        }
+    }
+}
+
+impl Metrics {
+    pub fn session_spawned(&self, tool: &str, ok: bool) {
+        let counts = if ok {
+            &self.spawns
+        } else {
+            &self.spawn_failures
+        };
+        *lock(counts).entry(tool.to_string()).or_default() += 1;
+    }
+
+    pub fn client_connected(self: &Arc<Self>, transport: Transport) -> ClientGuard {
+        self.clients[transport as usize].fetch_add(1, Ordering::Relaxed);
+        ClientGuard {
+            metrics: self.clone(),
+            transport,
+        }
+    }
+
+    /// A client fell behind the live output and had to resync.
+    pub fn client_resynced(&self, transport: Transport) {
+        self.resyncs[transport as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn request_finished(&self, method: &str, route: &str, status: u16, elapsed: Duration) {
+        let secs = elapsed.as_secs_f64();
+        let mut requests = lock(&self.requests);
+        let histogram = requests
+            .entry((method.to_string(), route.to_string(), status))
+            .or_default();
+        for (bucket, bound) in histogram.buckets.iter_mut().zip(LATENCY_BUCKETS) {
+            if secs <= bound {
+                *bucket += 1;
+            }
+        }
+        histogram.count += 1;
+        histogram.sum += secs;
+    }
+
+    /// Everything in the Prometheus text format. `session_counts` are
+    /// `(status, tool, count)` over all sessions; `sessions` are the ones
+    /// this server runs or ran.
+    pub fn render(
+        &self,
+        session_counts: &[(String, String, u64)],
+        sessions: &[(SessionMeta, Arc<SessionCounters>)],
+    ) -> String {
+        let mut out = String::new();
+
+        let started = self
+            .started_at
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default();
+        header(
+            &mut out,
+            "lineforge_start_time_seconds",
+            "gauge",
+            "Unix time the server started; changes when it restarts",
+        );
+        let _ = writeln!(out, "lineforge_start_time_seconds {}", started.as_secs());
+
+        header(
+            &mut out,
+            "lineforge_sessions",
+            "gauge",
+            "Sessions by status and tool",
+        );
+        for (status, tool, count) in session_counts {
+            let _ = writeln!(
+                out,
+                "lineforge_sessions{{status=\"{}\",tool=\"{}\"}} {count}",
+                escape(status),
+                escape(tool)
+            );
+        }
+
+        for (name, help, counts) in [
+            (
+                "lineforge_session_spawns_total",
+                "Sessions started",
+                &self.spawns,
+            ),
+            (
+                "lineforge_session_spawn_failures_total",
+                "Sessions that failed to start",
+                &self.spawn_failures,
+            ),
+        ] {
+            header(&mut out, name, "counter", help);
+            for (tool, count) in lock(counts).iter() {
+                let _ = writeln!(out, "{name}{{tool=\"{}\"}} {count}", escape(tool));
+            }
+        }
+
+        type Field = fn(&SessionCounters) -> &AtomicU64;
+        let per_session: [(&str, &str, &str, Field); 3] = [
+            (
+                "lineforge_pty_output_bytes_total",
+                "counter",
+                "Bytes read from a session's PTY",
+                |c| &c.output_bytes,
+            ),
+            (
+                "lineforge_pty_input_bytes_total",
+                "counter",
+                "Bytes written to a session's PTY",
+                |c| &c.input_bytes,
+            ),
+            (
+                "lineforge_log_buffer_bytes",
+                "gauge",
+                "Output held in a session's in-memory buffer",
+                |c| &c.buffer_bytes,
+            ),
+        ];
+        for (name, kind, help, field) in per_session {
+            header(&mut out, name, kind, help);
+            for (meta, counters) in sessions {
+                let _ = writeln!(
+                    out,
+                    "{name}{{session=\"{}\",name=\"{}\"}} {}",
+                    meta.id,
+                    escape(&meta.name),
+                    field(counters).load(Ordering::Relaxed)
+                );
+            }
+        }
+
+        header(
+            &mut out,
+            "lineforge_clients",
+            "gauge",
+            "Clients following session output",
+        );
+        for transport in Transport::ALL {
+            let count = self.clients[transport as usize].load(Ordering::Relaxed);
+            let _ = writeln!(
+                out,
+                "lineforge_clients{{transport=\"{}\"}} {count}",
+                transport.label()
+            );
+        }
+        header(
+            &mut out,
+            "lineforge_client_resyncs_total",
+            "counter",
+            "Times a client fell behind the live output and had to resync",
+        );
+        for transport in Transport::ALL {
+            let count = self.resyncs[transport as usize].load(Ordering::Relaxed);
+            let _ = writeln!(
+                out,
+                "lineforge_client_resyncs_total{{transport=\"{}\"}} {count}",
+                transport.label()
+            );
+        }
+
+        let name = "lineforge_http_request_duration_seconds";
+        header(
+            &mut out,
+            name,
+            "histogram",
+            "Time to respond to HTTP requests, by route",
+        );
+        for ((method, route, status), histogram) in lock(&self.requests).iter() {
+            let labels = format!(
+                "method=\"{}\",route=\"{}\",status=\"{status}\"",
+                escape(method),
+                escape(route)
+            );
+            for (count, bound) in histogram.buckets.iter().zip(LATENCY_BUCKETS) {
+                let _ = writeln!(out, "{name}_bucket{{{labels},le=\"{bound}\"}} {count}");
+            }
+            let _ = writeln!(
+                out,
+                "{name}_bucket{{{labels},le=\"+Inf\"}} {}",
+                histogram.count
+            );
+            let _ = writeln!(out, "{name}_sum{{{labels}}} {}", histogram.sum);
+            let _ = writeln!(out, "{name}_count{{{labels}}} {}", histogram.count);
+        }
+
+        out
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {name} {help}\n# TYPE {name} {kind}");
+}
+
+/// Escape a label value.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
@@ -0,0 +1,226 @@
+use std::path::{Component, Path, PathBuf};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::session::model::ToolKind;
+use crate::session::prompt::PermissionPrompt;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Decision {
+    Allow,
+    Deny,
+    Ask,
+}
+
+impl std::fmt::Display for Decision {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Decision::Allow => write!(f, "allow"),
+            Decision::Deny => write!(f, "deny"),
+            Decision::Ask => write!(f, "ask"),
+        }
+    }
+}
+
+/// Rule-based answering of permission prompts, a narrower alternative to yolo mode.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ApprovalConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default)]
+    pub rules: Vec<ApprovalRule>,
+}
+
+/// A single allow/deny rule. Every pattern that is set must match; patterns
+/// are globs where `*` matches any run of characters and `?` a single one.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApprovalRule {
+    pub decision: Decision,
+    /// Agent CLI the rule applies to (`claude` or `codex`), all when unset
+    pub agent: Option<String>,
+    /// Tool requested by the agent (`Bash`, `Edit`, `Write`, `mcp__*`, ...)
+    pub tool: Option<String>,
+    pub command: Option<String>,
+    pub path: Option<String>,
+}
+
+impl ApprovalRule {
+    fn matches(&self, agent: &ToolKind, working_dir: &Path, prompt: &PermissionPrompt) -> bool {
+        if let Some(ref a) = self.agent
+            && !a.eq_ignore_ascii_case(agent.command_name())
+        {
+            return false;
+        }
+        if let Some(ref pat) = self.tool
+            && !prompt.tool.as_deref().is_some_and(|t| glob_match(pat, t))
+        {
+            return false;
+        }
+        if let Some(ref pat) = self.command
+            && !prompt
+                .command
+                .as_deref()
+                .is_some_and(|c| glob_match(pat, c))
+        {
+            return false;
+        }
+        if let Some(ref pat) = self.path {
+            let Some(ref path) = prompt.path else {
+                return false;
+            };
+            // Prompts usually show paths relative to the session's working
+            // dir. A path that climbs out with `..` is only matched in its
+            // resolved form, so `../../etc/passwd` can't pass for `src/*`.
+            let relative = normalize(Path::new(path));
+            let absolute = normalize(&working_dir.join(path));
+            if ![relative, absolute]
+                .iter()
+                .filter(|p| !climbs_out(p))
+                .any(|p| glob_match(pat, &p.to_string_lossy()))
+            {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+impl ApprovalConfig {
+    /// Decide how to answer a prompt. Deny rules win over ask, ask over allow,
+    /// and prompts no rule matches are escalated to a human. Allow rules never
+    /// match a command that chains or redirects, or a path that still climbs
+    /// out with `..` once resolved; those are always escalated.
+    ///
+    /// Returns the decision and the index of the rule that produced it.
+    pub fn evaluate(
+        &self,
+        agent: &ToolKind,
+        working_dir: &Path,
+        prompt: &PermissionPrompt,
+    ) -> (Decision, Option<usize>) {
+        let allowable = allowable(working_dir, prompt);
+        let mut best: (Decision, Option<usize>) = (Decision::Ask, None);
+        for (idx, rule) in self.rules.iter().enumerate() {
+            if (rule.decision == Decision::Allow && !allowable)
+                || !rule.matches(agent, working_dir, prompt)
+            {
+                continue;
+            }
+            let rank = |d: Decision| match d {
+                Decision::Deny => 2,
+                Decision::Ask => 1,
+                Decision::Allow => 0,
+            };
+            if best.1.is_none() || rank(rule.decision) > rank(best.0) {
+                best = (rule.decision, Some(idx));
+            }
+        }
+        best
+    }
+}
+
+/// Shell syntax that runs more than the command a pattern was written for:
+/// a `*` in `cargo *` would otherwise also match `cargo build && curl … | sh`.
+const SHELL_OPERATORS: &[&str] = &[";", "&", "|", "`", "$(", ">", "<", "\n"];
+
+/// Whether a prompt is simple enough for an allow rule to answer it.
+fn allowable(working_dir: &Path, prompt: &PermissionPrompt) -> bool {
+    let plain_command = prompt
+        .command
+        .as_deref()
+        .is_none_or(|c| !SHELL_OPERATORS.iter().any(|op| c.contains(op)));
+    let contained_path = prompt
+        .path
+        .as_deref()
+        .is_none_or(|p| !climbs_out(&normalize(&working_dir.join(p))));
+    plain_command && contained_path
+}
+
+/// Resolve `.` and `..` without touching the disk, since the file a prompt
+/// names may not exist yet. A relative path keeps the `..`s it can't resolve.
+fn normalize(path: &Path) -> PathBuf {
+    let mut out = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => match out.components().next_back() {
+                Some(Component::Normal(_)) => {
+                    out.pop();
+                }
+                // `/..` is `/`
+                Some(Component::RootDir | Component::Prefix(_)) => {}
+                _ => out.push(".."),
+            },
+            other => out.push(other),
+        }
+    }
+    out
+}
+
+fn climbs_out(path: &Path) -> bool {
+    path.components().any(|c| c == Component::ParentDir)
+}
+
+/// An automatic decision, appended to the session's `approvals.jsonl`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApprovalRecord {
+    pub timestamp: DateTime<Utc>,
+    pub decision: Decision,
+    /// Index into `approval.rules` of the rule that decided, if any
+    pub rule: Option<usize>,
+    pub tool: Option<String>,
+    pub command: Option<String>,
+    pub path: Option<String>,
+    pub question: String,
+    /// Label of the option that was selected
+    pub answer: Option<String>,
+}
+
+pub fn append_record(session_dir: &Path, record: &ApprovalRecord) -> std::io::Result<()> {
+    use std::io::Write;
+
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(session_dir.join("approvals.jsonl"))?;
+    let line = serde_json::to_string(record)?;
+    writeln!(file, "{line}")
+}
+
+pub fn load_records(session_dir: &Path) -> Vec<ApprovalRecord> {
+    std::fs::read_to_string(session_dir.join("approvals.jsonl"))
+        .map(|content| {
+            content
+                .lines()
+                .filter_map(|l| serde_json::from_str(l).ok())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Minimal glob matching: `*` matches any run of characters, `?` exactly one.
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let p: Vec<char> = pattern.chars().collect();
+    let t: Vec<char> = text.chars().collect();
+    let (mut pi, mut ti) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+
+    while ti < t.len() {
+        if pi < p.len() && (p[pi] == '?' || p[pi] == t[ti]) {
+            pi += 1;
+            ti += 1;
+        } else if pi < p.len() && p[pi] == '*' {
+            star = Some((pi, ti));
+            pi += 1;
+        } else if let Some((sp, st)) = star {
+            pi = sp + 1;
+            ti = st + 1;
+            star = Some((sp, st + 1));
+        } else {
+            return false;
+        }
+    }
+    p[pi..].iter().all(|&c| c == '*')
+}
@@ -0,0 +1,590 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, TimeDelta, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::error::ForgeError;
+use crate::session::ansi::strip_ansi;
+use crate::session::model::{SessionMeta, ToolKind};
+use crate::session::query::{SessionFilter, parse_since};
+use crate::store::Store;
+
+/// Largest number of hits one search returns.
+pub const MAX_RESULTS: usize = 500;
+const DEFAULT_RESULTS: usize = 50;
+
+/// Tokens longer than this are not indexed (base64 blobs, hashes).
+const MAX_TOKEN_LEN: usize = 64;
+
+/// Live output waiting to be indexed beyond this is dropped, rather than
+/// growing without bound when sessions print faster than it is indexed.
+const MAX_QUEUED_BYTES: usize = 8 * 1024 * 1024;
+
+/// A partial line is indexed as it stands once it grows past this, so
+/// output that never prints a newline (progress bars redrawn with `\r`,
+/// spinners) doesn't pile up.
+const MAX_PENDING_LINE: usize = 16 * 1024;
+
+/// Updates the indexer applies under one write lock.
+const BATCH: usize = 256;
+
+/// How often sessions older than the retention period are dropped.
+const EXPIRE_INTERVAL: Duration = Duration::from_secs(600);
+
+/// Inverted index over the ANSI-stripped output of every session, live and
+/// saved. Matching is per line: a line matches when it contains every
+/// query term.
+///
+/// Live output is queued and indexed on a thread of its own, so sessions
+/// never wait for the index. It holds at most `budget` bytes (roughly):
+/// beyond that the oldest output goes first, whole finished sessions
+/// before the oldest lines of live ones. Sessions last updated before the
+/// retention period are dropped.
+pub struct SearchIndex {
+    inner: Arc<RwLock<Inner>>,
+    updates: mpsc::Sender<Update>,
+    /// Bytes of output sent to the indexer and not yet taken off the queue
+    queued: Arc<AtomicUsize>,
+    dropping: Arc<AtomicBool>,
+    retention: TimeDelta,
+}
+
+enum Update {
+    Output {
+        id: Uuid,
+        text: String,
+        at: DateTime<Utc>,
+    },
+    Finish(Uuid),
+}
+
+struct Inner {
+    docs: HashMap<Uuid, Document>,
+    /// token -> (session, line number) of every line containing it. Entries
+    /// of evicted lines are skipped, and cleared out once they pile up.
+    postings: HashMap<String, Vec<(Uuid, usize)>>,
+    postings_len: usize,
+    stale: usize,
+    /// Approximate memory held by indexed lines and their postings
+    bytes: usize,
+    budget: usize,
+}
+
+struct Document {
+    name: String,
+    tool: ToolKind,
+    updated_at: DateTime<Utc>,
+    lines: VecDeque<IndexedLine>,
+    /// Line number of `lines[0]`; the lines before it were evicted
+    first: usize,
+    postings: usize,
+    bytes: usize,
+    /// Still receiving output; finished sessions are evicted first
+    live: bool,
+}
+
+/// A line ready to be indexed, prepared outside the lock.
+struct PreparedLine {
+    text: String,
+    tokens: HashSet<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct IndexedLine {
+    pub text: String,
+    /// When the line was written; `None` for lines loaded from `output.log`
+    pub timestamp: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SearchHit {
+    pub session_id: Uuid,
+    pub session_name: String,
+    pub tool: ToolKind,
+    /// 1-based line number in the session's plain-text log
+    pub line: usize,
+    pub text: String,
+    pub timestamp: Option<DateTime<Utc>>,
+    /// Page showing the line in context
+    pub url: String,
+}
+
+/// Query for `GET /api/search`.
+#[derive(Debug, Default, Deserialize)]
+pub struct SearchQuery {
+    #[serde(default)]
+    pub q: String,
+    /// Session UUID, UUID prefix or name
+    pub session: Option<String>,
+    /// RFC 3339 timestamp or a relative age such as `2h`
+    pub since: Option<String>,
+    pub until: Option<String>,
+    pub limit: Option<usize>,
+}
+
+/// A [`SearchQuery`] with its values parsed and checked.
+pub struct SearchFilter {
+    terms: Vec<String>,
+    sessions: Option<HashSet<Uuid>>,
+    since: Option<DateTime<Utc>>,
+    until: Option<DateTime<Utc>>,
+    limit: usize,
+}
+
+impl SearchQuery {
+    /// `sessions` is the set the `session` filter resolved to, if one was given.
+    fn parse(&self, sessions: Option<HashSet<Uuid>>) -> Result<SearchFilter, ForgeError> {
+        let terms = tokenize(&self.q);
+        if terms.is_empty() {
+            return Err(ForgeError::validation(
+                "q",
+                "must contain at least one word of two or more letters or digits",
+            ));
+        }
+        let now = Utc::now();
+        let parse = |field: &str, value: &Option<String>| {
+            value
+                .as_deref()
+                .map(|v| parse_since(v, now))
+                .transpose()
+                .map_err(|e| ForgeError::validation(field, e))
+        };
+        let limit = self.limit.unwrap_or(DEFAULT_RESULTS);
+        if limit == 0 || limit > MAX_RESULTS {
+            return Err(ForgeError::validation(
+                "limit",
+                format!("must be 1-{MAX_RESULTS}"),
+            ));
+        }
+
+        Ok(SearchFilter {
+            terms,
+            sessions,
+            since: parse("since", &self.since)?,
+            until: parse("until", &self.until)?,
+            limit,
+        })
+    }
+}
+
+impl SearchIndex {
+    /// An index holding about `budget` bytes, forgetting sessions that
+    /// haven't been updated for `retention`.
+    pub fn new(budget: usize, retention: TimeDelta) -> Self {
+        let inner = Arc::new(RwLock::new(Inner {
+            docs: HashMap::new(),
+            postings: HashMap::new(),
+            postings_len: 0,
+            stale: 0,
+            bytes: 0,
+            budget,
+        }));
+        let (updates, rx) = mpsc::channel();
+        let queued = Arc::new(AtomicUsize::new(0));
+        let dropping = Arc::new(AtomicBool::new(false));
+        let indexer = Indexer {
+            inner: inner.clone(),
+            queued: queued.clone(),
+            dropping: dropping.clone(),
+            retention,
+            pending: HashMap::new(),
+        };
+        if let Err(e) = std::thread::Builder::new()
+            .name("search-index".into())
+            .spawn(move || indexer.run(rx))
+        {
+            tracing::error!("Failed to start the search indexer: {e}");
+        }
+        Self {
+            inner,
+            updates,
+            queued,
+            dropping,
+            retention,
+        }
+    }
+
+    /// Start indexing a session. Output for unknown sessions is ignored.
+    pub fn add_session(&self, meta: &SessionMeta) {
+        let mut inner = self.inner.write().unwrap_or_else(|e| e.into_inner());
+        inner
+            .docs
+            .entry(meta.id)
+            .or_insert_with(|| Document::new(meta, true));
+    }
+
+    /// Queue a chunk of live output for indexing. Never blocks; the chunk
+    /// is dropped when the indexer is too far behind.
+    pub fn append(&self, id: Uuid, text: String, at: DateTime<Utc>) {
+        let len = text.len();
+        if self.queued.fetch_add(len, Ordering::Relaxed) + len > MAX_QUEUED_BYTES {
+            self.queued.fetch_sub(len, Ordering::Relaxed);
+            if !self.dropping.swap(true, Ordering::Relaxed) {
+                tracing::warn!("Search indexing is behind; dropping output until it catches up");
+            }
+            return;
+        }
+        let _ = self.updates.send(Update::Output { id, text, at });
+    }
+
+    /// Index whatever is left of an unterminated last line; the session
+    /// gets no more output.
+    pub fn finish(&self, id: Uuid) {
+        let _ = self.updates.send(Update::Finish(id));
+    }
+
+    /// Index the saved output (all log segments) of every session in the
+    /// store that isn't already indexed, back to the retention period.
+    pub fn index_saved_sessions(&self, store: &dyn Store) {
+        let metas = match store.list_sessions(&SessionFilter::all()) {
+            Ok((metas, _)) => metas,
+            Err(e) => {
+                tracing::warn!("Failed to list saved sessions: {e:#}");
+                return;
+            }
+        };
+        let cutoff = Utc::now() - self.retention;
+        let mut indexed = 0;
+        for meta in metas.into_iter().filter(|m| m.updated_at >= cutoff) {
+            if self.contains(meta.id) {
+                continue;
+            }
+            let dir = crate::session::persist::session_dir(meta.id);
+            let Ok(bytes) = crate::session::writer::read_log(&dir) else {
+                continue;
+            };
+            let lines = prepare_lines(&String::from_utf8_lossy(&bytes));
+
+            let mut inner = self.inner.write().unwrap_or_else(|e| e.into_inner());
+            if inner.docs.contains_key(&meta.id) {
+                continue;
+            }
+            inner.docs.insert(meta.id, Document::new(&meta, false));
+            inner.add_lines(meta.id, lines, None);
+            inner.evict();
+            indexed += 1;
+        }
+        tracing::info!("Indexed {indexed} saved session logs for search");
+    }
+
+    fn contains(&self, id: Uuid) -> bool {
+        let inner = self.inner.read().unwrap_or_else(|e| e.into_inner());
+        inner.docs.contains_key(&id)
+    }
+
+    /// Parse `query`, resolve its session filter and run it.
+    pub fn query(&self, query: &SearchQuery) -> Result<Vec<SearchHit>, ForgeError> {
+        let sessions = query.session.as_deref().map(|q| self.sessions_matching(q));
+        let filter = query.parse(sessions)?;
+        Ok(self.search(&filter))
+    }
+
+    /// Indexed sessions whose name is `query`, or failing that whose UUID
+    /// starts with it. Unlike session routes, several matches are fine.
+    pub fn sessions_matching(&self, query: &str) -> HashSet<Uuid> {
+        let inner = self.inner.read().unwrap_or_else(|e| e.into_inner());
+        let by_name: HashSet<Uuid> = inner
+            .docs
+            .iter()
+            .filter(|(_, doc)| doc.name == query)
+            .map(|(id, _)| *id)
+            .collect();
+        if !by_name.is_empty() {
+            return by_name;
+        }
+        let query = query.to_lowercase();
+        inner
+            .docs
+            .keys()
+            .filter(|id| id.to_string().starts_with(&query))
+            .copied()
+            .collect()
+    }
+
+    pub fn search(&self, filter: &SearchFilter) -> Vec<SearchHit> {
+        let inner = self.inner.read().unwrap_or_else(|e| e.into_inner());
+
+        // Start from the rarest term and check the rest line by line
+        let Some(postings) = filter
+            .terms
+            .iter()
+            .map(|t| inner.postings.get(t).map(Vec::as_slice).unwrap_or_default())
+            .min_by_key(|p| p.len())
+        else {
+            return Vec::new();
+        };
+
+        let mut hits: Vec<SearchHit> = postings
+            .iter()
+            .filter(|(id, _)| filter.sessions.as_ref().is_none_or(|s| s.contains(id)))
+            .filter_map(|&(id, idx)| {
+                let doc = inner.docs.get(&id)?;
+                let line = doc.lines.get(idx.checked_sub(doc.first)?)?;
+                let at = line.timestamp.unwrap_or(doc.updated_at);
+                if filter.since.is_some_and(|since| at < since)
+                    || filter.until.is_some_and(|until| at > until)
+                {
+                    return None;
+                }
+                let tokens: HashSet<String> = tokenize(&line.text).into_iter().collect();
+                if !filter.terms.iter().all(|t| tokens.contains(t)) {
+                    return None;
+                }
+                Some(SearchHit {
+                    session_id: id,
+                    session_name: doc.name.clone(),
+                    tool: doc.tool.clone(),
+                    line: idx + 1,
+                    text: line.text.clone(),
+                    timestamp: line.timestamp,
+                    url: format!("/sessions/{id}/log#L{}", idx + 1),
+                })
+            })
+            .collect();
+
+        hits.sort_by_key(|h| {
+            let doc_updated = inner.docs.get(&h.session_id).map(|d| d.updated_at);
+            std::cmp::Reverse((h.timestamp.or(doc_updated), h.line))
+        });
+        hits.truncate(filter.limit);
+        hits
+    }
+
+    /// Name and plain-text lines of an indexed session, with the 0-based
+    /// number of the first line still indexed.
+    pub fn lines(&self, id: Uuid) -> Option<(String, usize, Vec<IndexedLine>)> {
+        let inner = self.inner.read().unwrap_or_else(|e| e.into_inner());
+        let doc = inner.docs.get(&id)?;
+        Some((
+            doc.name.clone(),
+            doc.first,
+            doc.lines.iter().cloned().collect(),
+        ))
+    }
+}
+
+/// Indexes queued live output on its own thread, and expires old sessions.
+struct Indexer {
+    inner: Arc<RwLock<Inner>>,
+    queued: Arc<AtomicUsize>,
+    dropping: Arc<AtomicBool>,
+    retention: TimeDelta,
+    /// Output after the last newline per session, indexed once the line
+    /// completes, with when it was written
+    pending: HashMap<Uuid, (String, DateTime<Utc>)>,
+}
+
+impl Indexer {
+    fn run(mut self, updates: mpsc::Receiver<Update>) {
+        let mut expired_at = Instant::now();
+        loop {
+            let first = match updates.recv_timeout(EXPIRE_INTERVAL) {
+                Ok(update) => Some(update),
+                Err(RecvTimeoutError::Timeout) => None,
+                Err(RecvTimeoutError::Disconnected) => return,
+            };
+            // Split, strip and tokenize before taking the lock, then add
+            // everything queued so far at once
+            let mut batch = Vec::new();
+            let mut finished = Vec::new();
+            for update in first.into_iter().chain(updates.try_iter().take(BATCH)) {
+                match update {
+                    Update::Output { id, text, at } => {
+                        self.queued.fetch_sub(text.len(), Ordering::Relaxed);
+                        let (pending, updated) = self.pending.entry(id).or_default();
+                        pending.push_str(&text);
+                        *updated = at;
+                        if pending.len() > MAX_PENDING_LINE {
+                            batch.push((id, prepare_lines(&std::mem::take(pending)), at));
+                        } else if let Some(end) = pending.rfind('\n') {
+                            let complete: String = pending.drain(..=end).collect();
+                            batch.push((id, prepare_lines(&complete), at));
+                        }
+                    }
+                    Update::Finish(id) => {
+                        if let Some((rest, at)) = self.pending.remove(&id) {
+                            batch.push((id, prepare_lines(&rest), at));
+                        }
+                        finished.push(id);
+                    }
+                }
+            }
+            if self.queued.load(Ordering::Relaxed) == 0 {
+                self.dropping.store(false, Ordering::Relaxed);
+            }
+
+            let mut inner = self.inner.write().unwrap_or_else(|e| e.into_inner());
+            for (id, lines, at) in batch {
+                inner.add_lines(id, lines, Some(at));
+            }
+            for id in finished {
+                if let Some(doc) = inner.docs.get_mut(&id) {
+                    doc.live = false;
+                }
+            }
+            if expired_at.elapsed() >= EXPIRE_INTERVAL {
+                inner.expire(Utc::now() - self.retention);
+                expired_at = Instant::now();
+            }
+            inner.evict();
+        }
+    }
+}
+
+impl Document {
+    fn new(meta: &SessionMeta, live: bool) -> Self {
+        Self {
+            name: meta.name.clone(),
+            tool: meta.tool.clone(),
+            updated_at: meta.updated_at,
+            lines: VecDeque::new(),
+            first: 0,
+            postings: 0,
+            bytes: 0,
+            live,
+        }
+    }
+}
+
+impl Inner {
+    fn add_lines(&mut self, id: Uuid, lines: Vec<PreparedLine>, timestamp: Option<DateTime<Utc>>) {
+        let Some(doc) = self.docs.get_mut(&id) else {
+            return;
+        };
+        if let Some(at) = timestamp {
+            doc.updated_at = at;
+        }
+        for line in lines {
+            // Full-screen tools redraw the same line over and over
+            if doc.lines.back().is_some_and(|l| l.text == line.text) {
+                continue;
+            }
+            let idx = doc.first + doc.lines.len();
+            let cost = line_cost(&line.text, line.tokens.len());
+            doc.postings += line.tokens.len();
+            self.postings_len += line.tokens.len();
+            for token in line.tokens {
+                self.postings.entry(token).or_default().push((id, idx));
+            }
+            doc.bytes += cost;
+            self.bytes += cost;
+            doc.lines.push_back(IndexedLine {
+                text: line.text,
+                timestamp,
+            });
+        }
+    }
+
+    /// Drop finished sessions last updated before `cutoff`.
+    fn expire(&mut self, cutoff: DateTime<Utc>) {
+        let expired: Vec<Uuid> = self
+            .docs
+            .iter()
+            .filter(|(_, doc)| !doc.live && doc.updated_at < cutoff)
+            .map(|(id, _)| *id)
+            .collect();
+        for id in expired {
+            self.remove(id);
+        }
+        self.compact();
+    }
+
+    /// Get back under a tenth below the budget, oldest output first.
+    fn evict(&mut self) {
+        if self.bytes <= self.budget {
+            return;
+        }
+        let target = self.budget / 10 * 9;
+        let mut order: Vec<(bool, DateTime<Utc>, Uuid)> = self
+            .docs
+            .iter()
+            .map(|(id, doc)| (doc.live, doc.updated_at, *id))
+            .collect();
+        order.sort();
+        for (live, _, id) in order {
+            if self.bytes <= target {
+                break;
+            }
+            let Some(doc) = self.docs.get_mut(&id) else {
+                continue;
+            };
+            if !live && self.bytes - doc.bytes >= target {
+                self.remove(id);
+                continue;
+            }
+            while self.bytes > target
+                && let Some(line) = doc.lines.pop_front()
+            {
+                let tokens = tokenize(&line.text)
+                    .into_iter()
+                    .collect::<HashSet<_>>()
+                    .len();
+                let cost = line_cost(&line.text, tokens);
+                doc.first += 1;
+                doc.postings -= tokens;
+                doc.bytes -= cost;
+                self.bytes -= cost;
+                self.stale += tokens;
+            }
+            if !live && doc.lines.is_empty() {
+                self.remove(id);
+            }
+        }
+        self.compact();
+    }
+
+    fn remove(&mut self, id: Uuid) {
+        if let Some(doc) = self.docs.remove(&id) {
+            self.bytes -= doc.bytes;
+            self.stale += doc.postings;
+        }
+    }
+
+    /// Clear out postings of evicted lines once they are half of all.
+    fn compact(&mut self) {
+        if self.stale * 2 < self.postings_len {
+            return;
+        }
+        let docs = &self.docs;
+        self.postings.retain(|_, entries| {
+            entries.retain(|(id, idx)| docs.get(id).is_some_and(|doc| *idx >= doc.first));
+            entries.shrink_to_fit();
+            !entries.is_empty()
+        });
+        self.postings.shrink_to_fit();
+        self.postings_len -= self.stale;
+        self.stale = 0;
+    }
+}
+
+/// Strip, trim and tokenize the non-blank lines of `text`.
+fn prepare_lines(text: &str) -> Vec<PreparedLine> {
+    strip_ansi(text)
+        .lines()
+        .map(str::trim_end)
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| PreparedLine {
+            text: line.to_string(),
+            tokens: tokenize(line).into_iter().collect(),
+        })
+        .collect()
+}
+
+/// Roughly what an indexed line costs: its text, its entry in the
+/// document and one posting per distinct token.
+fn line_cost(text: &str, tokens: usize) -> usize {
+    text.len() + std::mem::size_of::<IndexedLine>() + tokens * std::mem::size_of::<(Uuid, usize)>()
+}
+
+/// Lowercased runs of letters and digits, at least two characters long.
+pub fn tokenize(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|t| t.chars().count() >= 2 && t.len() <= MAX_TOKEN_LEN)
+        .map(str::to_lowercase)
+        .collect()
+}
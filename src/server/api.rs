@@ -1,37 +1,115 @@
+use std::collections::BTreeMap;
 use std::path::PathBuf;
 use std::sync::Arc;
 
-use axum::extract::{Path, State};
-use axum::http::StatusCode;
-use axum::response::IntoResponse;
+use axum::Router;
+use axum::extract::{MatchedPath, Request, State};
+use axum::http::{StatusCode, header};
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
 use axum::routing::{get, post};
-use axum::{Json, Router};
 use serde::Deserialize;
-use uuid::Uuid;
 
-use crate::session::manager::SessionManager;
+use crate::config::{Config, ResourceLimits};
+use crate::error::ForgeError;
+use crate::search::SearchQuery;
+use crate::server::extract::{Json, Query, SessionId};
+use crate::session::log::LogQuery;
+use crate::session::manager::{SessionManager, SpawnOptions};
 use crate::session::model::ToolKind;
+use crate::session::query::SessionQuery;
+
+type ApiResult<T> = Result<T, ForgeError>;
 
 type AppState = Arc<SessionManager>;
 
 pub fn routes() -> Router<AppState> {
     Router::new()
         .route("/health", get(health))
+        .route("/api/diagnostics", get(diagnostics))
+        .route("/api/search", get(search))
         .route("/api/sessions", get(list_sessions).post(create_session))
         .route("/api/sessions/{id}", get(get_session))
+        .route("/api/sessions/{id}/logs/history", get(log_history))
         .route("/api/sessions/{id}/input", post(send_input))
         .route("/api/sessions/{id}/stop", post(stop_session))
         .route("/api/sessions/{id}/resize", post(resize_session))
         .route("/api/sessions/{id}/open-iterm", post(open_iterm))
+        .route("/api/sessions/{id}/approvals", get(list_approvals))
+        .route("/api/sessions/{id}/tags", get(get_tags).put(set_tags))
+        .route("/api/sessions/{id}/events", get(list_events))
+        .route("/api/sessions/{id}/transitions", get(list_transitions))
+        .route("/api/sessions/{id}/resources", get(get_resources))
+        .route(
+            "/api/sessions/{id}/prompt",
+            get(get_prompt).post(answer_prompt),
+        )
 }
 
 async fn health() -> &'static str {
     "OK"
 }
 
-async fn list_sessions(State(mgr): State<AppState>) -> impl IntoResponse {
-    let sessions = mgr.list().await;
-    Json(sessions)
+/// `/metrics`, plus request latencies for every route. Only added when
+/// `metrics` is on.
+pub fn with_metrics(router: Router<AppState>, state: AppState) -> Router<AppState> {
+    router
+        .route("/metrics", get(metrics))
+        .layer(middleware::from_fn_with_state(state, track_requests))
+}
+
+async fn metrics(State(mgr): State<AppState>) -> ApiResult<impl IntoResponse> {
+    let counts = mgr.with_store(|store| store.session_counts()).await?;
+    let body = mgr.metrics.render(&counts, &mgr.session_counters());
+    Ok(([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], body))
+}
+
+async fn track_requests(
+    State(mgr): State<AppState>,
+    route: Option<MatchedPath>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let method = request.method().clone();
+    let started = std::time::Instant::now();
+    let response = next.run(request).await;
+    // Label by route pattern, not path, so session ids don't explode the series
+    let route = route.as_ref().map_or("unmatched", MatchedPath::as_str);
+    mgr.metrics.request_finished(
+        method.as_str(),
+        route,
+        response.status().as_u16(),
+        started.elapsed(),
+    );
+    response
+}
+
+async fn diagnostics(State(mgr): State<AppState>) -> impl IntoResponse {
+    let report = crate::doctor::run(
+        Ok(mgr.config.clone()),
+        &Config::config_path(),
+        crate::doctor::Origin::Server,
+    )
+    .await;
+    Json(report)
+}
+
+async fn search(
+    State(mgr): State<AppState>,
+    Query(query): Query<SearchQuery>,
+) -> ApiResult<impl IntoResponse> {
+    Ok(Json(mgr.search.query(&query)?))
+}
+
+/// Filtered, newest-first page of sessions. The number of matches before
+/// paging is returned in `X-Total-Count`.
+async fn list_sessions(
+    State(mgr): State<AppState>,
+    Query(query): Query<SessionQuery>,
+) -> ApiResult<impl IntoResponse> {
+    let filter = query.parse()?;
+    let (sessions, total) = mgr.query(filter).await?;
+    Ok(([("x-total-count", total.to_string())], Json(sessions)))
 }
 
 #[derive(Deserialize)]
@@ -43,50 +121,92 @@ struct CreateSessionRequest {
     auto_open_iterm: Option<bool>,
     rows: Option<u16>,
     cols: Option<u16>,
+    env: Option<BTreeMap<String, String>>,
+    env_files: Option<Vec<PathBuf>>,
+    load_dotenv: Option<bool>,
+    limits: Option<ResourceLimits>,
 }
 
 async fn create_session(
     State(mgr): State<AppState>,
     Json(req): Json<CreateSessionRequest>,
-) -> impl IntoResponse {
+) -> ApiResult<impl IntoResponse> {
     let tool: ToolKind = req
         .tool
         .unwrap_or_else(|| mgr.config.default_tool.clone())
         .parse()
-        .map_err(|e: String| (StatusCode::BAD_REQUEST, e))?;
+        .map_err(|e: String| ForgeError::validation("tool", e))?;
 
     let name = req.name.unwrap_or_else(|| format!("{tool} session"));
     let working_dir = req
         .working_dir
         .unwrap_or_else(|| std::env::current_dir().unwrap_or_default());
+    if !working_dir.is_dir() {
+        return Err(ForgeError::validation(
+            "working_dir",
+            format!("{} is not a directory", working_dir.display()),
+        ));
+    }
     let extra_args = req.extra_args.unwrap_or_default();
 
     let rows = req.rows.unwrap_or(24);
     let cols = req.cols.unwrap_or(80);
+    validate_size(rows, cols)?;
+    let limits = req.limits.unwrap_or_default();
+    limits.validate()?;
 
-    match mgr
-        .spawn(name, tool, working_dir.clone(), extra_args, rows, cols)
-        .await
+    let opts = SpawnOptions {
+        name,
+        tool,
+        working_dir: working_dir.clone(),
+        extra_args,
+        rows,
+        cols,
+        env: req.env.unwrap_or_default(),
+        env_files: req.env_files.unwrap_or_default(),
+        load_dotenv: req.load_dotenv,
+        limits,
+    };
+
+    let meta = mgr.spawn(opts).await?;
+
+    // Optionally open in iTerm2
+    if req.auto_open_iterm.unwrap_or(false)
+        && mgr.config.iterm_enabled
+        && let Err(e) = crate::iterm::open_in_iterm(meta.id, &working_dir)
     {
-        Ok(meta) => {
-            // Optionally open in iTerm2
-            if req.auto_open_iterm.unwrap_or(false)
-                && mgr.config.iterm_enabled
-                && let Err(e) = crate::iterm::open_in_iterm(meta.id, &working_dir)
-            {
-                tracing::warn!("Failed to open iTerm2: {e}");
-            }
-            Ok((StatusCode::CREATED, Json(meta)))
-        }
-        Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
+        tracing::warn!("Failed to open iTerm2: {e}");
     }
+    Ok((StatusCode::CREATED, Json(meta)))
 }
 
-async fn get_session(State(mgr): State<AppState>, Path(id): Path<Uuid>) -> impl IntoResponse {
-    match mgr.get(id).await {
-        Ok(meta) => Ok(Json(meta)),
-        Err(_) => Err((StatusCode::NOT_FOUND, "Session not found")),
-    }
+async fn get_session(
+    State(mgr): State<AppState>,
+    SessionId(id): SessionId,
+) -> ApiResult<impl IntoResponse> {
+    Ok(Json(mgr.get(id).await?))
+}
+
+/// Buffered output as JSON, optionally limited with
+/// `?after_seq=&since=&tail=&limit=`. `X-Last-Seq` is the newest entry's
+/// sequence number; `X-Missed-Entries` counts entries after `after_seq`
+/// that were already evicted.
+async fn log_history(
+    State(mgr): State<AppState>,
+    SessionId(id): SessionId,
+    Query(range): Query<LogQuery>,
+) -> ApiResult<impl IntoResponse> {
+    let slice = mgr.get_log_after(id, range.after_seq.unwrap_or(0)).await?;
+    let missed = if range.after_seq.is_some() {
+        slice.missed
+    } else {
+        0
+    };
+    let headers = [
+        ("x-last-seq", slice.last_seq.to_string()),
+        ("x-missed-entries", missed.to_string()),
+    ];
+    Ok((headers, Json(range.select(slice.entries)?)))
 }
 
 #[derive(Deserialize)]
@@ -96,13 +216,11 @@ struct InputRequest {
 
 async fn send_input(
     State(mgr): State<AppState>,
-    Path(id): Path<Uuid>,
+    SessionId(id): SessionId,
     Json(req): Json<InputRequest>,
-) -> impl IntoResponse {
-    match mgr.send_input(id, req.text.into_bytes()).await {
-        Ok(()) => Ok(StatusCode::OK),
-        Err(e) => Err((StatusCode::BAD_REQUEST, e.to_string())),
-    }
+) -> ApiResult<StatusCode> {
+    mgr.send_input(id, req.text.into_bytes()).await?;
+    Ok(StatusCode::OK)
 }
 
 #[derive(Deserialize)]
@@ -111,45 +229,145 @@ struct ResizeRequest {
     cols: u16,
 }
 
+fn validate_size(rows: u16, cols: u16) -> ApiResult<()> {
+    if rows == 0 || rows > 500 || cols == 0 || cols > 500 {
+        return Err(ForgeError::validation(
+            "size",
+            "rows and cols must be 1-500",
+        ));
+    }
+    Ok(())
+}
+
 async fn resize_session(
     State(mgr): State<AppState>,
-    Path(id): Path<Uuid>,
+    SessionId(id): SessionId,
     Json(req): Json<ResizeRequest>,
-) -> impl IntoResponse {
-    if req.rows == 0 || req.rows > 500 || req.cols == 0 || req.cols > 500 {
-        return Err((
-            StatusCode::BAD_REQUEST,
-            "rows and cols must be 1-500".to_string(),
+) -> ApiResult<StatusCode> {
+    validate_size(req.rows, req.cols)?;
+    mgr.resize(id, req.rows, req.cols).await?;
+    Ok(StatusCode::OK)
+}
+
+async fn stop_session(
+    State(mgr): State<AppState>,
+    SessionId(id): SessionId,
+) -> ApiResult<StatusCode> {
+    mgr.stop(id).await?;
+    Ok(StatusCode::OK)
+}
+
+async fn open_iterm(
+    State(mgr): State<AppState>,
+    SessionId(id): SessionId,
+) -> ApiResult<StatusCode> {
+    if !mgr.config.iterm_enabled {
+        return Err(ForgeError::Unavailable(
+            "iTerm2 integration disabled".into(),
         ));
     }
-    match mgr.resize(id, req.rows, req.cols).await {
-        Ok(()) => Ok(StatusCode::OK),
-        Err(e) => Err((StatusCode::BAD_REQUEST, e.to_string())),
-    }
+
+    let meta = mgr.get(id).await?;
+    crate::iterm::open_in_iterm(id, &meta.working_dir)?;
+    Ok(StatusCode::OK)
 }
 
-async fn stop_session(State(mgr): State<AppState>, Path(id): Path<Uuid>) -> impl IntoResponse {
-    match mgr.stop(id).await {
-        Ok(()) => Ok(StatusCode::OK),
-        Err(e) => Err((StatusCode::BAD_REQUEST, e.to_string())),
-    }
+async fn list_approvals(
+    State(mgr): State<AppState>,
+    SessionId(id): SessionId,
+) -> ApiResult<impl IntoResponse> {
+    mgr.get(id).await?;
+    let session_dir = Config::sessions_dir().join(id.to_string());
+    Ok(Json(crate::policy::load_records(&session_dir)))
 }
 
-async fn open_iterm(State(mgr): State<AppState>, Path(id): Path<Uuid>) -> impl IntoResponse {
-    if !mgr.config.iterm_enabled {
-        return Err((
-            StatusCode::BAD_REQUEST,
-            "iTerm2 integration disabled".to_string(),
+async fn get_tags(
+    State(mgr): State<AppState>,
+    SessionId(id): SessionId,
+) -> ApiResult<impl IntoResponse> {
+    Ok(Json(mgr.with_store(move |store| store.tags(id)).await?))
+}
+
+/// Replace a session's tags with the given list.
+async fn set_tags(
+    State(mgr): State<AppState>,
+    SessionId(id): SessionId,
+    Json(tags): Json<Vec<String>>,
+) -> ApiResult<impl IntoResponse> {
+    let mut tags: Vec<String> = tags.iter().map(|t| t.trim().to_string()).collect();
+    if tags.iter().any(|t| t.is_empty() || t.len() > 64) {
+        return Err(ForgeError::validation(
+            "tags",
+            "tags must be 1-64 characters",
         ));
     }
+    tags.sort();
+    tags.dedup();
+    let saved = tags.clone();
+    mgr.with_store(move |store| store.set_tags(id, &saved))
+        .await?;
+    Ok(Json(tags))
+}
 
-    let meta = mgr
-        .get(id)
-        .await
-        .map_err(|_| (StatusCode::NOT_FOUND, "Session not found".to_string()))?;
+#[derive(Deserialize)]
+struct EventsQuery {
+    limit: Option<usize>,
+}
 
-    match crate::iterm::open_in_iterm(id, &meta.working_dir) {
-        Ok(()) => Ok(StatusCode::OK),
-        Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
+/// The session's most recent events (prompts, approvals, stop and exit),
+/// oldest first.
+async fn list_events(
+    State(mgr): State<AppState>,
+    SessionId(id): SessionId,
+    Query(query): Query<EventsQuery>,
+) -> ApiResult<impl IntoResponse> {
+    let limit = query.limit.unwrap_or(100);
+    if limit == 0 || limit > 1000 {
+        return Err(ForgeError::validation("limit", "must be 1-1000"));
     }
+    Ok(Json(
+        mgr.with_store(move |store| store.events(id, limit)).await?,
+    ))
+}
+
+async fn list_transitions(
+    State(mgr): State<AppState>,
+    SessionId(id): SessionId,
+) -> ApiResult<impl IntoResponse> {
+    Ok(Json(
+        mgr.with_store(move |store| store.transitions(id)).await?,
+    ))
+}
+
+async fn get_resources(
+    State(mgr): State<AppState>,
+    SessionId(id): SessionId,
+) -> ApiResult<impl IntoResponse> {
+    Ok(Json(mgr.resources(id).await?))
+}
+
+async fn get_prompt(
+    State(mgr): State<AppState>,
+    SessionId(id): SessionId,
+) -> ApiResult<impl IntoResponse> {
+    Ok(Json(mgr.current_prompt(id).await?))
+}
+
+#[derive(Deserialize)]
+struct AnswerPromptRequest {
+    /// Index into the prompt's `options`
+    option: usize,
+    /// `id` of the prompt the answer is for; rejected if another is pending
+    #[serde(default)]
+    prompt_id: Option<String>,
+}
+
+async fn answer_prompt(
+    State(mgr): State<AppState>,
+    SessionId(id): SessionId,
+    Json(req): Json<AnswerPromptRequest>,
+) -> ApiResult<StatusCode> {
+    mgr.answer_prompt(id, req.option, req.prompt_id.as_deref())
+        .await?;
+    Ok(StatusCode::OK)
 }
@@ -0,0 +1,139 @@
+use std::os::unix::process::CommandExt;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+
+use crate::config::Config;
+use crate::server::runtime::RuntimeState;
+use crate::session::manager::Api;
+
+const START_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Run `forge <args>` detached from this terminal, with its output appended
+/// to `server.log`, and wait until it answers. Returns its pid.
+pub async fn start_detached(config: &Config, args: &[String]) -> Result<u32> {
+    if let Some(state) = RuntimeState::read() {
+        anyhow::bail!(
+            "A server is already running (pid {}) at {}",
+            state.pid,
+            state.url
+        );
+    }
+
+    let log_path = Config::server_log_path();
+    std::fs::create_dir_all(Config::data_dir())?;
+    let log = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&log_path)
+        .with_context(|| format!("Failed to open {}", log_path.display()))?;
+    let mut command = std::process::Command::new(std::env::current_exe()?);
+    command
+        .args(args)
+        .stdin(std::process::Stdio::null())
+        .stdout(log.try_clone()?)
+        .stderr(log);
+    // A session of its own: no controlling terminal, so closing this one
+    // or pressing Ctrl-C doesn't reach it
+    unsafe {
+        command.pre_exec(|| {
+            libc::setsid();
+            Ok(())
+        });
+    }
+    let mut child = command.spawn().context("Failed to start the server")?;
+
+    let deadline = tokio::time::Instant::now() + START_TIMEOUT;
+    while tokio::time::Instant::now() < deadline {
+        let exited = child.try_wait()?;
+        // Another command may have started one at the same time
+        if Api::discover(config)?.healthy().await {
+            return Ok(child.id());
+        }
+        if let Some(status) = exited {
+            anyhow::bail!(
+                "The server exited during startup ({status}); see {}",
+                log_path.display()
+            );
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    anyhow::bail!(
+        "The server didn't answer within {}s; see {}",
+        START_TIMEOUT.as_secs(),
+        log_path.display()
+    )
+}
+
+/// `forge serve --daemon`: `args` are the `serve` arguments without
+/// `--daemon`.
+pub async fn daemonize(config: &Config, args: &[String]) -> Result<()> {
+    let pid = start_detached(config, args).await?;
+    println!(
+        "Server started (pid {pid}), logging to {}",
+        Config::server_log_path().display()
+    );
+    Ok(())
+}
+
+/// `forge stop-server`
+pub async fn stop(config: &Config) -> Result<()> {
+    let Some(state) = RuntimeState::read() else {
+        println!("No server is running");
+        return Ok(());
+    };
+    println!("Stopping server (pid {})", state.pid);
+    terminate(config, &state).await?;
+    println!("Server stopped");
+    Ok(())
+}
+
+/// `forge restart-server`: stop the running server and start it again with
+/// the same arguments, through the service manager if it started it.
+pub async fn restart(config: &Config) -> Result<()> {
+    let mut args = vec!["serve".to_string()];
+    match RuntimeState::read() {
+        Some(RuntimeState {
+            service: Some(service),
+            ..
+        }) => return crate::cli::service::restart(&service),
+        Some(state) => {
+            println!("Stopping server (pid {})", state.pid);
+            terminate(config, &state).await?;
+            args.extend(state.args);
+        }
+        None => println!("No server is running"),
+    }
+    daemonize(config, &args).await
+}
+
+/// Send SIGTERM and wait for the process to go away. Shutdown may take a
+/// while when the policy waits for sessions.
+async fn terminate(config: &Config, state: &RuntimeState) -> Result<()> {
+    // Checked again right before signalling, so an unrelated process that
+    // took over the pid in the meantime isn't killed
+    if !state.is_alive() {
+        return Ok(());
+    }
+    if unsafe { libc::kill(state.pid as libc::pid_t, libc::SIGTERM) } != 0 {
+        anyhow::bail!(
+            "Failed to signal the server (pid {}): {}",
+            state.pid,
+            std::io::Error::last_os_error()
+        );
+    }
+
+    let timeout = Duration::from_secs(config.shutdown_timeout_secs * 2 + 10);
+    let deadline = tokio::time::Instant::now() + timeout;
+    while state.process_running() {
+        if tokio::time::Instant::now() >= deadline {
+            anyhow::bail!(
+                "The server (pid {}) didn't exit within {}s",
+                state.pid,
+                timeout.as_secs()
+            );
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    Ok(())
+}
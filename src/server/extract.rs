@@ -0,0 +1,78 @@
+use std::sync::Arc;
+
+use axum::extract::{FromRequest, FromRequestParts, Request};
+use axum::http::request::Parts;
+use axum::response::{IntoResponse, Response};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use uuid::Uuid;
+
+use crate::error::ForgeError;
+use crate::session::manager::SessionManager;
+
+/// Session id taken from the `{id}` path segment, which may be a full UUID,
+/// a unique UUID prefix or a unique session name.
+pub struct SessionId(pub Uuid);
+
+impl FromRequestParts<Arc<SessionManager>> for SessionId {
+    type Rejection = Response;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        mgr: &Arc<SessionManager>,
+    ) -> Result<Self, Self::Rejection> {
+        let axum::extract::Path(query) =
+            axum::extract::Path::<String>::from_request_parts(parts, mgr)
+                .await
+                .map_err(|e| ForgeError::validation("id", e.body_text()).into_response())?;
+
+        mgr.resolve_id(&query)
+            .await
+            .map(SessionId)
+            .map_err(IntoResponse::into_response)
+    }
+}
+
+/// `axum::Json`, rejecting a body it can't parse with a validation error
+/// instead of a plain-text response.
+pub struct Json<T>(pub T);
+
+impl<T, S> FromRequest<S> for Json<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = ForgeError;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        axum::Json::<T>::from_request(req, state)
+            .await
+            .map(|axum::Json(value)| Json(value))
+            .map_err(|e| ForgeError::validation("body", e.body_text()))
+    }
+}
+
+impl<T: Serialize> IntoResponse for Json<T> {
+    fn into_response(self) -> Response {
+        axum::Json(self.0).into_response()
+    }
+}
+
+/// `axum::extract::Query`, rejecting a query string it can't parse with a
+/// validation error.
+pub struct Query<T>(pub T);
+
+impl<T, S> FromRequestParts<S> for Query<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = ForgeError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        axum::extract::Query::<T>::from_request_parts(parts, state)
+            .await
+            .map(|axum::extract::Query(value)| Query(value))
+            .map_err(|e| ForgeError::validation("query", e.body_text()))
+    }
+}
@@ -1,19 +1,33 @@
 pub mod api;
+pub mod daemon;
+pub mod extract;
+pub mod runtime;
 pub mod sse;
 pub mod templates;
 
 use std::sync::Arc;
+use std::time::Duration;
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use axum::Router;
 use axum::extract::Path;
-use axum::http::{StatusCode, header};
-use axum::response::IntoResponse;
+use axum::http::{HeaderMap, StatusCode, Uri, header};
+use axum::response::{IntoResponse, Redirect};
+use axum_server::tls_rustls::RustlsConfig;
 use rust_embed::Embed;
+use tokio::sync::watch;
 use tower_http::cors::CorsLayer;
+use tower_http::request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer};
+use tower_http::trace::TraceLayer;
+use uuid::Uuid;
 
 use crate::config::{Config, resolve_bind_address};
 use crate::session::manager::SessionManager;
+use crate::session::model::SessionStatus;
+use crate::session::persist::{self, MetaError};
+use crate::store::SqliteStore;
+use crate::tls;
+use runtime::RuntimeState;
 
 #[derive(Embed)]
 #[folder = "static/"]
@@ -34,17 +48,53 @@ async fn serve_static(Path(path): Path<String>) -> impl IntoResponse {
     }
 }
 
+fn request_span(request: &axum::http::Request<axum::body::Body>) -> tracing::Span {
+    let request_id = request
+        .headers()
+        .get("x-request-id")
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default();
+    tracing::info_span!(
+        "request",
+        method = %request.method(),
+        path = %request.uri().path(),
+        request_id,
+    )
+}
+
+/// How long open connections (log follows, SSE) get to finish on shutdown.
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(2);
+
 pub async fn start(config: Config) -> Result<()> {
+    // Before anything else, as the state file and socket must go there
+    Config::private_runtime_dir()?;
+    if let Some(state) = RuntimeState::read() {
+        anyhow::bail!(
+            "A server is already running (pid {}) at {}",
+            state.pid,
+            state.url
+        );
+    }
+
     let bind = resolve_bind_address(&config.bind);
     let addr = format!("{bind}:{}", config.port);
-    let manager = SessionManager::new(config.clone());
+    let store = SqliteStore::open(&Config::store_path(), &Config::sessions_dir())?;
+    let manager = SessionManager::new(config.clone(), Arc::new(store));
 
     // Restore sessions from disk
     restore_sessions(&manager).await;
 
+    // Build the search index from saved logs in the background
+    let search = manager.search.clone();
+    let store = manager.store.clone();
+    tokio::task::spawn_blocking(move || search.index_saved_sessions(store.as_ref()));
+
     let state = Arc::new(manager);
+    if config.resource_sample_secs > 0 {
+        tokio::spawn(state.clone().sample_resources());
+    }
 
-    let app = Router::new()
+    let mut app = Router::new()
         // API routes
         .merge(api::routes())
         // SSE routes
@@ -52,67 +102,283 @@ pub async fn start(config: Config) -> Result<()> {
         // Template/page routes
         .merge(templates::routes())
         // Static files (embedded in binary)
-        .route("/static/{*path}", axum::routing::get(serve_static))
+        .route("/static/{*path}", axum::routing::get(serve_static));
+    if config.metrics {
+        app = api::with_metrics(app, state.clone());
+    }
+    let app = app
         // CORS: deny all cross-origin requests (same-origin passes through)
         .layer(CorsLayer::new())
-        .with_state(state);
+        .layer(TraceLayer::new_for_http().make_span_with(request_span))
+        // Requests get an X-Request-Id (unless the client sent one), which
+        // is echoed in the response and recorded on the request's span
+        .layer(PropagateRequestIdLayer::x_request_id())
+        .layer(SetRequestIdLayer::x_request_id(MakeRequestUuid))
+        .with_state(state.clone());
+
+    // Every listener stops accepting connections once this flips
+    let (shutdown_tx, shutdown_rx) = watch::channel(false);
+
+    let listener = tokio::net::TcpListener::bind(&addr)
+        .await
+        .with_context(|| format!("Failed to listen on {addr}"))?;
+    let listen = listener.local_addr()?;
+    let control_socket = Config::control_socket();
+    let (control_socket, control_task) = match bind_control_socket(&control_socket) {
+        Ok(socket) => {
+            tracing::info!("Control socket at {}", control_socket.display());
+            let task = tokio::spawn(serve_control_socket(
+                socket,
+                app.clone(),
+                shutdown_rx.clone(),
+            ));
+            (Some(control_socket), Some(task))
+        }
+        Err(e) => {
+            tracing::warn!("Control socket disabled: {e:#}");
+            (None, None)
+        }
+    };
+
+    let tls_files = config.tls_files();
+    let scheme = if tls_files.is_some() { "https" } else { "http" };
+    let redirect = match (&tls_files, config.tls_redirect_port) {
+        (Some(_), Some(port)) => format!("{bind}:{port}").parse().ok(),
+        _ => None,
+    };
+    let runtime = RuntimeState {
+        pid: std::process::id(),
+        start_time: crate::session::resources::start_time(std::process::id()),
+        version: env!("CARGO_PKG_VERSION").into(),
+        started_at: chrono::Utc::now(),
+        url: format!("{scheme}://{listen}"),
+        listen,
+        redirect,
+        control_socket: control_socket.clone(),
+        args: std::env::args().skip(2).collect(),
+        service: std::env::var("LINEFORGE_SERVICE").ok(),
+    }
+    .write()?;
+
+    let mut server = match tls_files {
+        None => {
+            tracing::info!(
+                "Lineforge v{} listening on http://{listen}",
+                env!("CARGO_PKG_VERSION")
+            );
+            let shutdown = stopped(shutdown_rx.clone());
+            tokio::spawn(async move {
+                axum::serve(listener, app)
+                    .with_graceful_shutdown(shutdown)
+                    .await?;
+                Ok(())
+            })
+        }
+        Some((cert, key)) => {
+            if (cert.clone(), key.clone()) == tls::self_signed_paths() {
+                let mut hosts = vec!["localhost".to_string(), "127.0.0.1".to_string()];
+                if !hosts.contains(&bind) {
+                    hosts.push(bind.clone());
+                }
+                tls::ensure_self_signed(&hosts)?;
+            }
+            let tls_config = RustlsConfig::from_config(Arc::new(tls::server_config(&cert, &key)?));
+            if let Some(port) = config.tls_redirect_port {
+                tokio::spawn(redirect_to_https(
+                    format!("{bind}:{port}"),
+                    config.port,
+                    shutdown_rx.clone(),
+                ));
+            }
+            tracing::info!(
+                "Lineforge v{} listening on https://{listen}",
+                env!("CARGO_PKG_VERSION")
+            );
+            let handle = axum_server::Handle::new();
+            let mut shutdown = shutdown_rx.clone();
+            tokio::spawn({
+                let handle = handle.clone();
+                async move {
+                    let _ = shutdown.wait_for(|s| *s).await;
+                    handle.graceful_shutdown(Some(DRAIN_TIMEOUT));
+                }
+            });
+            let listener = listener.into_std()?;
+            tokio::spawn(async move {
+                axum_server::from_tcp_rustls(listener, tls_config)
+                    .handle(handle)
+                    .serve(app.into_make_service())
+                    .await?;
+                Ok(())
+            })
+        }
+    };
 
-    let listener = tokio::net::TcpListener::bind(&addr).await?;
-    tracing::info!(
-        "Lineforge v{} listening on http://{addr}",
-        env!("CARGO_PKG_VERSION")
-    );
+    tokio::select! {
+        result = &mut server => return result?,
+        _ = shutdown_signal() => {}
+    }
+
+    tracing::info!("Shutting down");
+    let _ = shutdown_tx.send(true);
+    // Streams such as log follows never end on their own
+    if tokio::time::timeout(DRAIN_TIMEOUT, &mut server)
+        .await
+        .is_err()
+    {
+        server.abort();
+    }
+    if let Some(task) = control_task {
+        let _ = tokio::time::timeout(DRAIN_TIMEOUT, task).await;
+    }
+    if let Some(path) = &control_socket {
+        let _ = std::fs::remove_file(path);
+    }
 
-    axum::serve(listener, app).await?;
+    state.shutdown().await;
+    drop(runtime);
+    tracing::info!("Server stopped");
     Ok(())
 }
 
-async fn restore_sessions(_manager: &SessionManager) {
-    let sessions_dir = Config::sessions_dir();
-    if !sessions_dir.exists() {
-        return;
+/// Resolves on SIGTERM or Ctrl-C.
+async fn shutdown_signal() {
+    use tokio::signal::unix::{SignalKind, signal};
+
+    let mut term = match signal(SignalKind::terminate()) {
+        Ok(term) => term,
+        Err(e) => {
+            tracing::warn!("Failed to listen for SIGTERM: {e}");
+            let _ = tokio::signal::ctrl_c().await;
+            return;
+        }
+    };
+    tokio::select! {
+        _ = term.recv() => {}
+        _ = tokio::signal::ctrl_c() => {}
+    }
+}
+
+/// Resolves once shutdown has begun.
+async fn stopped(mut shutdown: watch::Receiver<bool>) {
+    let _ = shutdown.wait_for(|s| *s).await;
+}
+
+/// Serve the same routes on the local control socket, readable only by the
+/// user. Local CLI commands prefer it over TCP.
+async fn serve_control_socket(
+    listener: tokio::net::UnixListener,
+    app: Router,
+    shutdown: watch::Receiver<bool>,
+) {
+    if let Err(e) = axum::serve(listener, app)
+        .with_graceful_shutdown(stopped(shutdown))
+        .await
+    {
+        tracing::warn!("Control socket stopped: {e}");
+    }
+}
+
+fn bind_control_socket(path: &std::path::Path) -> Result<tokio::net::UnixListener> {
+    use std::os::unix::fs::PermissionsExt;
+
+    Config::private_runtime_dir()?;
+    if std::os::unix::net::UnixStream::connect(path).is_ok() {
+        anyhow::bail!("another server is listening on {}", path.display());
     }
+    // Left behind by a server that didn't shut down cleanly
+    let _ = std::fs::remove_file(path);
+    let listener = tokio::net::UnixListener::bind(path)
+        .with_context(|| format!("Failed to bind {}", path.display()))?;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+    Ok(listener)
+}
+
+/// Answer plain HTTP on `addr` with a redirect to the same path over HTTPS.
+async fn redirect_to_https(addr: String, https_port: u16, shutdown: watch::Receiver<bool>) {
+    let app = Router::new().fallback(move |headers: HeaderMap, uri: Uri| async move {
+        let host = headers
+            .get(header::HOST)
+            .and_then(|h| h.to_str().ok())
+            .unwrap_or("localhost");
+        // Drop the port, keeping IPv6 brackets
+        let host = match host.rfind(':') {
+            Some(i) if !host[i..].contains(']') => &host[..i],
+            _ => host,
+        };
+        let path = uri.path_and_query().map(|p| p.as_str()).unwrap_or("/");
+        Redirect::permanent(&format!("https://{host}:{https_port}{path}"))
+    });
+
+    match tokio::net::TcpListener::bind(&addr).await {
+        Ok(listener) => {
+            tracing::info!("Redirecting http://{addr} to HTTPS");
+            if let Err(e) = axum::serve(listener, app)
+                .with_graceful_shutdown(stopped(shutdown))
+                .await
+            {
+                tracing::warn!("HTTP redirect on {addr} stopped: {e}");
+            }
+        }
+        Err(e) => tracing::warn!("Failed to bind HTTP redirect on {addr}: {e}"),
+    }
+}
 
-    let entries = match std::fs::read_dir(&sessions_dir) {
-        Ok(e) => e,
-        Err(_) => return,
+/// Bring the store and the session directories back in line after the last
+/// run: sessions that were running died with the server and are marked
+/// stopped, and a missing or corrupt `meta.json` is rewritten from the store,
+/// or rebuilt from the directory if the store doesn't know the session.
+async fn restore_sessions(manager: &SessionManager) {
+    let Ok(entries) = std::fs::read_dir(Config::sessions_dir()) else {
+        return;
     };
 
     for entry in entries.flatten() {
-        let meta_path = entry.path().join("meta.json");
-        if !meta_path.exists() {
+        let dir = entry.path();
+        let Some(id) = dir
+            .file_name()
+            .and_then(|n| n.to_str())
+            .and_then(|n| n.parse::<Uuid>().ok())
+        else {
             continue;
-        }
+        };
+        let stored = match manager.store.session(id) {
+            Ok(stored) => stored,
+            Err(e) => {
+                tracing::warn!("Failed to read session {id} from the store: {e:#}");
+                continue;
+            }
+        };
 
-        match std::fs::read_to_string(&meta_path) {
-            Ok(content) => {
-                match serde_json::from_str::<crate::session::model::SessionMeta>(&content) {
-                    Ok(mut meta) => {
-                        // Mark previously running sessions as stopped (they died with the server)
-                        if meta.status == crate::session::model::SessionStatus::Running {
-                            meta.status = crate::session::model::SessionStatus::Stopped;
-                            meta.pid = None;
-                            meta.updated_at = chrono::Utc::now();
-                            if let Ok(json) = serde_json::to_string_pretty(&meta) {
-                                let _ = std::fs::write(&meta_path, json);
-                            }
-                        }
-                        tracing::debug!("Found previous session: {} ({})", meta.id, meta.name);
-                    }
-                    Err(e) => {
-                        tracing::warn!(
-                            "Failed to parse session meta at {}: {e}",
-                            meta_path.display()
-                        );
-                    }
+        let (mut meta, mut dirty) = match (persist::load(&dir), stored) {
+            (Ok(meta), None) => (meta, true),
+            (Ok(_), Some(stored)) => (stored, false),
+            (Err(e @ (MetaError::Missing | MetaError::Corrupt(_))), stored) => {
+                // Keep the broken file around for inspection
+                if matches!(e, MetaError::Corrupt(_)) {
+                    let _ = std::fs::rename(dir.join("meta.json"), dir.join("meta.json.corrupt"));
                 }
+                let Some(meta) = stored.or_else(|| persist::recover(&dir)) else {
+                    continue;
+                };
+                tracing::warn!("Session {id}: {e}, rewriting it");
+                (meta, true)
             }
-            Err(e) => {
-                tracing::warn!(
-                    "Failed to read session meta at {}: {e}",
-                    meta_path.display()
-                );
+            (Err(e), _) => {
+                tracing::warn!("Skipping session {id}: {e}");
+                continue;
             }
+        };
+
+        if meta.status == SessionStatus::Running {
+            meta.status = SessionStatus::Stopped;
+            meta.pid = None;
+            meta.updated_at = chrono::Utc::now();
+            dirty = true;
+            tracing::debug!("Marked previous session stopped: {id} ({})", meta.name);
+        }
+        if dirty && let Err(e) = persist::persist(manager.store.as_ref(), &meta) {
+            tracing::warn!("Failed to save session {id}: {e:#}");
         }
     }
 }
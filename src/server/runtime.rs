@@ -0,0 +1,145 @@
+use std::io::Write;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::config::Config;
+use crate::session::resources;
+
+/// What a running server records about itself, so CLI commands can find it
+/// even when it was started with `--port` or `--bind`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RuntimeState {
+    pub pid: u32,
+    /// When `pid` started, in clock ticks since boot (Linux), so a process
+    /// that reused the pid isn't taken for the server
+    #[serde(default)]
+    pub start_time: Option<u64>,
+    pub version: String,
+    pub started_at: DateTime<Utc>,
+    /// Base URL of the TCP listener, e.g. `https://127.0.0.1:42067`
+    pub url: String,
+    pub listen: SocketAddr,
+    /// Plain HTTP listener redirecting to `url`
+    pub redirect: Option<SocketAddr>,
+    pub control_socket: Option<PathBuf>,
+    /// Arguments after `forge serve`, for `forge restart-server`
+    #[serde(default)]
+    pub args: Vec<String>,
+    /// Set when started by the unit `forge service install` generates
+    /// (`systemd` or `launchd`)
+    #[serde(default)]
+    pub service: Option<String>,
+}
+
+impl RuntimeState {
+    /// The state of the server that is running now, if any. A file left
+    /// behind by a server that died is ignored, even once its pid is reused.
+    pub fn read() -> Option<Self> {
+        // Nothing in a runtime dir another user could write to is trusted
+        Config::private_runtime_dir().ok()?;
+        let json = std::fs::read(Config::runtime_state_path()).ok()?;
+        let state: Self = serde_json::from_slice(&json).ok()?;
+        state.is_alive().then_some(state)
+    }
+
+    /// Whether the server that wrote this state is still running: its
+    /// process, identified by start time where `/proc` has it, or else a
+    /// process with its pid that also still listens where it did.
+    pub fn is_alive(&self) -> bool {
+        match self.process() {
+            Liveness::Gone => false,
+            Liveness::Same => true,
+            Liveness::Unknown => self.listening(),
+        }
+    }
+
+    /// Whether `pid` is still the server's process. Unlike `is_alive`, this
+    /// stays true while it shuts down after closing its listeners.
+    pub fn process_running(&self) -> bool {
+        match self.process() {
+            Liveness::Gone => false,
+            Liveness::Same => true,
+            // Its listeners were checked before it was signalled
+            Liveness::Unknown => true,
+        }
+    }
+
+    fn process(&self) -> Liveness {
+        // Signal 0 only checks that the process exists
+        let rc = unsafe { libc::kill(self.pid as libc::pid_t, 0) };
+        if rc != 0 && std::io::Error::last_os_error().raw_os_error() != Some(libc::EPERM) {
+            return Liveness::Gone;
+        }
+        match (self.start_time, resources::start_time(self.pid)) {
+            (Some(recorded), Some(current)) if recorded == current => Liveness::Same,
+            (Some(_), Some(_)) => Liveness::Gone,
+            _ => Liveness::Unknown,
+        }
+    }
+
+    /// Whether something accepts connections on the control socket, or the
+    /// TCP listener when there is none.
+    fn listening(&self) -> bool {
+        if let Some(socket) = &self.control_socket {
+            return std::os::unix::net::UnixStream::connect(socket).is_ok();
+        }
+        let mut addr = self.listen;
+        if addr.ip().is_unspecified() {
+            addr.set_ip(match addr {
+                SocketAddr::V4(_) => std::net::Ipv4Addr::LOCALHOST.into(),
+                SocketAddr::V6(_) => std::net::Ipv6Addr::LOCALHOST.into(),
+            });
+        }
+        std::net::TcpStream::connect_timeout(&addr, Duration::from_millis(500)).is_ok()
+    }
+
+    /// Write the state file, replacing it atomically, and the pid file. Removed again when the
+    /// returned guard is dropped.
+    pub fn write(&self) -> Result<RuntimeGuard> {
+        Config::private_runtime_dir()?;
+        let path = Config::runtime_state_path();
+        let tmp = path.with_extension("json.tmp");
+        let write = || -> std::io::Result<()> {
+            let mut file = std::fs::File::create(&tmp)?;
+            file.write_all(&serde_json::to_vec_pretty(self)?)?;
+            file.sync_all()?;
+            std::fs::rename(&tmp, &path)
+        };
+        write().with_context(|| format!("Failed to write {}", path.display()))?;
+        std::fs::write(Config::pid_file(), format!("{}\n", self.pid))?;
+        Ok(RuntimeGuard { pid: self.pid })
+    }
+}
+
+enum Liveness {
+    Gone,
+    Same,
+    /// A process with the pid exists, but can't be told apart from one that
+    /// reused it
+    Unknown,
+}
+
+/// Removes the state and pid files on shutdown, unless another server
+/// replaced them.
+pub struct RuntimeGuard {
+    pid: u32,
+}
+
+impl Drop for RuntimeGuard {
+    fn drop(&mut self) {
+        let path = Config::runtime_state_path();
+        let ours = std::fs::read(&path)
+            .ok()
+            .and_then(|json| serde_json::from_slice::<RuntimeState>(&json).ok())
+            .is_some_and(|state| state.pid == self.pid);
+        if ours {
+            let _ = std::fs::remove_file(path);
+            let _ = std::fs::remove_file(Config::pid_file());
+        }
+    }
+}
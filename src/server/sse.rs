@@ -2,16 +2,20 @@ use std::convert::Infallible;
 use std::sync::Arc;
 
 use axum::Router;
-use axum::extract::{Path, State};
-use axum::http::StatusCode;
+use axum::extract::State;
+use axum::http::HeaderMap;
 use axum::response::IntoResponse;
 use axum::response::sse::{Event, KeepAlive, Sse};
 use axum::routing::get;
 use tokio::sync::mpsc;
-use tokio_stream::StreamExt;
 use tokio_stream::wrappers::ReceiverStream;
-use uuid::Uuid;
+use tracing::Instrument;
 
+use crate::error::ForgeError;
+use crate::metrics::Transport;
+use crate::server::extract::{Query, SessionId};
+use crate::session::feed::{CHUNK_BYTES, FeedItem, coalesce};
+use crate::session::log::{LogEntry, LogQuery};
 use crate::session::manager::SessionManager;
 
 type AppState = Arc<SessionManager>;
@@ -20,25 +24,48 @@ pub fn routes() -> Router<AppState> {
     Router::new().route("/api/sessions/{id}/logs", get(stream_logs))
 }
 
-async fn stream_logs(State(mgr): State<AppState>, Path(id): Path<Uuid>) -> impl IntoResponse {
-    let snapshot = match mgr.get_log_snapshot(id).await {
-        Ok(s) => s,
-        Err(_) => return Err((StatusCode::NOT_FOUND, "Session not found")),
-    };
+/// Stream a session's output. A reconnecting client sends `Last-Event-ID`
+/// (or `?after_seq=`) and gets only the entries it missed; otherwise the
+/// buffered history is sent first, limited by `?since=&tail=`.
+async fn stream_logs(
+    State(mgr): State<AppState>,
+    SessionId(id): SessionId,
+    headers: HeaderMap,
+    Query(range): Query<LogQuery>,
+) -> Result<impl IntoResponse, ForgeError> {
+    let resume = headers
+        .get("last-event-id")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.trim().parse::<u64>().ok())
+        .or(range.after_seq);
 
-    let log_rx = match mgr.subscribe_logs(id).await {
-        Ok(r) => r,
-        Err(_) => return Err((StatusCode::NOT_FOUND, "Session not found")),
-    };
+    // The backlog and the live feed come from one request, so nothing falls
+    // in between
+    let (slice, mut feed) = mgr.follow_logs(id, resume.unwrap_or(0)).await?;
+    let mut size_rx = mgr.subscribe_size(id).await?;
+    let mut prompt_rx = mgr.subscribe_prompt(id).await?;
 
-    let mut size_rx = match mgr.subscribe_size(id).await {
-        Ok(r) => r,
-        Err(_) => return Err((StatusCode::NOT_FOUND, "Session not found")),
+    // An id past the end means the client saw a different log; start over
+    let reset = resume.is_some_and(|seq| seq > slice.last_seq);
+    let (backlog, missed) = match resume {
+        Some(_) if !reset => (slice.entries, slice.missed),
+        Some(_) => {
+            let (slice, from_start) = mgr.follow_logs(id, 0).await?;
+            feed = from_start;
+            (slice.entries, 0)
+        }
+        None => (range.select(slice.entries)?, 0),
     };
+    // History cut by since/tail still counts as delivered
+    let delivered = slice.last_seq.max(backlog.last().map_or(0, |e| e.seq));
 
-    let (tx, rx) = mpsc::channel::<Result<Event, Infallible>>(256);
+    // Kept small so a slow client backs up into its byte-bounded feed
+    let (tx, rx) = mpsc::channel::<Result<Event, Infallible>>(16);
 
-    tokio::spawn(async move {
+    let client = mgr.metrics.client_connected(Transport::Sse);
+    let span = tracing::info_span!("follow", session = %id);
+    let forward = async move {
+        let _client = client;
         // Send initial resize event with current PTY size
         let (rows, cols) = *size_rx.borrow_and_update();
         let resize_data = serde_json::json!({ "rows": rows, "cols": cols });
@@ -48,31 +75,59 @@ async fn stream_logs(State(mgr): State<AppState>, Path(id): Path<Uuid>) -> impl
                 .data(resize_data.to_string())))
             .await;
 
-        // Send log snapshot
-        for entry in snapshot {
+        // Send the pending permission prompt, if any
+        let prompt = prompt_rx.borrow_and_update().clone();
+        let _ = tx
+            .send(Ok(Event::default()
+                .event("prompt")
+                .data(serde_json::to_string(&prompt).unwrap_or_default())))
+            .await;
+
+        if reset {
             let _ = tx
-                .send(Ok(Event::default()
-                    .event("log")
-                    .data(serde_json::to_string(&entry).unwrap_or_default())))
+                .send(Ok(Event::default().event("reset").data("Log restarted")))
                 .await;
         }
+        if missed > 0 {
+            let _ = tx.send(Ok(gap_event(missed))).await;
+        }
 
-        // Merge live log + resize events
-        let mut log_stream = tokio_stream::wrappers::BroadcastStream::new(log_rx);
+        // Send buffered history, or just what the client missed
+        for entry in coalesce(backlog, CHUNK_BYTES) {
+            let _ = tx.send(Ok(log_event(&entry))).await;
+        }
+        let mut last_seq = delivered;
 
+        // Merge live log + resize events
         loop {
             tokio::select! {
-                Some(result) = log_stream.next() => {
-                    let event = match result {
-                        Ok(entry) => Event::default()
-                            .event("log")
-                            .data(serde_json::to_string(&entry).unwrap_or_default()),
-                        Err(_lagged) => Event::default()
-                            .event("gap")
-                            .data("Missed messages, refresh for full log"),
+                Some(item) = feed.next() => {
+                    let entries = match item {
+                        FeedItem::Output(entry) => vec![entry],
+                        // Fell too far behind; pick up from the last entry sent,
+                        // redrawing from the buffer if output was lost meanwhile
+                        FeedItem::Resync => {
+                            mgr.metrics.client_resynced(Transport::Sse);
+                            let Ok((slice, next)) = mgr.follow_logs(id, last_seq).await else {
+                                break;
+                            };
+                            feed = next;
+                            if slice.missed > 0 {
+                                let event = Event::default()
+                                    .event("reset")
+                                    .data("Client fell behind; redrawing");
+                                if tx.send(Ok(event)).await.is_err() {
+                                    break;
+                                }
+                            }
+                            coalesce(slice.entries, CHUNK_BYTES)
+                        }
                     };
-                    if tx.send(Ok(event)).await.is_err() {
-                        break;
+                    for entry in entries {
+                        last_seq = entry.seq;
+                        if tx.send(Ok(log_event(&entry))).await.is_err() {
+                            return;
+                        }
                     }
                 }
                 Ok(()) = size_rx.changed() => {
@@ -85,11 +140,35 @@ async fn stream_logs(State(mgr): State<AppState>, Path(id): Path<Uuid>) -> impl
                         break;
                     }
                 }
+                Ok(()) = prompt_rx.changed() => {
+                    let prompt = prompt_rx.borrow_and_update().clone();
+                    let event = Event::default()
+                        .event("prompt")
+                        .data(serde_json::to_string(&prompt).unwrap_or_default());
+                    if tx.send(Ok(event)).await.is_err() {
+                        break;
+                    }
+                }
                 else => break,
             }
         }
-    });
+    };
+    tokio::spawn(forward.instrument(span));
 
     let stream = ReceiverStream::new(rx);
     Ok(Sse::new(stream).keep_alive(KeepAlive::default()))
 }
+
+/// A log entry, with its sequence number as the SSE id for resuming.
+fn log_event(entry: &LogEntry) -> Event {
+    Event::default()
+        .event("log")
+        .id(entry.seq.to_string())
+        .data(serde_json::to_string(entry).unwrap_or_default())
+}
+
+fn gap_event(missed: u64) -> Event {
+    Event::default().event("gap").data(format!(
+        "{missed} earlier entries were evicted from the buffer"
+    ))
+}
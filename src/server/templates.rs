@@ -1,13 +1,16 @@
+use std::collections::HashSet;
 use std::sync::Arc;
 
 use askama::Template;
 use axum::Router;
-use axum::extract::{Path, State};
+use axum::extract::{Path, Query, State};
 use axum::http::StatusCode;
 use axum::response::IntoResponse;
 use axum::routing::get;
 use uuid::Uuid;
 
+use crate::search::{IndexedLine, SearchHit, SearchQuery};
+use crate::server::extract::SessionId;
 use crate::session::manager::SessionManager;
 use crate::session::model::SessionMeta;
 
@@ -18,17 +21,31 @@ pub fn routes() -> Router<AppState> {
         .route("/", get(index_page))
         .route("/sessions/{id}", get(session_page))
         .route("/new", get(new_page))
+        .route("/diagnostics", get(diagnostics_page))
+        .route("/search", get(search_page))
+        .route("/sessions/{id}/log", get(log_page))
 }
 
 #[derive(Template)]
 #[template(path = "index.html")]
 struct IndexTemplate {
     sessions: Vec<SessionMeta>,
+    awaiting_approval: HashSet<Uuid>,
+}
+
+impl IndexTemplate {
+    fn needs_approval(&self, id: &Uuid) -> bool {
+        self.awaiting_approval.contains(id)
+    }
 }
 
 async fn index_page(State(mgr): State<AppState>) -> impl IntoResponse {
     let sessions = mgr.list().await;
-    let template = IndexTemplate { sessions };
+    let awaiting_approval = mgr.awaiting_approval().await;
+    let template = IndexTemplate {
+        sessions,
+        awaiting_approval,
+    };
     HtmlTemplate(template)
 }
 
@@ -38,7 +55,7 @@ struct SessionTemplate {
     session: SessionMeta,
 }
 
-async fn session_page(State(mgr): State<AppState>, Path(id): Path<Uuid>) -> impl IntoResponse {
+async fn session_page(State(mgr): State<AppState>, SessionId(id): SessionId) -> impl IntoResponse {
     match mgr.get(id).await {
         Ok(session) => {
             let template = SessionTemplate { session };
@@ -56,6 +73,79 @@ async fn new_page() -> impl IntoResponse {
     HtmlTemplate(NewTemplate)
 }
 
+#[derive(Template)]
+#[template(path = "diagnostics.html")]
+struct DiagnosticsTemplate {
+    report: crate::doctor::Report,
+}
+
+async fn diagnostics_page(State(mgr): State<AppState>) -> impl IntoResponse {
+    let report = crate::doctor::run(
+        Ok(mgr.config.clone()),
+        &crate::config::Config::config_path(),
+        crate::doctor::Origin::Server,
+    )
+    .await;
+    HtmlTemplate(DiagnosticsTemplate { report })
+}
+
+#[derive(Template)]
+#[template(path = "search.html")]
+struct SearchTemplate {
+    query: String,
+    hits: Vec<SearchHit>,
+    error: Option<String>,
+}
+
+async fn search_page(
+    State(mgr): State<AppState>,
+    Query(query): Query<SearchQuery>,
+) -> impl IntoResponse {
+    let (hits, error) = if query.q.trim().is_empty() {
+        (Vec::new(), None)
+    } else {
+        match mgr.search.query(&query) {
+            Ok(hits) => (hits, None),
+            Err(e) => (Vec::new(), Some(e.to_string())),
+        }
+    };
+    HtmlTemplate(SearchTemplate {
+        query: query.q,
+        hits,
+        error,
+    })
+}
+
+#[derive(Template)]
+#[template(path = "log.html")]
+struct LogTemplate {
+    id: Uuid,
+    name: String,
+    /// Lines before this were dropped from the index
+    first: usize,
+    lines: Vec<IndexedLine>,
+}
+
+/// Plain-text log with a `#L{n}` anchor per line, for search results.
+/// Works for saved sessions as well as live ones.
+async fn log_page(State(mgr): State<AppState>, Path(query): Path<String>) -> impl IntoResponse {
+    let matches = mgr.search.sessions_matching(&query);
+    let mut ids = matches.into_iter();
+    match (ids.next(), ids.next()) {
+        (Some(id), None) => match mgr.search.lines(id) {
+            Some((name, first, lines)) => Ok(HtmlTemplate(LogTemplate {
+                id,
+                name,
+                first,
+                lines,
+            })),
+            None => Err((StatusCode::NOT_FOUND, "Session not found")),
+        },
+        (Some(_), Some(_)) => Err((StatusCode::CONFLICT, "Several sessions match")),
+        (None, _) => Err((StatusCode::NOT_FOUND, "Session not found")),
+    }
+}
+
 struct HtmlTemplate<T>(T);
 
 impl<T: Template> IntoResponse for HtmlTemplate<T> {
@@ -0,0 +1,645 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::sync::{mpsc, oneshot, watch};
+use tracing::Instrument;
+use uuid::Uuid;
+
+use crate::error::ForgeError;
+use crate::metrics::SessionCounters;
+use crate::policy::{ApprovalConfig, ApprovalRecord, Decision};
+use crate::search::SearchIndex;
+use crate::session::feed::OutputFeed;
+use crate::session::limits::SessionCgroup;
+use crate::session::log::{LogSlice, SessionLog};
+use crate::session::model::{SessionMeta, SessionStatus};
+use crate::session::persist::{persist, session_dir};
+use crate::session::prompt::{PermissionPrompt, PromptDetector};
+use crate::session::resources::SessionResources;
+use crate::store::{SessionEvent, Store};
+
+pub enum PtyCommand {
+    Input(Vec<u8>),
+    Resize { rows: u16, cols: u16 },
+}
+
+/// Requests that need the session's own state; answered by its actor task.
+enum SessionCommand {
+    LogAfter {
+        seq: u64,
+        reply: oneshot::Sender<LogSlice>,
+    },
+    Follow {
+        seq: u64,
+        reply: oneshot::Sender<(LogSlice, OutputFeed)>,
+    },
+    Stop {
+        reply: oneshot::Sender<Result<(), ForgeError>>,
+    },
+}
+
+/// Cheap, cloneable handle to a session's actor.
+///
+/// Metadata, the pending prompt and the PTY size are published on watch
+/// channels, so reading them never waits on the actor. Input goes straight
+/// to the PTY writer; only log reads, follows and stop go through the actor.
+#[derive(Clone)]
+pub struct SessionHandle {
+    pub id: Uuid,
+    commands: mpsc::Sender<SessionCommand>,
+    input_tx: mpsc::Sender<PtyCommand>,
+    meta_rx: watch::Receiver<SessionMeta>,
+    prompt_rx: watch::Receiver<Option<PermissionPrompt>>,
+    size_rx: watch::Receiver<(u16, u16)>,
+    log_done: Option<watch::Receiver<()>>,
+    pub counters: Arc<SessionCounters>,
+    pub resources: Arc<SessionResources>,
+}
+
+impl SessionHandle {
+    pub fn meta(&self) -> SessionMeta {
+        self.meta_rx.borrow().clone()
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.meta_rx.borrow().status == SessionStatus::Running
+    }
+
+    pub fn prompt(&self) -> Option<PermissionPrompt> {
+        self.prompt_rx.borrow().clone()
+    }
+
+    /// Resolves once the tool process has exited and its output is on disk.
+    pub async fn exited(&self) {
+        let mut meta = self.meta_rx.clone();
+        let _ = meta.wait_for(|m| m.pid.is_none()).await;
+        if let Some(mut done) = self.log_done.clone() {
+            while done.changed().await.is_ok() {}
+        }
+    }
+
+    pub fn subscribe_prompt(&self) -> watch::Receiver<Option<PermissionPrompt>> {
+        self.prompt_rx.clone()
+    }
+
+    pub fn subscribe_size(&self) -> watch::Receiver<(u16, u16)> {
+        self.size_rx.clone()
+    }
+
+    pub async fn send(&self, cmd: PtyCommand) -> Result<(), ForgeError> {
+        if !self.is_running() {
+            return Err(ForgeError::SessionAlreadyStopped(self.id));
+        }
+        self.input_tx
+            .send(cmd)
+            .await
+            .map_err(|_| ForgeError::Unavailable("Input channel closed".into()))
+    }
+
+    pub async fn log_after(&self, seq: u64) -> Result<LogSlice, ForgeError> {
+        let (reply, rx) = oneshot::channel();
+        self.request(SessionCommand::LogAfter { seq, reply })
+            .await?;
+        rx.await.map_err(|_| session_gone())
+    }
+
+    /// Entries after `seq` and a feed of live output from there on.
+    pub async fn follow(&self, seq: u64) -> Result<(LogSlice, OutputFeed), ForgeError> {
+        let (reply, rx) = oneshot::channel();
+        self.request(SessionCommand::Follow { seq, reply }).await?;
+        rx.await.map_err(|_| session_gone())
+    }
+
+    pub async fn stop(&self) -> Result<(), ForgeError> {
+        let (reply, rx) = oneshot::channel();
+        self.request(SessionCommand::Stop { reply }).await?;
+        rx.await.map_err(|_| session_gone())?
+    }
+
+    async fn request(&self, cmd: SessionCommand) -> Result<(), ForgeError> {
+        self.commands.send(cmd).await.map_err(|_| session_gone())
+    }
+}
+
+fn session_gone() -> ForgeError {
+    ForgeError::Unavailable("Session task has exited".into())
+}
+
+/// Bookkeeping the actor hands off, so disk and database writes never hold
+/// up its read loop.
+enum Record {
+    Event(SessionEvent),
+    /// Appended to `approvals.jsonl`
+    Approval(ApprovalRecord),
+    /// Save the metadata as published at the time it is written
+    Meta,
+}
+
+/// Records queued per session. Events beyond it are dropped with a warning;
+/// approvals and metadata saves wait for room.
+const RECORD_QUEUE: usize = 256;
+
+/// A spawned child and the PTY it runs in.
+pub struct SessionProcess {
+    pub pty: pty_process::Pty,
+    pub child: tokio::process::Child,
+    pub rows: u16,
+    pub cols: u16,
+    /// Holds the session's limits, when it got a cgroup
+    pub cgroup: Option<SessionCgroup>,
+}
+
+/// Owns everything about one session: the PTY, its output log and metadata.
+/// Nothing else mutates them, so a busy session never holds up another.
+struct SessionActor {
+    meta_tx: watch::Sender<SessionMeta>,
+    prompt_tx: watch::Sender<Option<PermissionPrompt>>,
+    log: SessionLog,
+    input_tx: mpsc::Sender<PtyCommand>,
+    approval: ApprovalConfig,
+    search: Arc<SearchIndex>,
+    recorder: mpsc::Sender<Record>,
+    detector: PromptDetector,
+    last_prompt: Option<PermissionPrompt>,
+    /// Set by the PTY writer: any input may have answered the prompt on screen
+    input_seen: Arc<AtomicBool>,
+    /// Incomplete UTF-8 sequence from the end of the previous read
+    leftover: Vec<u8>,
+    counters: Arc<SessionCounters>,
+    cgroup: Option<SessionCgroup>,
+}
+
+/// Start the actor for a freshly spawned session.
+pub fn spawn(
+    meta: SessionMeta,
+    process: SessionProcess,
+    log: SessionLog,
+    approval: ApprovalConfig,
+    search: Arc<SearchIndex>,
+    store: Arc<dyn Store>,
+) -> SessionHandle {
+    let SessionProcess {
+        pty,
+        child,
+        rows,
+        cols,
+        cgroup,
+    } = process;
+    let (pty_reader, pty_writer) = pty.into_split();
+
+    let (commands, commands_rx) = mpsc::channel(64);
+    let (input_tx, input_rx) = mpsc::channel::<PtyCommand>(256);
+    let id = meta.id;
+    let (meta_tx, meta_rx) = watch::channel(meta);
+    let (recorder, records) = mpsc::channel(RECORD_QUEUE);
+    tokio::spawn(run_recorder(id, store, meta_rx.clone(), records));
+    let (prompt_tx, prompt_rx) = watch::channel(None);
+    // PTY size changes, applied and published by the writer (webview syncs via SSE)
+    let (size_tx, size_rx) = watch::channel((rows, cols));
+    let input_seen = Arc::new(AtomicBool::new(false));
+    let counters = Arc::new(SessionCounters::default());
+
+    let handle = SessionHandle {
+        id,
+        commands,
+        input_tx: input_tx.clone(),
+        meta_rx,
+        prompt_rx,
+        size_rx,
+        log_done: log.writer_done(),
+        counters: counters.clone(),
+        resources: Arc::default(),
+    };
+
+    let writer = tokio::spawn(run_pty_writer(
+        pty_writer,
+        input_rx,
+        size_tx,
+        input_seen.clone(),
+        counters.clone(),
+    ));
+    let actor = SessionActor {
+        meta_tx,
+        prompt_tx,
+        log,
+        input_tx,
+        approval,
+        search,
+        recorder,
+        detector: PromptDetector::new(),
+        last_prompt: None,
+        input_seen,
+        leftover: Vec::new(),
+        counters,
+        cgroup,
+    };
+    // A root span: as a child it would keep the spawn request's span open
+    // for the session's lifetime
+    let span = tracing::info_span!(parent: None, "session", session = %id);
+    tokio::spawn(
+        actor
+            .run(pty_reader, child, commands_rx, writer)
+            .instrument(span),
+    );
+
+    handle
+}
+
+/// Forward input and resize commands to the PTY.
+async fn run_pty_writer(
+    mut pty_writer: pty_process::OwnedWritePty,
+    mut input_rx: mpsc::Receiver<PtyCommand>,
+    size_tx: watch::Sender<(u16, u16)>,
+    input_seen: Arc<AtomicBool>,
+    counters: Arc<SessionCounters>,
+) {
+    while let Some(cmd) = input_rx.recv().await {
+        match cmd {
+            PtyCommand::Input(data) => {
+                input_seen.store(true, Ordering::Relaxed);
+                counters
+                    .input_bytes
+                    .fetch_add(data.len() as u64, Ordering::Relaxed);
+                if pty_writer.write_all(&data).await.is_err() {
+                    break;
+                }
+            }
+            PtyCommand::Resize { rows, cols } => {
+                let _ = pty_writer.resize(pty_process::Size::new(rows, cols));
+                let _ = size_tx.send((rows, cols));
+            }
+        }
+    }
+}
+
+/// Queue a record without blocking. Approvals must all be recorded, and a
+/// metadata save can't be skipped, so when the queue is full those are sent
+/// once there is room instead of being dropped.
+fn queue_record(recorder: &mpsc::Sender<Record>, id: Uuid, record: Record) {
+    match recorder.try_send(record) {
+        Ok(()) | Err(mpsc::error::TrySendError::Closed(_)) => {}
+        Err(mpsc::error::TrySendError::Full(record @ (Record::Meta | Record::Approval(_)))) => {
+            let recorder = recorder.clone();
+            tokio::spawn(async move { recorder.send(record).await });
+        }
+        Err(mpsc::error::TrySendError::Full(Record::Event(_))) => {
+            tracing::warn!("Session {id} has too many records queued; dropped an event");
+        }
+    }
+}
+
+/// Write a session's records in order, in batches, on the blocking pool.
+async fn run_recorder(
+    id: Uuid,
+    store: Arc<dyn Store>,
+    meta_rx: watch::Receiver<SessionMeta>,
+    mut records: mpsc::Receiver<Record>,
+) {
+    let mut batch = Vec::new();
+    while records.recv_many(&mut batch, RECORD_QUEUE).await > 0 {
+        let batch = std::mem::take(&mut batch);
+        // A copy, so the writes don't hold up anyone reading the metadata
+        let meta = batch
+            .iter()
+            .any(|r| matches!(r, Record::Meta))
+            .then(|| meta_rx.borrow().clone());
+        let store = store.clone();
+        let written =
+            tokio::task::spawn_blocking(move || write_records(id, store.as_ref(), batch, meta));
+        if let Err(e) = written.await {
+            tracing::warn!("Failed to write records for session {id}: {e}");
+        }
+    }
+}
+
+fn write_records(id: Uuid, store: &dyn Store, batch: Vec<Record>, meta: Option<SessionMeta>) {
+    let mut events = Vec::new();
+    for record in batch {
+        match record {
+            Record::Event(event) => events.push(event),
+            Record::Approval(approval) => {
+                if let Err(e) = crate::policy::append_record(&session_dir(id), &approval) {
+                    tracing::warn!("Failed to record approval decision for {id}: {e}");
+                }
+            }
+            Record::Meta => {}
+        }
+    }
+    if !events.is_empty()
+        && let Err(e) = store.record_events(id, &events)
+    {
+        tracing::warn!("Failed to record {} events for {id}: {e:#}", events.len());
+    }
+    if let Some(meta) = meta
+        && let Err(e) = persist(store, &meta)
+    {
+        tracing::warn!("Failed to save session {id}: {e:#}");
+    }
+}
+
+impl SessionActor {
+    async fn run(
+        mut self,
+        mut pty_reader: pty_process::OwnedReadPty,
+        mut child: tokio::process::Child,
+        mut commands: mpsc::Receiver<SessionCommand>,
+        writer: tokio::task::JoinHandle<()>,
+    ) {
+        let mut buf = vec![0u8; 4096];
+        let mut reading = true;
+        let mut exited = false;
+
+        // Keep answering log reads after the process exits, until the
+        // manager lets go of the session
+        loop {
+            tokio::select! {
+                read = pty_reader.read(&mut buf), if reading => match read {
+                    Ok(n) if n > 0 => self.on_output(&buf[..n]),
+                    _ => {
+                        reading = false;
+                        writer.abort();
+                        self.search.finish(self.id());
+                    }
+                },
+                status = child.wait(), if !reading && !exited => {
+                    exited = true;
+                    self.on_exit(status);
+                }
+                cmd = commands.recv() => match cmd {
+                    Some(cmd) => self.handle(cmd),
+                    None => break,
+                },
+            }
+        }
+    }
+
+    fn id(&self) -> Uuid {
+        self.meta_tx.borrow().id
+    }
+
+    fn on_output(&mut self, bytes: &[u8]) {
+        let id = self.id();
+        let chunk = bytes::Bytes::copy_from_slice(bytes);
+
+        // Text view for prompt detection and search; the log keeps the raw
+        // bytes. Prepend any leftover bytes from previous read
+        let mut data = std::mem::take(&mut self.leftover);
+        data.extend_from_slice(&chunk);
+
+        // Find the last valid UTF-8 boundary
+        let valid_up_to = match std::str::from_utf8(&data) {
+            Ok(_) => data.len(),
+            Err(e) => e.valid_up_to(),
+        };
+
+        // Save incomplete trailing bytes for next read
+        if valid_up_to < data.len() {
+            self.leftover = data[valid_up_to..].to_vec();
+        }
+
+        let text = String::from_utf8_lossy(&data[..valid_up_to]).to_string();
+
+        if self.input_seen.swap(false, Ordering::Relaxed) {
+            self.detector.clear();
+            self.last_prompt = None;
+        }
+        let mut prompt = self.detector.feed(&text);
+        let now = chrono::Utc::now();
+        self.search.append(id, text, now);
+
+        self.counters
+            .output_bytes
+            .fetch_add(chunk.len() as u64, Ordering::Relaxed);
+        self.log.push(chunk);
+        self.counters
+            .buffer_bytes
+            .store(self.log.buffered_bytes() as u64, Ordering::Relaxed);
+        self.meta_tx.send_modify(|m| m.last_output_at = Some(now));
+
+        // Only act once per prompt; redraws of the same prompt are ignored
+        if self.approval.enabled
+            && let Some(ref p) = prompt
+            && self.last_prompt.as_ref() != Some(p)
+        {
+            if self.apply_policy(p) {
+                self.detector.clear();
+                self.last_prompt = None;
+                prompt = None;
+            } else {
+                self.last_prompt = prompt.clone();
+            }
+        }
+
+        let shown = self.prompt_tx.send_if_modified(|current| {
+            if *current == prompt {
+                return false;
+            }
+            *current = prompt;
+            true
+        });
+        if shown && let Some(ref prompt) = *self.prompt_tx.borrow() {
+            self.record_event(
+                "prompt",
+                serde_json::json!({
+                    "tool": prompt.tool,
+                    "command": prompt.command,
+                    "path": prompt.path,
+                    "question": prompt.question,
+                }),
+            );
+        }
+    }
+
+    fn on_exit(&mut self, status: std::io::Result<std::process::ExitStatus>) {
+        self.log.close();
+        let code = status.as_ref().ok().and_then(|s| s.code());
+        self.record_event("exit", serde_json::json!({ "code": code }));
+        let hits = self
+            .cgroup
+            .as_ref()
+            .map(SessionCgroup::hits)
+            .unwrap_or_default();
+        if hits.oom_kills > 0 || hits.pids_max > 0 {
+            self.record_event("limit", serde_json::json!(hits));
+        }
+        // Removed now if nothing the tool started is left in it
+        self.cgroup = None;
+        // Only if still Running - a stop may have already set it
+        self.meta_tx.send_modify(|meta| {
+            if meta.status == SessionStatus::Running {
+                let memory_mb = meta.limits.as_ref().and_then(|l| l.limits.memory_mb);
+                meta.status = match status {
+                    Ok(exit) if exit.success() => SessionStatus::Stopped,
+                    Ok(_) if hits.oom_kills > 0 => SessionStatus::Errored(format!(
+                        "Killed by the memory limit ({} MB)",
+                        memory_mb.unwrap_or_default()
+                    )),
+                    Ok(_) => SessionStatus::Errored("Process exited with non-zero status".into()),
+                    Err(e) => SessionStatus::Errored(e.to_string()),
+                };
+            }
+            meta.updated_at = chrono::Utc::now();
+            meta.pid = None;
+        });
+        self.save_meta();
+    }
+
+    fn handle(&mut self, cmd: SessionCommand) {
+        match cmd {
+            SessionCommand::LogAfter { seq, reply } => {
+                let _ = reply.send(self.log.after(seq));
+            }
+            SessionCommand::Follow { seq, reply } => {
+                let _ = reply.send(self.log.follow(seq));
+            }
+            SessionCommand::Stop { reply } => {
+                let _ = reply.send(self.stop());
+            }
+        }
+    }
+
+    fn stop(&mut self) -> Result<(), ForgeError> {
+        let id = self.id();
+        let mut result = Ok(());
+        self.meta_tx.send_if_modified(|meta| {
+            if meta.status != SessionStatus::Running {
+                result = Err(ForgeError::SessionAlreadyStopped(id));
+                return false;
+            }
+
+            // Send SIGTERM via kill
+            if let Some(pid) = meta.pid {
+                unsafe {
+                    libc::kill(pid as i32, libc::SIGTERM);
+                }
+            }
+            meta.status = SessionStatus::Stopped;
+            meta.updated_at = chrono::Utc::now();
+            true
+        });
+        result?;
+        self.record_event("stop", serde_json::Value::Null);
+        self.save_meta();
+
+        // Clean up attach socket
+        let sock_file = crate::session::manager::sock_dir().join(format!("{id}.sock"));
+        let _ = std::fs::remove_file(&sock_file);
+        Ok(())
+    }
+
+    /// Answer a permission prompt according to the approval rules and record
+    /// the decision in the session's `approvals.jsonl`. Returns true if an
+    /// answer was sent.
+    fn apply_policy(&self, prompt: &PermissionPrompt) -> bool {
+        let (decision, rule) = {
+            let meta = self.meta_tx.borrow();
+            self.approval
+                .evaluate(&meta.tool, &meta.working_dir, prompt)
+        };
+        let id = self.id();
+        let option = match decision {
+            Decision::Allow => prompt.approve_option(),
+            Decision::Deny => prompt.deny_option(),
+            Decision::Ask => None,
+        };
+
+        let answered = match option {
+            Some(opt) => self
+                .input_tx
+                .try_send(PtyCommand::Input(opt.key.clone().into_bytes()))
+                .is_ok(),
+            None => false,
+        };
+
+        // A decision that could not be delivered falls back to asking the user
+        let decision = if answered { decision } else { Decision::Ask };
+        tracing::info!(
+            session = %id,
+            tool = prompt.tool.as_deref().unwrap_or("unknown"),
+            "Permission prompt: {decision}"
+        );
+
+        let record = ApprovalRecord {
+            timestamp: chrono::Utc::now(),
+            decision,
+            rule,
+            tool: prompt.tool.clone(),
+            command: prompt.command.clone(),
+            path: prompt.path.clone(),
+            question: prompt.question.clone(),
+            answer: option.filter(|_| answered).map(|o| o.label.clone()),
+        };
+        match serde_json::to_value(&record) {
+            Ok(data) => self.record_event("approval", data),
+            Err(e) => tracing::warn!("Failed to record approval event for {id}: {e}"),
+        }
+        self.record(Record::Approval(record));
+
+        answered
+    }
+
+    /// Persist the current metadata, in the background; failures are
+    /// logged, not fatal.
+    fn save_meta(&self) {
+        self.record(Record::Meta);
+    }
+
+    fn record_event(&self, kind: &str, data: serde_json::Value) {
+        self.record(Record::Event(SessionEvent::new(kind, data)));
+    }
+
+    fn record(&self, record: Record) {
+        queue_record(&self.recorder, self.id(), record);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn approval_is_kept_when_the_queue_is_full() {
+        let (recorder, mut records) = mpsc::channel(RECORD_QUEUE);
+        let id = Uuid::new_v4();
+        for _ in 0..RECORD_QUEUE {
+            queue_record(
+                &recorder,
+                id,
+                Record::Event(SessionEvent::new("output", serde_json::Value::Null)),
+            );
+        }
+        let approval = ApprovalRecord {
+            timestamp: chrono::Utc::now(),
+            decision: Decision::Allow,
+            rule: Some(0),
+            tool: Some("Bash".into()),
+            command: Some("cargo build".into()),
+            path: None,
+            question: "Do you want to proceed?".into(),
+            answer: Some("Yes".into()),
+        };
+        queue_record(&recorder, id, Record::Approval(approval));
+        queue_record(
+            &recorder,
+            id,
+            Record::Event(SessionEvent::new("dropped", serde_json::Value::Null)),
+        );
+        drop(recorder);
+
+        let mut approvals = 0;
+        let mut events = 0;
+        while let Some(record) = records.recv().await {
+            match record {
+                Record::Approval(a) => {
+                    assert_eq!(a.command.as_deref(), Some("cargo build"));
+                    approvals += 1;
+                }
+                Record::Event(_) => events += 1,
+                Record::Meta => {}
+            }
+        }
+        assert_eq!(approvals, 1);
+        assert_eq!(events, RECORD_QUEUE);
+    }
+}
@@ -0,0 +1,46 @@
+/// Remove ANSI escape sequences from terminal output.
+///
+/// Handles CSI (`ESC [`), OSC (`ESC ]`, terminated by BEL or `ESC \`) and
+/// two-byte escapes. Cursor-forward (`CSI n C`) is replaced with spaces since
+/// TUIs use it in place of padding, and carriage returns are dropped.
+pub fn strip_ansi(input: &str) -> String {
+    let mut out = String::with_capacity(input.len());
+    let mut chars = input.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\x1b' => match chars.next() {
+                Some('[') => {
+                    let mut params = String::new();
+                    for p in chars.by_ref() {
+                        if ('\x40'..='\x7e').contains(&p) {
+                            if p == 'C' {
+                                let n = params.parse::<usize>().unwrap_or(1).min(512);
+                                out.extend(std::iter::repeat_n(' ', n));
+                            }
+                            break;
+                        }
+                        params.push(p);
+                    }
+                }
+                Some(']') => {
+                    while let Some(p) = chars.next() {
+                        if p == '\x07' {
+                            break;
+                        }
+                        if p == '\x1b' && chars.peek() == Some(&'\\') {
+                            chars.next();
+                            break;
+                        }
+                    }
+                }
+                _ => {}
+            },
+            '\r' => {}
+            c if c.is_control() && c != '\n' && c != '\t' => {}
+            c => out.push(c),
+        }
+    }
+
+    out
+}
@@ -0,0 +1,207 @@
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Duration;
+
+use bytes::BytesMut;
+use tokio::sync::Notify;
+
+use crate::session::log::LogEntry;
+
+/// Largest entry a feed hands out in one go, unless a single read is larger
+pub const CHUNK_BYTES: usize = 64 * 1024;
+
+/// Limits for each client following a session's output.
+#[derive(Debug, Clone, Copy)]
+pub struct FeedConfig {
+    /// Output a client may fall behind by before it has to resync
+    pub buffer_bytes: usize,
+    /// Chunks arriving within this window are sent as one entry
+    pub coalesce: Duration,
+}
+
+/// What a client gets next from its feed.
+pub enum FeedItem {
+    /// New output, possibly several PTY reads merged into one entry
+    Output(LogEntry),
+    /// The client fell more than `buffer_bytes` behind and was cut off. It
+    /// should follow the session again from the last entry it handled.
+    Resync,
+}
+
+#[derive(Default)]
+struct Pending {
+    entries: Vec<LogEntry>,
+    bytes: usize,
+    overflowed: bool,
+    closed: bool,
+}
+
+struct Shared {
+    pending: Mutex<Pending>,
+    notify: Notify,
+}
+
+impl Shared {
+    fn lock(&self) -> std::sync::MutexGuard<'_, Pending> {
+        self.pending.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// The session's side of a feed. Pushing never blocks: a client that can't
+/// keep up is cut off instead of holding up the session or other clients.
+pub struct FeedSender {
+    shared: Weak<Shared>,
+    max_bytes: usize,
+}
+
+impl FeedSender {
+    /// Queue `entry`. Returns false once the client is gone or cut off, so
+    /// the sender can be dropped.
+    pub fn push(&self, entry: &LogEntry) -> bool {
+        let Some(shared) = self.shared.upgrade() else {
+            return false;
+        };
+        let mut pending = shared.lock();
+        if pending.bytes + entry.data.len() > self.max_bytes {
+            // Nothing queued is worth sending once the client has to resync
+            *pending = Pending {
+                overflowed: true,
+                ..Pending::default()
+            };
+            drop(pending);
+            shared.notify.notify_one();
+            return false;
+        }
+        pending.bytes += entry.data.len();
+        pending.entries.push(entry.clone());
+        drop(pending);
+        shared.notify.notify_one();
+        true
+    }
+
+    /// No more output will come; the feed ends once drained.
+    pub fn close(&self) {
+        if let Some(shared) = self.shared.upgrade() {
+            shared.lock().closed = true;
+            shared.notify.notify_one();
+        }
+    }
+}
+
+/// A client's buffered view of a session's live output.
+pub struct OutputFeed {
+    shared: Arc<Shared>,
+    coalesce: Duration,
+    /// Queued output is sent without waiting out `coalesce` past this size
+    flush_bytes: usize,
+    /// Output was left queued after the last chunk
+    backlogged: bool,
+}
+
+impl OutputFeed {
+    pub fn new(config: FeedConfig) -> (FeedSender, OutputFeed) {
+        let shared = Arc::new(Shared {
+            pending: Mutex::new(Pending::default()),
+            notify: Notify::new(),
+        });
+        let sender = FeedSender {
+            shared: Arc::downgrade(&shared),
+            max_bytes: config.buffer_bytes,
+        };
+        let feed = OutputFeed {
+            shared,
+            coalesce: config.coalesce,
+            flush_bytes: config.buffer_bytes.min(CHUNK_BYTES),
+            backlogged: false,
+        };
+        (sender, feed)
+    }
+
+    /// Wait for output. Returns `None` once the session's output has ended
+    /// and everything queued has been taken.
+    pub async fn next(&mut self) -> Option<FeedItem> {
+        loop {
+            {
+                let pending = self.shared.lock();
+                if pending.overflowed {
+                    return Some(FeedItem::Resync);
+                }
+                if !pending.entries.is_empty() {
+                    break;
+                }
+                if pending.closed {
+                    return None;
+                }
+            }
+            self.shared.notify.notified().await;
+        }
+
+        // Let a burst of small reads accumulate into one entry, unless output
+        // is already backed up
+        if !self.backlogged {
+            let deadline = tokio::time::Instant::now() + self.coalesce;
+            while self.shared.lock().bytes < self.flush_bytes {
+                tokio::select! {
+                    _ = tokio::time::sleep_until(deadline) => break,
+                    _ = self.shared.notify.notified() => {}
+                }
+            }
+        }
+
+        // Hand out one chunk at a time; the rest stays queued and counts
+        // against the client's buffer until it is taken
+        let mut pending = self.shared.lock();
+        if pending.overflowed {
+            return Some(FeedItem::Resync);
+        }
+        let mut size = 0;
+        let take = pending
+            .entries
+            .iter()
+            .position(|e| {
+                size += e.data.len();
+                size > CHUNK_BYTES
+            })
+            .unwrap_or(pending.entries.len())
+            .max(1);
+        let entries: Vec<LogEntry> = pending.entries.drain(..take).collect();
+        pending.bytes -= entries.iter().map(|e| e.data.len()).sum::<usize>();
+        self.backlogged = !pending.entries.is_empty();
+        drop(pending);
+        coalesce(entries, usize::MAX).pop().map(FeedItem::Output)
+    }
+}
+
+/// Merge consecutive entries into entries of at most `max_bytes` each
+/// (a single larger entry is kept whole). A merged entry has the sequence
+/// number of its last part, so resuming after it skips all of them.
+pub fn coalesce(entries: Vec<LogEntry>, max_bytes: usize) -> Vec<LogEntry> {
+    if entries.len() < 2 {
+        return entries;
+    }
+    let mut merged = Vec::new();
+    let mut current: Option<(LogEntry, BytesMut)> = None;
+    for entry in entries {
+        if let Some((head, data)) = current.as_mut()
+            && data.len() + entry.data.len() <= max_bytes
+        {
+            data.extend_from_slice(&entry.data);
+            head.seq = entry.seq;
+            continue;
+        }
+        if let Some((head, data)) = current.take() {
+            merged.push(LogEntry {
+                data: data.freeze(),
+                ..head
+            });
+        }
+        let data = BytesMut::from(&entry.data[..]);
+        current = Some((entry, data));
+    }
+    if let Some((head, data)) = current {
+        merged.push(LogEntry {
+            data: data.freeze(),
+            ..head
+        });
+    }
+    merged
+}
@@ -0,0 +1,385 @@
+use std::os::fd::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::config::{IoPriority, ResourceLimits};
+
+const MB: u64 = 1024 * 1024;
+
+/// How a session's limits are enforced.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Enforcement {
+    /// A cgroup of its own limits the whole process tree
+    Cgroup,
+    /// Per-process rlimits: `memory_mb` caps each process's data segment,
+    /// so going over makes allocations fail rather than getting the tool
+    /// killed, which can't be told apart from any other failure. CPU and
+    /// process limits aren't enforced.
+    Rlimit,
+}
+
+/// The limits a session was started with and that are enforced, as
+/// recorded in its metadata.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct AppliedLimits {
+    #[serde(flatten)]
+    pub limits: ResourceLimits,
+    pub enforcement: Enforcement,
+}
+
+impl std::fmt::Display for AppliedLimits {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let enforcement = match self.enforcement {
+            Enforcement::Cgroup => "cgroup",
+            Enforcement::Rlimit => "rlimit",
+        };
+        write!(f, "{} ({enforcement})", self.limits)
+    }
+}
+
+/// Limits prepared for one spawn: the session's cgroup, if it got one, and
+/// what the child applies to itself before exec.
+pub struct PreparedLimits {
+    pub applied: AppliedLimits,
+    pub cgroup: Option<SessionCgroup>,
+    /// The cgroup's `cgroup.procs`, opened before the fork
+    procs: Option<std::fs::File>,
+    data_bytes: Option<u64>,
+    nice: Option<i32>,
+    ioprio: Option<IoPriority>,
+}
+
+impl PreparedLimits {
+    /// Set up limits for session `id`; `None` when there are none that can
+    /// be enforced. Falls back to rlimits when no cgroup can be created.
+    pub fn prepare(id: Uuid, limits: &ResourceLimits) -> Option<Self> {
+        if limits.is_empty() {
+            return None;
+        }
+        let needs_cgroup =
+            limits.memory_mb.is_some() || limits.cpu_percent.is_some() || limits.max_pids.is_some();
+        let created = needs_cgroup
+            .then(cgroup_root)
+            .flatten()
+            .map(|root| SessionCgroup::create(root, id, limits));
+        let (cgroup, procs) = match created {
+            Some(Ok((cgroup, procs))) => (Some(cgroup), Some(procs)),
+            Some(Err(e)) => {
+                tracing::warn!("Failed to create a cgroup for session {id}: {e}");
+                (None, None)
+            }
+            None => (None, None),
+        };
+
+        let mut enforced = limits.clone();
+        let enforcement = if cgroup.is_some() {
+            Enforcement::Cgroup
+        } else {
+            if limits.cpu_percent.is_some() || limits.max_pids.is_some() {
+                tracing::warn!(
+                    "cpu_percent and max_pids need a cgroup; not enforced for session {id}"
+                );
+            }
+            enforced.cpu_percent = None;
+            enforced.max_pids = None;
+            Enforcement::Rlimit
+        };
+        if enforced.is_empty() {
+            return None;
+        }
+        Some(Self {
+            data_bytes: match enforcement {
+                Enforcement::Rlimit => limits.memory_mb.map(|mb| mb * MB),
+                Enforcement::Cgroup => None,
+            },
+            nice: limits.nice,
+            ioprio: limits.ionice,
+            applied: AppliedLimits {
+                limits: enforced,
+                enforcement,
+            },
+            cgroup,
+            procs,
+        })
+    }
+
+    /// What the child runs between fork and exec: join the cgroup first, so
+    /// everything it starts is in it, then lower its own limits and
+    /// priority. Only async-signal-safe calls, no allocation.
+    pub fn pre_exec(&self) -> impl FnMut() -> std::io::Result<()> + Send + Sync + 'static {
+        let procs = self.procs.as_ref().map(AsRawFd::as_raw_fd);
+        let data_bytes = self.data_bytes;
+        let nice = self.nice;
+        let ioprio = self.ioprio;
+        move || {
+            let check = |rc: libc::c_int| {
+                if rc < 0 {
+                    Err(std::io::Error::last_os_error())
+                } else {
+                    Ok(())
+                }
+            };
+            // "0" moves the writing process
+            if let Some(fd) = procs {
+                check(unsafe { libc::write(fd, b"0".as_ptr().cast(), 1) } as libc::c_int)?;
+            }
+            if let Some(bytes) = data_bytes {
+                let limit = libc::rlimit {
+                    rlim_cur: bytes as libc::rlim_t,
+                    rlim_max: bytes as libc::rlim_t,
+                };
+                check(unsafe { libc::setrlimit(libc::RLIMIT_DATA, &limit) })?;
+            }
+            if let Some(nice) = nice {
+                check(unsafe { libc::setpriority(libc::PRIO_PROCESS as _, 0, nice) })?;
+            }
+            if let Some(ioprio) = ioprio {
+                set_ioprio(ioprio)?;
+            }
+            Ok(())
+        }
+    }
+
+    /// Done spawning: the child is in the cgroup and no longer needs it
+    /// open.
+    pub fn spawned(mut self) -> (AppliedLimits, Option<SessionCgroup>) {
+        self.procs = None;
+        (self.applied, self.cgroup)
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn set_ioprio(priority: IoPriority) -> std::io::Result<()> {
+    const IOPRIO_WHO_PROCESS: libc::c_long = 1;
+    const IOPRIO_CLASS_SHIFT: libc::c_long = 13;
+    let value = match priority {
+        // Best effort (class 2) at its lowest level
+        IoPriority::Low => (2 << IOPRIO_CLASS_SHIFT) | 7,
+        IoPriority::Idle => 3 << IOPRIO_CLASS_SHIFT,
+    };
+    let rc = unsafe { libc::syscall(libc::SYS_ioprio_set, IOPRIO_WHO_PROCESS, 0, value) };
+    if rc < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn set_ioprio(_priority: IoPriority) -> std::io::Result<()> {
+    Ok(())
+}
+
+/// Whether this process may start children at niceness `nice`. Anyone may
+/// lower a priority; raising it above the process's own takes
+/// CAP_SYS_NICE, or on Linux an `RLIMIT_NICE` of at least `20 - nice`.
+pub fn nice_allowed(nice: i32) -> bool {
+    let current = unsafe { libc::getpriority(libc::PRIO_PROCESS as _, 0) };
+    nice >= current || may_raise_priority(nice)
+}
+
+#[cfg(target_os = "linux")]
+fn may_raise_priority(nice: i32) -> bool {
+    const CAP_SYS_NICE: u32 = 23;
+    let mut limit = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    if unsafe { libc::getrlimit(libc::RLIMIT_NICE, &mut limit) } == 0
+        && (20 - nice) as libc::rlim_t <= limit.rlim_cur
+    {
+        return true;
+    }
+    std::fs::read_to_string("/proc/self/status")
+        .ok()
+        .and_then(|status| {
+            let caps = status.lines().find_map(|l| l.strip_prefix("CapEff:"))?;
+            u64::from_str_radix(caps.trim(), 16).ok()
+        })
+        .is_some_and(|caps| caps & (1 << CAP_SYS_NICE) != 0)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn may_raise_priority(_nice: i32) -> bool {
+    unsafe { libc::geteuid() == 0 }
+}
+
+/// What a limit did to a session's processes, read when the tool exits.
+#[derive(Debug, Default, Serialize)]
+pub struct LimitHits {
+    /// Processes the kernel killed for exceeding `memory_mb`
+    pub oom_kills: u64,
+    /// Forks refused because of `max_pids`
+    pub pids_max: u64,
+}
+
+/// A session's cgroup, `session-<id>` under the server's. Removed when
+/// dropped, unless processes are still in it.
+pub struct SessionCgroup {
+    path: PathBuf,
+}
+
+impl SessionCgroup {
+    /// Create the cgroup with its limits, and open its `cgroup.procs` for
+    /// the child to join.
+    fn create(
+        root: &Path,
+        id: Uuid,
+        limits: &ResourceLimits,
+    ) -> std::io::Result<(Self, std::fs::File)> {
+        let path = root.join(format!("session-{id}"));
+        std::fs::create_dir(&path)?;
+        let cgroup = Self { path };
+        if let Some(mb) = limits.memory_mb {
+            cgroup.write("memory.max", &(mb * MB).to_string())?;
+        }
+        if let Some(percent) = limits.cpu_percent {
+            // Quota per 100ms period
+            cgroup.write("cpu.max", &format!("{} 100000", u64::from(percent) * 1000))?;
+        }
+        if let Some(pids) = limits.max_pids {
+            cgroup.write("pids.max", &pids.to_string())?;
+        }
+        let procs = std::fs::OpenOptions::new()
+            .write(true)
+            .open(cgroup.path.join("cgroup.procs"))?;
+        Ok((cgroup, procs))
+    }
+
+    fn write(&self, file: &str, value: &str) -> std::io::Result<()> {
+        std::fs::write(self.path.join(file), value)
+            .map_err(|e| std::io::Error::new(e.kind(), format!("{file}: {e}")))
+    }
+
+    pub fn hits(&self) -> LimitHits {
+        LimitHits {
+            oom_kills: self.event("memory.events", "oom_kill"),
+            pids_max: self.event("pids.events", "max"),
+        }
+    }
+
+    /// A counter from one of the `*.events` files; 0 if it isn't there.
+    fn event(&self, file: &str, key: &str) -> u64 {
+        std::fs::read_to_string(self.path.join(file))
+            .ok()
+            .and_then(|events| {
+                events.lines().find_map(|line| {
+                    let (name, count) = line.split_once(' ')?;
+                    (name == key).then(|| count.trim().parse().ok())?
+                })
+            })
+            .unwrap_or(0)
+    }
+}
+
+impl Drop for SessionCgroup {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir(&self.path);
+    }
+}
+
+/// Whether the unified cgroup v2 hierarchy is mounted.
+pub fn cgroup_v2_mounted() -> bool {
+    cfg!(target_os = "linux") && Path::new("/sys/fs/cgroup/cgroup.controllers").exists()
+}
+
+/// The cgroup sessions are created under, set up on first use; `None` when
+/// cgroup v2 isn't available or not delegated to this user.
+fn cgroup_root() -> Option<&'static Path> {
+    static ROOT: OnceLock<Option<PathBuf>> = OnceLock::new();
+    ROOT.get_or_init(|| match setup_cgroup_root() {
+        Ok(root) => {
+            tracing::info!("Session limits use cgroups under {}", root.display());
+            Some(root)
+        }
+        Err(reason) => {
+            tracing::warn!("Session limits fall back to rlimits: {reason}");
+            None
+        }
+    })
+    .as_deref()
+}
+
+#[cfg(target_os = "linux")]
+fn setup_cgroup_root() -> Result<PathBuf, String> {
+    let mount = Path::new("/sys/fs/cgroup");
+    if !cgroup_v2_mounted() {
+        return Err("cgroup v2 isn't mounted at /sys/fs/cgroup".into());
+    }
+    let own = std::fs::read_to_string("/proc/self/cgroup").map_err(|e| e.to_string())?;
+    let relative = own
+        .lines()
+        .find_map(|line| line.strip_prefix("0::"))
+        .ok_or("this process isn't in a cgroup v2 hierarchy")?;
+    let root = mount.join(relative.trim_start_matches('/'));
+
+    let read = |file: &str| std::fs::read_to_string(root.join(file)).unwrap_or_default();
+    let available = read("cgroup.controllers");
+    let wanted: Vec<&str> = ["memory", "cpu", "pids"]
+        .into_iter()
+        .filter(|c| available.split_whitespace().any(|a| a == *c))
+        .collect();
+    if wanted.is_empty() {
+        return Err(format!(
+            "no memory, cpu or pids controller is delegated to {}",
+            root.display()
+        ));
+    }
+
+    let enabled = read("cgroup.subtree_control");
+    if !wanted
+        .iter()
+        .all(|c| enabled.split_whitespace().any(|e| e == *c))
+    {
+        // A cgroup that hands controllers to its children can't hold
+        // processes itself, so the server moves into a leaf of its own
+        let leaf = root.join("server");
+        if let Err(e) = std::fs::create_dir(&leaf)
+            && e.kind() != std::io::ErrorKind::AlreadyExists
+        {
+            return Err(format!("{}: {e}", leaf.display()));
+        }
+        move_self(&leaf).map_err(|e| format!("{}: {e}", leaf.display()))?;
+        let control = wanted
+            .iter()
+            .map(|c| format!("+{c}"))
+            .collect::<Vec<_>>()
+            .join(" ");
+        if let Err(e) = std::fs::write(root.join("cgroup.subtree_control"), control) {
+            // Others share the cgroup, e.g. the shell that started us
+            let _ = move_self(&root);
+            let _ = std::fs::remove_dir(&leaf);
+            return Err(format!(
+                "can't enable controllers in {}: {e}",
+                root.display()
+            ));
+        }
+    }
+
+    // Cgroups of sessions from an earlier run, once their processes are gone
+    if let Ok(entries) = std::fs::read_dir(&root) {
+        for entry in entries.flatten() {
+            if entry.file_name().to_string_lossy().starts_with("session-") {
+                let _ = std::fs::remove_dir(entry.path());
+            }
+        }
+    }
+    Ok(root)
+}
+
+#[cfg(target_os = "linux")]
+fn move_self(cgroup: &Path) -> std::io::Result<()> {
+    use std::io::Write;
+    std::fs::OpenOptions::new()
+        .write(true)
+        .open(cgroup.join("cgroup.procs"))?
+        .write_all(std::process::id().to_string().as_bytes())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn setup_cgroup_root() -> Result<PathBuf, String> {
+    Err("cgroups are only available on Linux".into())
+}
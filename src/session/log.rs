@@ -1,65 +1,226 @@
 use std::collections::VecDeque;
-use std::path::PathBuf;
 
+use bytes::Bytes;
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use tokio::sync::broadcast;
+
+use crate::error::ForgeError;
+use crate::session::feed::{FeedConfig, FeedSender, OutputFeed};
+use crate::session::query::parse_since;
+use crate::session::writer::LogWriter;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct LogEntry {
+    /// Position in the session's output, starting at 1 and never reused
+    #[serde(default)]
+    pub seq: u64,
     pub timestamp: DateTime<Utc>,
-    pub data: String,
+    /// Output exactly as the PTY produced it; base64 in JSON
+    #[serde(with = "base64_bytes")]
+    pub data: Bytes,
+}
+
+mod base64_bytes {
+    use base64::Engine;
+    use base64::engine::general_purpose::STANDARD;
+    use bytes::Bytes;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(data: &Bytes, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_str(&STANDARD.encode(data))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Bytes, D::Error> {
+        let encoded = String::deserialize(d)?;
+        STANDARD
+            .decode(encoded)
+            .map(Bytes::from)
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+/// Buffered entries after some sequence number.
+pub struct LogSlice {
+    pub entries: Vec<LogEntry>,
+    /// Entries after the requested one that were already evicted
+    pub missed: u64,
+    /// Sequence number of the newest entry, 0 if there is none
+    pub last_seq: u64,
 }
 
+/// Recent output, bounded by total bytes rather than entry count since each
+/// entry is one PTY read of any size.
 pub struct SessionLog {
     buffer: VecDeque<LogEntry>,
-    max_lines: usize,
-    pub broadcast_tx: broadcast::Sender<LogEntry>,
-    log_file: Option<PathBuf>,
+    buffered_bytes: usize,
+    max_bytes: usize,
+    next_seq: u64,
+    /// Clients following live output
+    clients: Vec<FeedSender>,
+    feed: FeedConfig,
+    closed: bool,
+    writer: Option<LogWriter>,
 }
 
 impl SessionLog {
-    pub fn new(max_lines: usize, log_file: Option<PathBuf>) -> Self {
-        let (broadcast_tx, _) = broadcast::channel(1000);
+    pub fn new(max_bytes: usize, feed: FeedConfig, writer: Option<LogWriter>) -> Self {
         Self {
-            buffer: VecDeque::with_capacity(max_lines),
-            max_lines,
-            broadcast_tx,
-            log_file,
+            buffer: VecDeque::new(),
+            buffered_bytes: 0,
+            max_bytes,
+            next_seq: 1,
+            clients: Vec::new(),
+            feed,
+            closed: false,
+            writer,
         }
     }
 
-    pub fn push(&mut self, data: String) {
+    pub fn push(&mut self, data: Bytes) {
         let entry = LogEntry {
+            seq: self.next_seq,
             timestamp: Utc::now(),
             data,
         };
+        self.next_seq += 1;
 
-        if self.buffer.len() >= self.max_lines {
-            self.buffer.pop_front();
+        // Evict oldest entries to fit; the newest entry is always kept
+        while self.buffered_bytes + entry.data.len() > self.max_bytes
+            && let Some(old) = self.buffer.pop_front()
+        {
+            self.buffered_bytes -= old.data.len();
         }
+        self.buffered_bytes += entry.data.len();
         self.buffer.push_back(entry.clone());
 
-        // Best-effort broadcast; receivers may have been dropped
-        let _ = self.broadcast_tx.send(entry.clone());
+        // Clients that went away or fell too far behind are dropped
+        self.clients.retain(|client| client.push(&entry));
 
-        // Append to log file if configured
-        if let Some(ref path) = self.log_file
-            && let Ok(mut file) = std::fs::OpenOptions::new()
-                .create(true)
-                .append(true)
-                .open(path)
-        {
-            use std::io::Write;
-            let _ = writeln!(file, "{}", entry.data);
+        // Queue for output.log if configured
+        if let Some(ref mut writer) = self.writer {
+            writer.write(entry.data);
+        }
+    }
+
+    /// Bytes of output currently held in memory.
+    pub fn buffered_bytes(&self) -> usize {
+        self.buffered_bytes
+    }
+
+    /// See [`LogWriter::done`]; `None` without a writer.
+    pub fn writer_done(&self) -> Option<tokio::sync::watch::Receiver<()>> {
+        self.writer.as_ref().map(LogWriter::done)
+    }
+
+    /// Flush and close the log file and end every client's feed once the
+    /// session's output has ended.
+    pub fn close(&mut self) {
+        self.closed = true;
+        for client in self.clients.drain(..) {
+            client.close();
         }
+        if let Some(ref writer) = self.writer {
+            writer.close();
+        }
+    }
+
+    /// Buffered entries after `seq` plus a feed of everything that follows,
+    /// with nothing missed or repeated in between.
+    pub fn follow(&mut self, seq: u64) -> (LogSlice, OutputFeed) {
+        let (sender, feed) = OutputFeed::new(self.feed);
+        if self.closed {
+            sender.close();
+        } else {
+            self.clients.push(sender);
+        }
+        (self.after(seq), feed)
+    }
+
+    /// Entries with a sequence number above `seq`, noting how many of those
+    /// have already been evicted.
+    pub fn after(&self, seq: u64) -> LogSlice {
+        let first = self.buffer.front().map_or(self.next_seq, |e| e.seq);
+        LogSlice {
+            entries: self
+                .buffer
+                .iter()
+                .filter(|e| e.seq > seq)
+                .cloned()
+                .collect(),
+            missed: first.saturating_sub(seq + 1),
+            last_seq: self.next_seq - 1,
+        }
+    }
+}
+
+/// Range of log history to return, as `?after_seq=&since=&tail=&limit=` on
+/// the log endpoints.
+#[derive(Debug, Default, Deserialize)]
+pub struct LogQuery {
+    /// Only entries after this sequence number
+    pub after_seq: Option<u64>,
+    /// RFC 3339 timestamp or a relative age such as `30m`
+    pub since: Option<String>,
+    /// Only the last this many lines
+    pub tail: Option<usize>,
+    /// At most this many entries, oldest first
+    pub limit: Option<usize>,
+}
+
+impl LogQuery {
+    /// Apply `since`, `tail` and `limit` to entries already cut at `after_seq`.
+    pub fn select(&self, entries: Vec<LogEntry>) -> Result<Vec<LogEntry>, ForgeError> {
+        let since = self
+            .since
+            .as_deref()
+            .map(|s| parse_since(s, Utc::now()))
+            .transpose()
+            .map_err(|e| ForgeError::validation("since", e))?;
+
+        let mut entries = entries;
+        if let Some(since) = since {
+            entries.retain(|e| e.timestamp >= since);
+        }
+        if let Some(lines) = self.tail {
+            entries = tail(entries, lines);
+        }
+        if let Some(limit) = self.limit {
+            entries.truncate(limit);
+        }
+        Ok(entries)
     }
+}
 
-    pub fn snapshot(&self) -> Vec<LogEntry> {
-        self.buffer.iter().cloned().collect()
+/// Keep only the last `lines` lines of output, cutting the first kept entry
+/// at a line boundary.
+pub fn tail(entries: Vec<LogEntry>, lines: usize) -> Vec<LogEntry> {
+    if lines == 0 {
+        return Vec::new();
     }
 
-    pub fn subscribe(&self) -> broadcast::Receiver<LogEntry> {
-        self.broadcast_tx.subscribe()
+    // A trailing newline ends the last line rather than starting a new one
+    let ends_with_newline = entries.last().is_some_and(|e| e.data.ends_with(b"\n"));
+    let mut remaining = lines + usize::from(ends_with_newline);
+    let mut kept = Vec::new();
+    for mut entry in entries.into_iter().rev() {
+        let newlines: Vec<usize> = entry
+            .data
+            .iter()
+            .enumerate()
+            .filter(|(_, b)| **b == b'\n')
+            .map(|(i, _)| i)
+            .collect();
+        if newlines.len() >= remaining {
+            let cut = newlines[newlines.len() - remaining] + 1;
+            entry.data = entry.data.slice(cut..);
+            if !entry.data.is_empty() {
+                kept.push(entry);
+            }
+            break;
+        }
+        remaining -= newlines.len();
+        kept.push(entry);
     }
+    kept.reverse();
+    kept
 }
@@ -1,105 +1,292 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::path::PathBuf;
-use std::sync::Arc;
+use std::sync::{Arc, RwLock};
 
 use anyhow::Result;
-use tokio::sync::{RwLock, mpsc, oneshot, watch};
+use tokio::sync::{oneshot, watch};
+use tracing::Instrument;
 use uuid::Uuid;
 
-use crate::config::Config;
+use crate::config::{Config, ResourceLimits, ShutdownPolicy};
 use crate::error::ForgeError;
-use crate::session::log::SessionLog;
-use crate::session::model::{SessionMeta, SessionStatus, ToolKind};
-
-fn sock_dir() -> PathBuf {
+use crate::metrics::{Metrics, SessionCounters, Transport};
+use crate::search::SearchIndex;
+use crate::server::runtime::RuntimeState;
+use crate::session::actor::{PtyCommand, SessionHandle, SessionProcess};
+use crate::session::env::SessionEnv;
+use crate::session::feed::{FeedItem, OutputFeed};
+use crate::session::limits::PreparedLimits;
+use crate::session::log::{LogSlice, SessionLog};
+use crate::session::model::{Activity, SessionMeta, SessionStatus, SessionSummary, ToolKind};
+use crate::session::persist;
+use crate::session::prompt::PermissionPrompt;
+use crate::session::query::SessionFilter;
+use crate::session::resources::{ResourceReport, Sampler};
+use crate::session::writer::{self, LogWriter};
+use crate::store::{SqliteStore, Store};
+
+pub fn sock_dir() -> PathBuf {
     PathBuf::from("/tmp/lineforge")
 }
 
-pub enum PtyCommand {
-    Input(Vec<u8>),
-    Resize { rows: u16, cols: u16 },
-}
-
-pub struct LiveSession {
-    pub meta: SessionMeta,
-    pub log: SessionLog,
-    pub input_tx: mpsc::Sender<PtyCommand>,
-    pub size_tx: watch::Sender<(u16, u16)>,
+/// Everything needed to spawn a session.
+pub struct SpawnOptions {
+    pub name: String,
+    pub tool: ToolKind,
+    pub working_dir: PathBuf,
+    pub extra_args: Vec<String>,
+    pub rows: u16,
+    pub cols: u16,
+    /// Extra environment variables, applied last
+    pub env: BTreeMap<String, String>,
+    /// Env files loaded before `env`, relative to `working_dir`
+    pub env_files: Vec<PathBuf>,
+    /// Load the project's `.env`/`.envrc`; falls back to the tool config
+    pub load_dotenv: Option<bool>,
+    /// Overrides the tool's and global limits, field by field
+    pub limits: ResourceLimits,
 }
 
+/// Registry of session actors. The map is only written when a session is
+/// spawned and is never held across an await. Sessions from earlier runs
+/// are only in the store.
 #[derive(Clone)]
 pub struct SessionManager {
-    pub sessions: Arc<RwLock<HashMap<Uuid, Arc<RwLock<LiveSession>>>>>,
+    sessions: Arc<RwLock<HashMap<Uuid, SessionHandle>>>,
     pub config: Config,
+    pub search: Arc<SearchIndex>,
+    pub store: Arc<dyn Store>,
+    pub metrics: Arc<Metrics>,
 }
 
 impl SessionManager {
-    pub fn new(config: Config) -> Self {
+    pub fn new(config: Config, store: Arc<dyn Store>) -> Self {
         Self {
             sessions: Arc::new(RwLock::new(HashMap::new())),
+            search: Arc::new(config.search_index()),
             config,
+            store,
+            metrics: Arc::new(Metrics::default()),
         }
     }
 
-    pub async fn list(&self) -> Vec<SessionMeta> {
-        let sessions = self.sessions.read().await;
-        let mut metas = Vec::new();
-        for session in sessions.values() {
-            let s = session.read().await;
-            metas.push(s.meta.clone());
+    fn handle(&self, id: Uuid) -> Result<SessionHandle, ForgeError> {
+        self.sessions
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(&id)
+            .cloned()
+            .ok_or(ForgeError::SessionNotFound(id))
+    }
+
+    fn handles(&self) -> Vec<SessionHandle> {
+        self.sessions
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .values()
+            .cloned()
+            .collect()
+    }
+
+    /// Byte counters of the sessions this server has run.
+    pub fn session_counters(&self) -> Vec<(SessionMeta, Arc<SessionCounters>)> {
+        self.handles()
+            .into_iter()
+            .map(|h| (h.meta(), h.counters.clone()))
+            .collect()
+    }
+
+    /// Recent resource usage of a session; empty for one this server
+    /// hasn't run.
+    pub async fn resources(&self, id: Uuid) -> Result<ResourceReport> {
+        let interval = self.config.resource_sample_secs;
+        match self.handle(id) {
+            Ok(handle) => Ok(handle.resources.report(interval)),
+            Err(_) => {
+                self.get(id).await?;
+                Ok(ResourceReport::empty(interval))
+            }
         }
-        metas.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+    }
+
+    /// Sample the process tree of every running session from `/proc`, every
+    /// `resource_sample_secs`. Runs until the server exits.
+    pub async fn sample_resources(self: Arc<Self>) {
+        let interval = std::time::Duration::from_secs(self.config.resource_sample_secs);
+        let mut ticker = tokio::time::interval(interval);
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        let mut sampler = Sampler::default();
+        loop {
+            ticker.tick().await;
+            let (running, done): (Vec<_>, Vec<_>) = self
+                .handles()
+                .into_iter()
+                .map(|h| (h.meta().pid, h))
+                .partition(|(pid, h)| pid.is_some() && h.is_running());
+            for (_, handle) in done {
+                handle.resources.record(None);
+            }
+            let roots: Vec<u32> = running.iter().filter_map(|(pid, _)| *pid).collect();
+            let sampled = tokio::task::spawn_blocking(move || {
+                let trees = sampler.sample(&roots);
+                (sampler, trees)
+            })
+            .await;
+            let Ok((returned, trees)) = sampled else {
+                return;
+            };
+            sampler = returned;
+            for ((_, handle), tree) in running.iter().zip(trees) {
+                handle.resources.record(tree);
+            }
+        }
+    }
+
+    pub async fn list(&self) -> Vec<SessionMeta> {
+        let mut metas: Vec<SessionMeta> = self.handles().iter().map(|h| h.meta()).collect();
+        metas.sort_by_key(|m| std::cmp::Reverse(m.created_at));
         metas
     }
 
+    /// Sessions matching `filter`, past and present, newest first, with the
+    /// total number of matches before paging.
+    pub async fn query(&self, filter: SessionFilter) -> Result<(Vec<SessionSummary>, usize)> {
+        let (metas, total) = self
+            .with_store(move |store| store.list_sessions(&filter))
+            .await?;
+        let now = chrono::Utc::now();
+        let page = metas
+            .into_iter()
+            .map(|stored| {
+                // The store only hears about status changes; live sessions
+                // know when they last produced output
+                let (meta, awaiting) = match self.handle(stored.id) {
+                    Ok(handle) => (handle.meta(), handle.prompt().is_some()),
+                    Err(_) => (stored, false),
+                };
+                let activity = Activity::of(&meta, awaiting, now);
+                SessionSummary { meta, activity }
+            })
+            .collect();
+        Ok((page, total))
+    }
+
+    /// Run a store call on the blocking pool. The database may be busy with
+    /// the sessions' recorders for up to its busy timeout, which must not
+    /// hold up a runtime thread.
+    pub async fn with_store<T, F>(&self, f: F) -> Result<T>
+    where
+        F: FnOnce(&dyn Store) -> Result<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        let store = self.store.clone();
+        tokio::task::spawn_blocking(move || f(store.as_ref())).await?
+    }
+
     pub async fn get(&self, id: Uuid) -> Result<SessionMeta> {
-        let sessions = self.sessions.read().await;
-        let session = sessions.get(&id).ok_or(ForgeError::SessionNotFound(id))?;
-        let s = session.read().await;
-        Ok(s.meta.clone())
+        if let Ok(handle) = self.handle(id) {
+            return Ok(handle.meta());
+        }
+        Ok(self
+            .with_store(move |store| store.session(id))
+            .await?
+            .ok_or(ForgeError::SessionNotFound(id))?)
     }
 
-    #[allow(dead_code)]
-    pub async fn resolve_id(&self, prefix: &str) -> Result<Uuid> {
+    /// Resolve a full UUID, a unique UUID prefix or a unique session name.
+    pub async fn resolve_id(&self, query: &str) -> Result<Uuid, ForgeError> {
         // Try full UUID first
-        if let Ok(id) = prefix.parse::<Uuid>() {
-            let sessions = self.sessions.read().await;
-            if sessions.contains_key(&id) {
+        if let Ok(id) = query.parse::<Uuid>() {
+            if self.handle(id).is_ok()
+                || self
+                    .with_store(move |store| store.session(id))
+                    .await?
+                    .is_some()
+            {
                 return Ok(id);
             }
-            return Err(ForgeError::SessionNotFound(id).into());
+            return Err(ForgeError::SessionNotFound(id));
         }
 
-        // Try prefix match
-        let sessions = self.sessions.read().await;
-        let matches: Vec<_> = sessions
-            .keys()
-            .filter(|id| id.to_string().starts_with(prefix))
-            .cloned()
-            .collect();
+        // Exact name matches take precedence over prefix matches
+        let mut by_name = Vec::new();
+        let mut by_prefix = Vec::new();
+        for handle in self.handles() {
+            let meta = handle.meta();
+            if meta.name == query {
+                by_name.push((meta.id, meta.name));
+            } else if meta.id.to_string().starts_with(query) {
+                by_prefix.push((meta.id, meta.name));
+            }
+        }
+        let mut matches = if by_name.is_empty() {
+            by_prefix
+        } else {
+            by_name
+        };
+        // Past sessions only count when no session of this run matches, so
+        // an old session can't make a running one's name ambiguous
+        if matches.is_empty() {
+            let query = query.to_string();
+            matches = self
+                .with_store(move |store| store.find_sessions(&query))
+                .await?
+                .into_iter()
+                .map(|m| (m.id, m.name))
+                .collect();
+        }
 
         match matches.len() {
-            0 => anyhow::bail!("No session matching prefix: {prefix}"),
-            1 => Ok(matches[0]),
-            n => anyhow::bail!("{n} sessions match prefix '{prefix}', be more specific"),
+            0 => Err(ForgeError::NoSessionMatch(query.to_string())),
+            1 => Ok(matches[0].0),
+            _ => {
+                matches.sort_by(|a, b| a.1.cmp(&b.1));
+                Err(ForgeError::AmbiguousSession {
+                    query: query.to_string(),
+                    candidates: matches,
+                })
+            }
         }
     }
 
-    pub async fn spawn(
-        &self,
-        name: String,
-        tool: ToolKind,
-        working_dir: PathBuf,
-        extra_args: Vec<String>,
-        rows: u16,
-        cols: u16,
-    ) -> Result<SessionMeta> {
+    pub async fn spawn(&self, opts: SpawnOptions) -> Result<SessionMeta> {
+        let tool = opts.tool.to_string();
+        let result = self.spawn_session(opts).await;
+        self.metrics.session_spawned(&tool, result.is_ok());
+        result
+    }
+
+    #[tracing::instrument(
+        name = "spawn",
+        skip_all,
+        fields(session = tracing::field::Empty, tool = %opts.tool, name = %opts.name)
+    )]
+    async fn spawn_session(&self, opts: SpawnOptions) -> Result<SessionMeta> {
+        let SpawnOptions {
+            name,
+            tool,
+            working_dir,
+            extra_args,
+            rows,
+            cols,
+            env,
+            env_files,
+            load_dotenv,
+            limits,
+        } = opts;
+
+        let tool_config = self.config.tool_config(&tool);
+        let load_dotenv = load_dotenv.unwrap_or(tool_config.load_dotenv);
+        let session_env =
+            SessionEnv::resolve(&tool_config, &working_dir, &env_files, &env, load_dotenv)?;
+        let limits = limits.or(&self.config.limits(&tool));
+        limits.validate()?;
+
         let id = Uuid::new_v4();
-        let session_dir = Config::sessions_dir().join(id.to_string());
+        tracing::Span::current().record("session", tracing::field::display(id));
+        let session_dir = persist::session_dir(id);
         std::fs::create_dir_all(&session_dir)?;
 
-        let tool_path = crate::session::pty::resolve_tool_path(&self.config, &tool)?;
-
         let mut extra_args = extra_args;
         if self.config.yolo_mode {
             let yolo_flag = match tool {
@@ -111,6 +298,17 @@ impl SessionManager {
             }
         }
 
+        // Preflight: resolving the login environment may run the user's shell
+        let launch = {
+            let config = self.config.clone();
+            let tool = tool.clone();
+            let args = extra_args.clone();
+            tokio::task::spawn_blocking(move || {
+                crate::session::pty::launch_spec(&config, &tool, &args)
+            })
+            .await??
+        };
+
         // Create PTY pair
         let (pty, pts) = pty_process::open()
             .map_err(|e| ForgeError::Pty(format!("Failed to create PTY: {e}")))?;
@@ -120,17 +318,29 @@ impl SessionManager {
             .map_err(|e| ForgeError::Pty(format!("Failed to resize PTY: {e}")))?;
 
         // Build and spawn command (builder methods consume self)
-        let child = pty_process::Command::new(&tool_path)
-            .args(&extra_args)
+        let mut command = pty_process::Command::new(&launch.program)
+            .args(&launch.args)
             .current_dir(&working_dir)
+            .envs(&launch.env)
+            .envs(&session_env.vars)
             .env("TERM", "xterm-256color")
-            .env("COLORTERM", "truecolor")
+            .env("COLORTERM", "truecolor");
+        let prepared = PreparedLimits::prepare(id, &limits);
+        if let Some(prepared) = &prepared {
+            command = unsafe { command.pre_exec(prepared.pre_exec()) };
+        }
+        let child = command
             .spawn(pts)
-            .map_err(|e| ForgeError::Pty(format!("Failed to spawn {tool_path}: {e}")))?;
+            .map_err(|e| ForgeError::Pty(format!("Failed to spawn {}: {e}", launch.program)))?;
+        let (limits, cgroup) = match prepared.map(PreparedLimits::spawned) {
+            Some((applied, cgroup)) => (Some(applied), cgroup),
+            None => (None, None),
+        };
 
         let pid = child.id();
         let now = chrono::Utc::now();
         let meta = SessionMeta {
+            schema_version: persist::SCHEMA_VERSION,
             id,
             name,
             tool,
@@ -140,66 +350,57 @@ impl SessionManager {
             updated_at: now,
             pid,
             extra_args,
+            env: session_env.masked(),
+            env_files: session_env.files,
+            last_output_at: None,
+            limits,
         };
 
-        // Save meta to disk
-        let meta_path = session_dir.join("meta.json");
-        let meta_json = serde_json::to_string_pretty(&meta)?;
-        std::fs::write(&meta_path, meta_json)?;
+        let saved = meta.clone();
+        self.with_store(move |store| persist::persist(store, &saved))
+            .await?;
 
         // Set up log
-        let log_file = session_dir.join("output.log");
-        let log = SessionLog::new(self.config.max_log_lines, Some(log_file));
-
-        // Set up input channel
-        let (input_tx, input_rx) = mpsc::channel::<PtyCommand>(256);
-
-        // Watch channel for PTY size changes (webview syncs via SSE)
-        let (size_tx, _) = watch::channel((rows, cols));
-
-        let live = Arc::new(RwLock::new(LiveSession {
-            meta: meta.clone(),
+        let writer = LogWriter::spawn(session_dir.clone(), self.config.log_rotation());
+        let log = SessionLog::new(
+            self.config.log_buffer_bytes(),
+            self.config.feed_config(),
+            Some(writer),
+        );
+
+        self.search.add_session(&meta);
+
+        let process = SessionProcess {
+            pty,
+            child,
+            rows,
+            cols,
+            cgroup,
+        };
+        let handle = crate::session::actor::spawn(
+            meta.clone(),
+            process,
             log,
-            input_tx,
-            size_tx,
-        }));
-
-        {
-            let mut sessions = self.sessions.write().await;
-            sessions.insert(id, live.clone());
-        }
-
-        // Spawn read/write tasks
-        let sessions_ref = self.sessions.clone();
-        tokio::spawn(async move {
-            run_pty_io(pty, child, input_rx, sessions_ref, id).await;
-        });
+            self.config.approval.clone(),
+            self.search.clone(),
+            self.store.clone(),
+        );
+        self.sessions
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(id, handle.clone());
 
         // Start Unix socket listener for attach
         let sock_base = sock_dir();
         std::fs::create_dir_all(&sock_base)?;
         let attach_sock = sock_base.join(format!("{id}.sock"));
-        let input_tx_attach = {
-            let s = live.read().await;
-            s.input_tx.clone()
-        };
-        let broadcast_tx_attach = {
-            let s = live.read().await;
-            s.log.broadcast_tx.clone()
-        };
-        let sessions_attach = self.sessions.clone();
         let (sock_ready_tx, sock_ready_rx) = oneshot::channel::<()>();
-        tokio::spawn(async move {
-            run_attach_listener(
-                attach_sock,
-                input_tx_attach,
-                broadcast_tx_attach,
-                sessions_attach,
-                id,
-                sock_ready_tx,
-            )
-            .await;
-        });
+        tokio::spawn(run_attach_listener(
+            attach_sock,
+            handle,
+            self.metrics.clone(),
+            sock_ready_tx,
+        ));
 
         // Wait for the attach socket to be ready before returning
         let _ = sock_ready_rx.await;
@@ -207,192 +408,226 @@ impl SessionManager {
         Ok(meta)
     }
 
+    #[tracing::instrument(name = "input", skip(self, data), fields(session = %id, bytes = data.len()))]
     pub async fn send_input(&self, id: Uuid, data: Vec<u8>) -> Result<()> {
-        let sessions = self.sessions.read().await;
-        let session = sessions.get(&id).ok_or(ForgeError::SessionNotFound(id))?;
-        let s = session.read().await;
-        if s.meta.status != SessionStatus::Running {
-            return Err(ForgeError::SessionAlreadyStopped(id).into());
-        }
-        s.input_tx
-            .send(PtyCommand::Input(data))
-            .await
-            .map_err(|_| ForgeError::Pty("Input channel closed".into()))?;
+        self.handle(id)?.send(PtyCommand::Input(data)).await?;
         Ok(())
     }
 
     pub async fn resize(&self, id: Uuid, rows: u16, cols: u16) -> Result<()> {
-        let sessions = self.sessions.read().await;
-        let session = sessions.get(&id).ok_or(ForgeError::SessionNotFound(id))?;
-        let s = session.read().await;
-        if s.meta.status != SessionStatus::Running {
-            return Err(ForgeError::SessionAlreadyStopped(id).into());
-        }
-        s.input_tx
+        self.handle(id)?
             .send(PtyCommand::Resize { rows, cols })
-            .await
-            .map_err(|_| ForgeError::Pty("Input channel closed".into()))?;
-        let _ = s.size_tx.send((rows, cols));
+            .await?;
         Ok(())
     }
 
+    #[tracing::instrument(name = "stop", skip(self), fields(session = %id))]
     pub async fn stop(&self, id: Uuid) -> Result<()> {
-        let sessions = self.sessions.read().await;
-        let session = sessions.get(&id).ok_or(ForgeError::SessionNotFound(id))?;
-        let mut s = session.write().await;
-        if s.meta.status != SessionStatus::Running {
-            return Err(ForgeError::SessionAlreadyStopped(id).into());
-        }
-
-        // Send SIGTERM via kill
-        if let Some(pid) = s.meta.pid {
-            unsafe {
-                libc::kill(pid as i32, libc::SIGTERM);
-            }
-        }
+        self.handle(id)?.stop().await?;
+        Ok(())
+    }
 
-        s.meta.status = SessionStatus::Stopped;
-        s.meta.updated_at = chrono::Utc::now();
+    pub async fn get_log_after(&self, id: Uuid, seq: u64) -> Result<LogSlice> {
+        Ok(self.handle(id)?.log_after(seq).await?)
+    }
 
-        // Update meta on disk
-        let meta_path = Config::sessions_dir()
-            .join(id.to_string())
-            .join("meta.json");
-        if let Ok(json) = serde_json::to_string_pretty(&s.meta) {
-            let _ = std::fs::write(&meta_path, json);
-        }
+    /// Buffered entries after `seq` and a feed of the session's output from
+    /// there on.
+    pub async fn follow_logs(&self, id: Uuid, seq: u64) -> Result<(LogSlice, OutputFeed)> {
+        Ok(self.handle(id)?.follow(seq).await?)
+    }
 
-        // Clean up attach socket
-        let sock_file = sock_dir().join(format!("{id}.sock"));
-        let _ = std::fs::remove_file(&sock_file);
+    /// Ids of running sessions with a permission prompt on screen.
+    pub async fn awaiting_approval(&self) -> HashSet<Uuid> {
+        self.handles()
+            .iter()
+            .filter(|h| h.is_running() && h.prompt().is_some())
+            .map(|h| h.id)
+            .collect()
+    }
 
-        Ok(())
+    pub async fn current_prompt(&self, id: Uuid) -> Result<Option<PermissionPrompt>> {
+        Ok(self.handle(id)?.prompt())
     }
 
-    pub async fn get_log_snapshot(&self, id: Uuid) -> Result<Vec<crate::session::log::LogEntry>> {
-        let sessions = self.sessions.read().await;
-        let session = sessions.get(&id).ok_or(ForgeError::SessionNotFound(id))?;
-        let s = session.read().await;
-        Ok(s.log.snapshot())
+    pub async fn subscribe_prompt(
+        &self,
+        id: Uuid,
+    ) -> Result<watch::Receiver<Option<PermissionPrompt>>> {
+        Ok(self.handle(id)?.subscribe_prompt())
     }
 
-    pub async fn subscribe_logs(
+    /// Answer the pending prompt by sending the key sequence for `option`.
+    /// With `prompt_id`, fails if the pending prompt is no longer that one.
+    pub async fn answer_prompt(
         &self,
         id: Uuid,
-    ) -> Result<tokio::sync::broadcast::Receiver<crate::session::log::LogEntry>> {
-        let sessions = self.sessions.read().await;
-        let session = sessions.get(&id).ok_or(ForgeError::SessionNotFound(id))?;
-        let s = session.read().await;
-        Ok(s.log.subscribe())
+        option: usize,
+        prompt_id: Option<&str>,
+    ) -> Result<()> {
+        let prompt = self
+            .current_prompt(id)
+            .await?
+            .ok_or(ForgeError::NoPendingPrompt(id))?;
+        if prompt_id.is_some_and(|p| p != prompt.id) {
+            return Err(ForgeError::PromptChanged(id).into());
+        }
+        let opt = prompt
+            .options
+            .get(option)
+            .ok_or(ForgeError::InvalidPromptOption(
+                option,
+                prompt.options.len(),
+            ))?;
+        self.send_input(id, opt.key.clone().into_bytes()).await
     }
 
     pub async fn subscribe_size(&self, id: Uuid) -> Result<watch::Receiver<(u16, u16)>> {
-        let sessions = self.sessions.read().await;
-        let session = sessions.get(&id).ok_or(ForgeError::SessionNotFound(id))?;
-        let s = session.read().await;
-        Ok(s.size_tx.subscribe())
+        Ok(self.handle(id)?.subscribe_size())
     }
-}
 
-async fn run_pty_io(
-    pty: pty_process::Pty,
-    mut child: tokio::process::Child,
-    mut input_rx: mpsc::Receiver<PtyCommand>,
-    sessions: Arc<RwLock<HashMap<Uuid, Arc<RwLock<LiveSession>>>>>,
-    id: Uuid,
-) {
-    use tokio::io::{AsyncReadExt, AsyncWriteExt};
-
-    let (mut pty_reader, mut pty_writer) = pty.into_split();
+    /// Apply `shutdown_policy` to running sessions, then save every
+    /// session's metadata. Called once the server stops taking requests.
+    pub async fn shutdown(&self) {
+        let timeout = std::time::Duration::from_secs(self.config.shutdown_timeout_secs);
+        let running: Vec<SessionHandle> = self
+            .handles()
+            .into_iter()
+            .filter(|h| h.is_running())
+            .collect();
 
-    // Write task: forward input and resize commands to PTY
-    let write_handle = tokio::spawn(async move {
-        while let Some(cmd) = input_rx.recv().await {
-            match cmd {
-                PtyCommand::Input(data) => {
-                    if pty_writer.write_all(&data).await.is_err() {
-                        break;
-                    }
+        if !running.is_empty() {
+            match self.config.shutdown_policy {
+                ShutdownPolicy::Stop => {
+                    tracing::info!("Stopping {} running sessions", running.len());
+                    stop_all(&running, timeout).await;
                 }
-                PtyCommand::Resize { rows, cols } => {
-                    let _ = pty_writer.resize(pty_process::Size::new(rows, cols));
+                ShutdownPolicy::Wait => {
+                    tracing::info!(
+                        "Waiting up to {}s for {} running sessions to exit",
+                        timeout.as_secs(),
+                        running.len()
+                    );
+                    if !wait_exited(&running, timeout).await {
+                        stop_all(&running, timeout).await;
+                    }
                 }
             }
         }
-    });
-
-    // Read loop: PTY output -> broadcast + ring buffer
-    let mut buf = vec![0u8; 4096];
-    let mut leftover = Vec::new();
-    loop {
-        match pty_reader.read(&mut buf).await {
-            Ok(0) => break,
-            Ok(n) => {
-                // Prepend any leftover bytes from previous read
-                let mut data = std::mem::take(&mut leftover);
-                data.extend_from_slice(&buf[..n]);
-
-                // Find the last valid UTF-8 boundary
-                let valid_up_to = match std::str::from_utf8(&data) {
-                    Ok(_) => data.len(),
-                    Err(e) => e.valid_up_to(),
-                };
 
-                // Save incomplete trailing bytes for next read
-                if valid_up_to < data.len() {
-                    leftover = data[valid_up_to..].to_vec();
-                }
-
-                let text = String::from_utf8_lossy(&data[..valid_up_to]).to_string();
-                let sessions_guard = sessions.read().await;
-                if let Some(session) = sessions_guard.get(&id) {
-                    let mut s = session.write().await;
-                    s.log.push(text);
-                }
+        for handle in self.handles() {
+            if let Err(e) = persist::persist(self.store.as_ref(), &handle.meta()) {
+                tracing::warn!("Failed to save session {}: {e:#}", handle.id);
             }
-            Err(_) => break,
         }
     }
+}
+
+/// Stop every session that is still running and wait for the tools to exit.
+async fn stop_all(handles: &[SessionHandle], timeout: std::time::Duration) {
+    for handle in handles.iter().filter(|h| h.is_running()) {
+        let _ = handle.stop().await;
+    }
+    wait_exited(handles, timeout).await;
+}
+
+/// Whether every tool exited within `timeout`.
+async fn wait_exited(handles: &[SessionHandle], timeout: std::time::Duration) -> bool {
+    let all = futures_util::future::join_all(handles.iter().map(|h| h.exited()));
+    if tokio::time::timeout(timeout, all).await.is_ok() {
+        return true;
+    }
+    let left = handles.iter().filter(|h| h.meta().pid.is_some()).count();
+    tracing::warn!("{left} sessions didn't exit within {}s", timeout.as_secs());
+    false
+}
 
-    write_handle.abort();
+// CLI helper functions - these call out to the running server via HTTP
 
-    // Wait for child process to exit
-    let status = child.wait().await;
+/// How the CLI reaches the server: over the local control socket when the
+/// server is listening on it, otherwise over TCP with a client that trusts
+/// the server's certificate when TLS is on.
+pub struct Api {
+    pub base_url: String,
+    pub http: reqwest::Client,
+}
 
-    // Update session status (only if still Running - stop() may have already set it)
-    let sessions_guard = sessions.read().await;
-    if let Some(session) = sessions_guard.get(&id) {
-        let mut s = session.write().await;
-        if s.meta.status == SessionStatus::Running {
-            s.meta.status = match status {
-                Ok(exit) if exit.success() => SessionStatus::Stopped,
-                Ok(_) => SessionStatus::Errored("Process exited with non-zero status".into()),
-                Err(e) => SessionStatus::Errored(e.to_string()),
-            };
+const HEALTH_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(2);
+
+impl Api {
+    /// Where the server should be, without contacting it: what the running
+    /// server recorded in its state file, falling back to the config.
+    pub fn discover(config: &Config) -> Result<Self> {
+        let state = RuntimeState::read();
+        let socket = match &state {
+            Some(state) => state.control_socket.clone(),
+            None => Some(Config::private_runtime_dir()?.join("control.sock")),
+        };
+        if let Some(socket) = socket
+            && std::os::unix::net::UnixStream::connect(&socket).is_ok()
+        {
+            return Ok(Self {
+                base_url: "http://localhost".into(),
+                http: reqwest::Client::builder().unix_socket(socket).build()?,
+            });
         }
-        s.meta.updated_at = chrono::Utc::now();
-        s.meta.pid = None;
+        // Before the first start there's no self-signed certificate to pin,
+        // and no server to talk to; `connect` discovers again once one runs
+        let http = if state.is_none() && crate::tls::self_signed_pending(config) {
+            reqwest::Client::builder().build()?
+        } else {
+            crate::tls::client_builder(config)?.build()?
+        };
+        Ok(Self {
+            base_url: state.map_or_else(|| config.server_url(), |s| s.url),
+            http,
+        })
+    }
 
-        // Update meta on disk
-        let meta_path = Config::sessions_dir()
-            .join(id.to_string())
-            .join("meta.json");
-        if let Ok(json) = serde_json::to_string_pretty(&s.meta) {
-            let _ = std::fs::write(&meta_path, json);
+    /// A server that answers, started in the background first if none does
+    /// and `auto_start` is set.
+    pub async fn connect(config: &Config) -> Result<Self> {
+        let api = Self::discover(config)?;
+        // A server that recorded itself may just be busy
+        if RuntimeState::read().is_some() || api.healthy().await {
+            return Ok(api);
+        }
+        if !config.auto_start {
+            anyhow::bail!(
+                "The Lineforge server isn't running (nothing answers at {}).\n\
+                 Start it with `forge serve`, or set `auto_start = true` in {} \
+                 to have commands start it when needed.",
+                api.base_url,
+                Config::config_path().display()
+            );
         }
+        eprintln!(
+            "Starting the Lineforge server in the background (log: {})",
+            Config::server_log_path().display()
+        );
+        crate::server::daemon::start_detached(config, &["serve".into()]).await?;
+        Self::discover(config)
+    }
+
+    pub async fn healthy(&self) -> bool {
+        let request = self.http.get(format!("{}/health", self.base_url)).send();
+        tokio::time::timeout(HEALTH_TIMEOUT, request)
+            .await
+            .is_ok_and(|r| r.is_ok_and(|r| r.status().is_success()))
     }
 }
 
-// CLI helper functions - these call out to the running server via HTTP
 pub async fn create_session_cli(
     config: &Config,
     label: Option<String>,
     cwd: Option<PathBuf>,
     tool: Option<String>,
     extra_args: Vec<String>,
+    env: &crate::cli::commands::EnvArgs,
+    limits: &ResourceLimits,
 ) -> Result<Uuid> {
-    let bind = crate::config::resolve_bind_address(&config.bind);
-    let url = format!("http://{bind}:{}/api/sessions", config.port);
+    let api = Api::connect(config).await?;
+    let url = format!("{}/api/sessions", api.base_url);
     let working_dir = cwd.unwrap_or_else(|| std::env::current_dir().unwrap_or_default());
     let default_name = working_dir
         .file_name()
@@ -401,6 +636,14 @@ pub async fn create_session_cli(
         .to_string();
     let name = label.unwrap_or(default_name);
     let (term_cols, term_rows) = crossterm::terminal::size().unwrap_or((80, 24));
+    // Env files given on the command line are relative to the caller's shell
+    let cwd = std::env::current_dir().unwrap_or_default();
+    let env_files: Vec<PathBuf> = env.files.iter().map(|f| cwd.join(f)).collect();
+    let env_vars: BTreeMap<&str, &str> = env
+        .vars
+        .iter()
+        .map(|(k, v)| (k.as_str(), v.as_str()))
+        .collect();
     let body = serde_json::json!({
         "name": name,
         "tool": tool.unwrap_or_else(|| config.default_tool.clone()),
@@ -408,64 +651,359 @@ pub async fn create_session_cli(
         "extra_args": extra_args,
         "rows": term_rows,
         "cols": term_cols,
+        "env": env_vars,
+        "env_files": env_files,
+        "load_dotenv": env.dotenv.then_some(true),
+        "limits": limits,
     });
 
-    let client = reqwest::Client::new();
-    let resp = client.post(&url).json(&body).send().await?;
+    let resp = api.http.post(&url).json(&body).send().await?;
 
     if resp.status().is_success() {
         let meta: SessionMeta = resp.json().await?;
         Ok(meta.id)
     } else {
-        let text = resp.text().await?;
-        anyhow::bail!("Failed to create session: {text}");
+        Err(api_error("Failed to create session", resp).await)
     }
 }
 
-pub async fn list_sessions_cli() -> Result<()> {
+pub async fn list_sessions_cli(args: &crate::cli::commands::ListArgs) -> Result<()> {
     let config = Config::load(None)?;
-    let bind = crate::config::resolve_bind_address(&config.bind);
-    let url = format!("http://{bind}:{}/api/sessions", config.port);
+    let api = Api::connect(&config).await?;
+    let url = format!("{}/api/sessions", api.base_url);
 
-    let client = reqwest::Client::new();
-    let resp = client.get(&url).send().await?;
+    let mut query: Vec<(&str, String)> = Vec::new();
+    if let Some(ref status) = args.status {
+        query.push(("status", status.clone()));
+    }
+    if let Some(ref tool) = args.tool {
+        query.push(("tool", tool.clone()));
+    }
+    if let Some(ref cwd) = args.cwd {
+        let cwd = std::path::absolute(cwd)?;
+        query.push(("cwd", cwd.display().to_string()));
+    }
+    if let Some(ref since) = args.since {
+        query.push(("since", since.clone()));
+    }
+    if let Some(ref tag) = args.tag {
+        query.push(("tag", tag.clone()));
+    }
+    if let Some(limit) = args.limit {
+        query.push(("limit", limit.to_string()));
+    }
 
-    if resp.status().is_success() {
-        let sessions: Vec<SessionMeta> = resp.json().await?;
-        if sessions.is_empty() {
-            println!("No sessions");
-        } else {
-            for s in &sessions {
-                println!(
-                    "{} | {} | {} | {} | {}",
-                    &s.id.to_string()[..8],
-                    s.name,
-                    s.tool,
-                    s.status,
-                    s.created_at.format("%H:%M:%S")
-                );
-            }
+    let resp = api.http.get(&url).query(&query).send().await?;
+    if !resp.status().is_success() {
+        return Err(api_error("Failed to list sessions", resp).await);
+    }
+    let total: Option<usize> = resp
+        .headers()
+        .get("x-total-count")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse().ok());
+    let sessions: Vec<SessionSummary> = resp.json().await?;
+
+    let now = chrono::Utc::now();
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&sessions)?);
+    } else if let Some(ref template) = args.format {
+        for s in &sessions {
+            println!("{}", crate::cli::list::render_format(template, s, now));
         }
+    } else if sessions.is_empty() {
+        println!("No sessions");
     } else {
-        let text = resp.text().await?;
-        anyhow::bail!("Failed to list sessions: {text}");
+        crate::cli::list::print_table(&sessions, now);
+        if let Some(total) = total.filter(|t| *t > sessions.len()) {
+            println!("\nShowing {} of {total} sessions", sessions.len());
+        }
     }
     Ok(())
 }
 
+pub async fn search_cli(args: &crate::cli::commands::SearchArgs) -> Result<()> {
+    let config = Config::load(None)?;
+    let api = Api::connect(&config).await?;
+    let url = format!("{}/api/search", api.base_url);
+
+    let mut query: Vec<(&str, String)> = vec![("q", args.query.join(" "))];
+    if let Some(ref session) = args.session {
+        query.push(("session", session.clone()));
+    }
+    if let Some(ref since) = args.since {
+        query.push(("since", since.clone()));
+    }
+    if let Some(ref until) = args.until {
+        query.push(("until", until.clone()));
+    }
+    if let Some(limit) = args.limit {
+        query.push(("limit", limit.to_string()));
+    }
+
+    let resp = api.http.get(&url).query(&query).send().await?;
+    if !resp.status().is_success() {
+        return Err(api_error("Search failed", resp).await);
+    }
+    let hits: Vec<crate::search::SearchHit> = resp.json().await?;
+
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&hits)?);
+    } else if hits.is_empty() {
+        println!("No matches");
+    } else {
+        for hit in &hits {
+            println!(
+                "{} {}:{}: {}",
+                &hit.session_id.to_string()[..8],
+                hit.session_name,
+                hit.line,
+                hit.text.trim()
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Look up a session on the server by UUID, UUID prefix or name. `Ok(None)`
+/// if the server doesn't know it.
+async fn fetch_session_cli(api: &Api, query: &str) -> Result<Option<SessionMeta>> {
+    // Names may contain spaces, `/`, `?` or `#`, so the query is one
+    // percent-encoded path segment
+    let mut url = reqwest::Url::parse(&api.base_url)?;
+    url.path_segments_mut()
+        .map_err(|_| anyhow::anyhow!("Invalid server URL {}", api.base_url))?
+        .pop_if_empty()
+        .extend(["api", "sessions", query]);
+    let resp = api.http.get(url).send().await?;
+
+    if resp.status() == reqwest::StatusCode::NOT_FOUND {
+        Ok(None)
+    } else if resp.status().is_success() {
+        Ok(Some(resp.json().await?))
+    } else {
+        Err(api_error("Failed to resolve session", resp).await)
+    }
+}
+
+/// Resolve a UUID, UUID prefix or session name to a full session id via the server.
+async fn resolve_session_cli(api: &Api, query: &str) -> Result<Uuid> {
+    match fetch_session_cli(api, query).await? {
+        Some(meta) => Ok(meta.id),
+        None => anyhow::bail!("No session matching '{query}'"),
+    }
+}
+
+/// Resolve a session from the session store, for sessions the server no
+/// longer has (or when it isn't running).
+fn resolve_local_session(query: &str) -> Result<SessionMeta> {
+    let store = SqliteStore::open(&Config::store_path(), &Config::sessions_dir())?;
+    let mut matches = store.find_sessions(query)?;
+    match matches.len() {
+        0 => anyhow::bail!("No session matching '{query}'"),
+        1 => Ok(matches[0].clone()),
+        _ => {
+            matches.sort_by(|a, b| a.name.cmp(&b.name));
+            let mut msg = format!(
+                "{} sessions match '{query}', be more specific",
+                matches.len()
+            );
+            for m in matches {
+                msg.push_str(&format!("\n  {} {}", &m.id.to_string()[..8], m.name));
+            }
+            anyhow::bail!(msg)
+        }
+    }
+}
+
+pub async fn logs_session_cli(args: &crate::cli::commands::LogsArgs) -> Result<()> {
+    use std::io::IsTerminal;
+
+    let config = Config::load(None)?;
+    let api = Api::discover(&config)?;
+    let base_url = &api.base_url;
+
+    let strip = args.strip_ansi || (!args.raw && !std::io::stdout().is_terminal());
+    let mut printer = crate::cli::logs::LogPrinter::new(strip);
+
+    let on_server = match fetch_session_cli(&api, &args.id).await {
+        Ok(meta) => meta,
+        Err(e)
+            if e.downcast_ref::<reqwest::Error>()
+                .is_some_and(|e| e.is_connect()) =>
+        {
+            None
+        }
+        Err(e) => return Err(e),
+    };
+
+    // Running sessions come from the server. Stopped ones are read from
+    // output.log, which has the full history rather than the last
+    // `log_buffer_mb` of output, unless --since needs the buffer's timestamps.
+    let meta = match on_server {
+        Some(meta) if meta.status == SessionStatus::Running || args.since.is_some() => meta,
+        Some(meta) => return print_log_file(&meta, args, &mut printer),
+        None => {
+            let meta = resolve_local_session(&args.id)?;
+            if args.since.is_some() {
+                eprintln!("Note: --since needs the server; showing saved output");
+            }
+            return print_log_file(&meta, args, &mut printer);
+        }
+    };
+
+    let mut query: Vec<(&str, String)> = Vec::new();
+    if let Some(ref since) = args.since {
+        query.push(("since", since.clone()));
+    }
+    if let Some(tail) = args.tail {
+        query.push(("tail", tail.to_string()));
+    }
+    let client = &api.http;
+
+    if !args.follow || meta.status != SessionStatus::Running {
+        let url = format!("{base_url}/api/sessions/{}/logs/history", meta.id);
+        let resp = client.get(&url).query(&query).send().await?;
+        if !resp.status().is_success() {
+            return Err(api_error("Failed to read logs", resp).await);
+        }
+        let entries: Vec<crate::session::log::LogEntry> = resp.json().await?;
+        for entry in entries {
+            printer.write(&entry.data)?;
+        }
+        printer.finish()?;
+        return Ok(());
+    }
+
+    let url = format!("{base_url}/api/sessions/{}/logs", meta.id);
+    let mut resp = client.get(&url).query(&query).send().await?;
+    if !resp.status().is_success() {
+        return Err(api_error("Failed to stream logs", resp).await);
+    }
+
+    // The log stream stays open after the tool exits, so poll for that
+    let mut status_check = tokio::time::interval(std::time::Duration::from_secs(2));
+    let mut buf = Vec::new();
+    let mut last_seq = 0;
+    loop {
+        tokio::select! {
+            chunk = resp.chunk() => {
+                let Some(chunk) = chunk? else { break };
+                buf.extend_from_slice(&chunk);
+                for (event, data) in crate::cli::logs::drain_sse_events(&mut buf) {
+                    match event.as_str() {
+                        // A resync replays the buffer; skip what was already printed
+                        "log" => {
+                            if let Ok(entry) = serde_json::from_str::<crate::session::log::LogEntry>(&data)
+                                && entry.seq > last_seq
+                            {
+                                last_seq = entry.seq;
+                                printer.write(&entry.data)?;
+                            }
+                        }
+                        "gap" => eprintln!("\n--- {data} ---"),
+                        "reset" => eprintln!("\n--- Some output was dropped while catching up ---"),
+                        _ => {}
+                    }
+                }
+            }
+            _ = status_check.tick() => {
+                let running = fetch_session_cli(&api, &meta.id.to_string())
+                    .await
+                    .is_ok_and(|m| m.is_some_and(|m| m.status == SessionStatus::Running));
+                if !running {
+                    break;
+                }
+            }
+        }
+    }
+    printer.finish()?;
+    Ok(())
+}
+
+/// Print a session's saved `output.log`, including rotated segments.
+fn print_log_file(
+    meta: &SessionMeta,
+    args: &crate::cli::commands::LogsArgs,
+    printer: &mut crate::cli::logs::LogPrinter,
+) -> Result<()> {
+    let dir = Config::sessions_dir().join(meta.id.to_string());
+    let segments = writer::log_segments(&dir);
+    if segments.is_empty() {
+        anyhow::bail!("No saved output in {}", dir.display());
+    }
+
+    // With --tail only the newest segments that cover it are read
+    let mut bytes = Vec::new();
+    for segment in segments.iter().rev() {
+        let mut data = writer::read_segment(segment)?;
+        data.extend_from_slice(&bytes);
+        bytes = data;
+        if args
+            .tail
+            .is_some_and(|lines| bytes.iter().filter(|b| **b == b'\n').count() > lines)
+        {
+            break;
+        }
+    }
+
+    let entry = crate::session::log::LogEntry {
+        seq: 1,
+        timestamp: meta.updated_at,
+        data: bytes.into(),
+    };
+    let entries = match args.tail {
+        Some(lines) => crate::session::log::tail(vec![entry], lines),
+        None => vec![entry],
+    };
+    for entry in entries {
+        printer.write(&entry.data)?;
+    }
+    printer.finish()?;
+    Ok(())
+}
+
+/// Turn an error response from the server into a readable error, listing
+/// the candidates when a session reference is ambiguous.
+async fn api_error(context: &str, resp: reqwest::Response) -> anyhow::Error {
+    let status = resp.status();
+    let text = resp.text().await.unwrap_or_default();
+    let Ok(body) = serde_json::from_str::<crate::error::ErrorBody>(&text) else {
+        return anyhow::anyhow!("{context}: {status} {text}");
+    };
+
+    let mut msg = body.message;
+    if body.code == "ambiguous_session" {
+        let candidates = body
+            .details
+            .as_ref()
+            .and_then(|d| d["candidates"].as_array().cloned())
+            .unwrap_or_default();
+        for c in candidates {
+            let id = c["id"].as_str().unwrap_or_default();
+            let name = c["name"].as_str().unwrap_or_default();
+            msg.push_str(&format!("\n  {} {name}", &id[..8.min(id.len())]));
+        }
+        return anyhow::anyhow!(msg);
+    }
+    if body.code == "session_not_found" {
+        return anyhow::anyhow!(msg);
+    }
+    anyhow::anyhow!("{context}: {msg}")
+}
+
 pub async fn kill_session_cli(id: &str) -> Result<()> {
     let config = Config::load(None)?;
-    let bind = crate::config::resolve_bind_address(&config.bind);
-    let url = format!("http://{bind}:{}/api/sessions/{id}/stop", config.port);
+    let api = Api::connect(&config).await?;
+    let id = resolve_session_cli(&api, id).await?;
+    let url = format!("{}/api/sessions/{id}/stop", api.base_url);
 
-    let client = reqwest::Client::new();
-    let resp = client.post(&url).send().await?;
+    let resp = api.http.post(&url).send().await?;
 
     if resp.status().is_success() {
         println!("Session stopped");
     } else {
-        let text = resp.text().await?;
-        anyhow::bail!("Failed to stop session: {text}");
+        return Err(api_error("Failed to stop session", resp).await);
     }
     Ok(())
 }
@@ -475,8 +1013,9 @@ pub async fn attach_session_cli(id: &str) -> Result<()> {
     use tokio::io::{AsyncReadExt, AsyncWriteExt};
 
     let config = Config::load(None)?;
-    let bind = crate::config::resolve_bind_address(&config.bind);
-    let resize_url = format!("http://{bind}:{}/api/sessions/{id}/resize", config.port);
+    let api = Api::connect(&config).await?;
+    let id = resolve_session_cli(&api, id).await?;
+    let resize_url = format!("{}/api/sessions/{id}/resize", api.base_url);
 
     // Find the attach socket in /tmp/lineforge/.
     // Retry a few times in case the socket hasn't been created yet (race condition).
@@ -508,8 +1047,8 @@ pub async fn attach_session_cli(id: &str) -> Result<()> {
 
     // Send initial terminal size to PTY
     if let Ok((cols, rows)) = terminal::size() {
-        let client = reqwest::Client::new();
-        let _ = client
+        let _ = api
+            .http
             .post(&resize_url)
             .json(&serde_json::json!({ "rows": rows, "cols": cols }))
             .send()
@@ -518,13 +1057,13 @@ pub async fn attach_session_cli(id: &str) -> Result<()> {
 
     // Listen for SIGWINCH (terminal resize) and forward to server
     let resize_url_sig = resize_url.clone();
+    let client = api.http.clone();
     tokio::spawn(async move {
         let mut sigwinch =
             tokio::signal::unix::signal(tokio::signal::unix::SignalKind::window_change())
                 .expect("Failed to register SIGWINCH handler");
         while sigwinch.recv().await.is_some() {
             if let Ok((cols, rows)) = terminal::size() {
-                let client = reqwest::Client::new();
                 let _ = client
                     .post(&resize_url_sig)
                     .json(&serde_json::json!({ "rows": rows, "cols": cols }))
@@ -595,10 +1134,8 @@ impl Drop for RawModeGuard {
 
 async fn run_attach_listener(
     sock_path: PathBuf,
-    input_tx: mpsc::Sender<PtyCommand>,
-    broadcast_tx: tokio::sync::broadcast::Sender<crate::session::log::LogEntry>,
-    sessions: Arc<RwLock<HashMap<Uuid, Arc<RwLock<LiveSession>>>>>,
-    id: Uuid,
+    handle: SessionHandle,
+    metrics: Arc<Metrics>,
     sock_ready_tx: oneshot::Sender<()>,
 ) {
     use tokio::io::{AsyncReadExt, AsyncWriteExt};
@@ -629,47 +1166,51 @@ async fn run_attach_listener(
             }
         };
 
-        let input_tx = input_tx.clone();
-        let sessions_clone = sessions.clone();
-        // Subscribe before reading the snapshot so we don't miss entries
-        // produced between snapshot and first recv.
-        let mut log_rx = broadcast_tx.subscribe();
-
-        // Grab ring buffer snapshot for replay
-        let snapshot = {
-            let sessions_guard = sessions.read().await;
-            if let Some(session) = sessions_guard.get(&id) {
-                let s = session.read().await;
-                s.log.snapshot()
-            } else {
-                Vec::new()
-            }
-        };
-
-        tokio::spawn(async move {
+        let handle = handle.clone();
+        let metrics = metrics.clone();
+        let span = tracing::info_span!("attach", session = %handle.id);
+        let connection = async move {
+            let _client = metrics.client_connected(Transport::Attach);
             let (mut reader, mut writer) = tokio::io::split(stream);
 
-            // Forward log output to attached client
+            // Forward log output to attached client, starting with the buffer
+            let output = handle.clone();
             let write_handle = tokio::spawn(async move {
-                // Replay ring buffer snapshot first
-                for entry in &snapshot {
-                    if writer.write_all(entry.data.as_bytes()).await.is_err() {
+                let mut last_seq = 0;
+                loop {
+                    let Ok((slice, mut feed)) = output.follow(last_seq).await else {
+                        return;
+                    };
+                    // Output was lost while catching up; clear the screen and
+                    // redraw it from the buffer rather than leave it garbled
+                    if last_seq > 0 && slice.missed > 0 && writer.write_all(b"\x1bc").await.is_err()
+                    {
                         return;
                     }
-                }
-                let _ = writer.flush().await;
-
-                // Then forward live broadcast
-                loop {
-                    match log_rx.recv().await {
-                        Ok(entry) => {
-                            if writer.write_all(entry.data.as_bytes()).await.is_err() {
+                    for entry in &slice.entries {
+                        last_seq = entry.seq;
+                        if writer.write_all(&entry.data).await.is_err() {
+                            return;
+                        }
+                    }
+                    let _ = writer.flush().await;
+
+                    // Then forward live output until the client falls behind
+                    loop {
+                        match feed.next().await {
+                            Some(FeedItem::Output(entry)) => {
+                                last_seq = entry.seq;
+                                if writer.write_all(&entry.data).await.is_err() {
+                                    return;
+                                }
+                                let _ = writer.flush().await;
+                            }
+                            Some(FeedItem::Resync) => {
+                                metrics.client_resynced(Transport::Attach);
                                 break;
                             }
-                            let _ = writer.flush().await;
+                            None => return,
                         }
-                        Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => continue,
-                        Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
                     }
                 }
             });
@@ -680,8 +1221,10 @@ async fn run_attach_listener(
                 match reader.read(&mut buf).await {
                     Ok(0) => break,
                     Ok(n) => {
-                        if input_tx
+                        let input = tracing::info_span!("input", session = %handle.id, bytes = n);
+                        if handle
                             .send(PtyCommand::Input(buf[..n].to_vec()))
+                            .instrument(input)
                             .await
                             .is_err()
                         {
@@ -694,27 +1237,10 @@ async fn run_attach_listener(
 
             write_handle.abort();
 
-            // Stop the session when the attach client disconnects
-            let sessions_guard = sessions_clone.read().await;
-            if let Some(session) = sessions_guard.get(&id) {
-                let mut s = session.write().await;
-                if s.meta.status == SessionStatus::Running {
-                    if let Some(pid) = s.meta.pid {
-                        unsafe {
-                            libc::kill(pid as i32, libc::SIGTERM);
-                        }
-                    }
-                    s.meta.status = SessionStatus::Stopped;
-                    s.meta.updated_at = chrono::Utc::now();
-
-                    let meta_path = Config::sessions_dir()
-                        .join(id.to_string())
-                        .join("meta.json");
-                    if let Ok(json) = serde_json::to_string_pretty(&s.meta) {
-                        let _ = std::fs::write(&meta_path, json);
-                    }
-                }
-            }
-        });
+            // Stop the session when the attach client disconnects; it may
+            // have exited already
+            let _ = handle.stop().await;
+        };
+        tokio::spawn(connection.instrument(span));
     }
 }
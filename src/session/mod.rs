@@ -1,4 +1,14 @@
+pub mod actor;
+pub mod ansi;
+pub mod env;
+pub mod feed;
+pub mod limits;
 pub mod log;
 pub mod manager;
 pub mod model;
+pub mod persist;
+pub mod prompt;
 pub mod pty;
+pub mod query;
+pub mod resources;
+pub mod writer;
@@ -1,9 +1,12 @@
+use std::collections::BTreeMap;
 use std::path::PathBuf;
 
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
+use crate::session::limits::AppliedLimits;
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 #[serde(rename_all = "lowercase")]
 pub enum SessionStatus {
@@ -20,6 +23,8 @@ pub enum ToolKind {
 }
 
 impl ToolKind {
+    pub const ALL: [ToolKind; 2] = [ToolKind::Claude, ToolKind::Codex];
+
     pub fn command_name(&self) -> &str {
         match self {
             ToolKind::Claude => "claude",
@@ -72,6 +77,8 @@ impl std::fmt::Display for SessionStatus {
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SessionMeta {
+    /// Layout version, see [`crate::session::persist::SCHEMA_VERSION`]
+    pub schema_version: u32,
     pub id: Uuid,
     pub name: String,
     pub tool: ToolKind,
@@ -81,4 +88,66 @@ pub struct SessionMeta {
     pub updated_at: DateTime<Utc>,
     pub pid: Option<u32>,
     pub extra_args: Vec<String>,
+    /// Session-specific environment, with secret values masked
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+    #[serde(default)]
+    pub env_files: Vec<PathBuf>,
+    /// When the tool last wrote to its terminal
+    #[serde(default)]
+    pub last_output_at: Option<DateTime<Utc>>,
+    /// Resource limits the session was started with
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub limits: Option<AppliedLimits>,
+}
+
+/// What a session is doing right now, derived from its status, output and
+/// any pending permission prompt.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Activity {
+    /// Produced output recently
+    Working,
+    Idle,
+    AwaitingApproval,
+    Exited,
+}
+
+impl Activity {
+    /// Output within this window counts as working.
+    pub const WORKING_WINDOW: chrono::TimeDelta = chrono::TimeDelta::seconds(5);
+
+    pub fn of(meta: &SessionMeta, awaiting_approval: bool, now: DateTime<Utc>) -> Self {
+        if meta.status != SessionStatus::Running {
+            Activity::Exited
+        } else if awaiting_approval {
+            Activity::AwaitingApproval
+        } else if meta
+            .last_output_at
+            .is_some_and(|t| now - t < Self::WORKING_WINDOW)
+        {
+            Activity::Working
+        } else {
+            Activity::Idle
+        }
+    }
+}
+
+impl std::fmt::Display for Activity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Activity::Working => write!(f, "working"),
+            Activity::Idle => write!(f, "idle"),
+            Activity::AwaitingApproval => write!(f, "awaiting approval"),
+            Activity::Exited => write!(f, "exited"),
+        }
+    }
+}
+
+/// A session as returned by `GET /api/sessions`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionSummary {
+    #[serde(flatten)]
+    pub meta: SessionMeta,
+    pub activity: Activity,
 }
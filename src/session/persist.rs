@@ -0,0 +1,137 @@
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde_json::{Map, Value};
+use uuid::Uuid;
+
+use crate::config::Config;
+use crate::session::model::{SessionMeta, SessionStatus, ToolKind};
+use crate::store::Store;
+
+/// Version of the `SessionMeta` layout written by this build.
+pub const SCHEMA_VERSION: u32 = 2;
+
+/// `MIGRATIONS[n]` upgrades a version `n + 1` document to version `n + 2`.
+/// Documents from before versioning count as version 1.
+const MIGRATIONS: &[fn(&mut Map<String, Value>)] = &[v1_to_v2];
+
+/// Fields added after the first release, filled in explicitly.
+fn v1_to_v2(meta: &mut Map<String, Value>) {
+    meta.entry("env")
+        .or_insert_with(|| Value::Object(Map::new()));
+    meta.entry("env_files")
+        .or_insert_with(|| Value::Array(Vec::new()));
+    meta.entry("last_output_at").or_insert(Value::Null);
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum MetaError {
+    #[error("meta.json is missing")]
+    Missing,
+
+    #[error("meta.json is corrupt: {0}")]
+    Corrupt(String),
+
+    #[error("meta.json has schema version {0}, this build only knows up to {SCHEMA_VERSION}")]
+    Newer(u32),
+
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+pub fn session_dir(id: Uuid) -> PathBuf {
+    Config::sessions_dir().join(id.to_string())
+}
+
+/// Save a session's metadata: `meta.json` first, then the store. Every
+/// metadata write goes through here.
+pub fn persist(store: &dyn Store, meta: &SessionMeta) -> Result<()> {
+    let dir = session_dir(meta.id);
+    write_meta(&dir, meta).with_context(|| format!("Failed to write {}", dir.display()))?;
+    store.save_session(meta)
+}
+
+/// Replace `meta.json` atomically: a crash leaves either the old or the new
+/// file, never a truncated one.
+fn write_meta(dir: &Path, meta: &SessionMeta) -> std::io::Result<()> {
+    let json = serde_json::to_vec_pretty(meta)?;
+    let tmp = dir.join("meta.json.tmp");
+    let mut file = std::fs::File::create(&tmp)?;
+    file.write_all(&json)?;
+    file.sync_all()?;
+    drop(file);
+    std::fs::rename(&tmp, dir.join("meta.json"))?;
+    // The rename is only durable once the directory is synced
+    std::fs::File::open(dir)?.sync_all()
+}
+
+/// Read a session directory's `meta.json`, upgrading older layouts.
+pub fn load(dir: &Path) -> Result<SessionMeta, MetaError> {
+    let json = match std::fs::read(dir.join("meta.json")) {
+        Ok(json) => json,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Err(MetaError::Missing),
+        Err(e) => return Err(e.into()),
+    };
+    let value = serde_json::from_slice(&json).map_err(|e| MetaError::Corrupt(e.to_string()))?;
+    from_value(value)
+}
+
+/// Deserialize metadata of any known schema version.
+pub fn from_value(mut value: Value) -> Result<SessionMeta, MetaError> {
+    let Some(fields) = value.as_object_mut() else {
+        return Err(MetaError::Corrupt("not a JSON object".into()));
+    };
+    let version = fields
+        .get("schema_version")
+        .and_then(Value::as_u64)
+        .unwrap_or(1) as u32;
+    if version > SCHEMA_VERSION {
+        return Err(MetaError::Newer(version));
+    }
+    for migrate in MIGRATIONS.iter().skip(version.saturating_sub(1) as usize) {
+        migrate(fields);
+    }
+    fields.insert("schema_version".into(), SCHEMA_VERSION.into());
+    serde_json::from_value(value).map_err(|e| MetaError::Corrupt(e.to_string()))
+}
+
+/// Rebuild minimal metadata for a session directory whose `meta.json` is
+/// gone or unreadable, from the directory name and file times. Name, tool
+/// and working dir are lost; the session is marked errored so it stands out.
+pub fn recover(dir: &Path) -> Option<SessionMeta> {
+    let id: Uuid = dir.file_name()?.to_str()?.parse().ok()?;
+    let dir_meta = std::fs::metadata(dir).ok()?;
+    let created_at: DateTime<Utc> = dir_meta
+        .created()
+        .or_else(|_| dir_meta.modified())
+        .ok()?
+        .into();
+
+    // Whatever was written last tells when the session was last alive
+    let last_output_at: Option<DateTime<Utc>> = crate::session::writer::log_segments(dir)
+        .iter()
+        .filter_map(|p| std::fs::metadata(p).and_then(|m| m.modified()).ok())
+        .max()
+        .map(Into::into);
+
+    Some(SessionMeta {
+        schema_version: SCHEMA_VERSION,
+        id,
+        name: format!("recovered-{}", &id.to_string()[..8]),
+        tool: ToolKind::Claude,
+        status: SessionStatus::Errored(
+            "Metadata was lost; recovered from the session directory".into(),
+        ),
+        working_dir: PathBuf::new(),
+        created_at,
+        updated_at: last_output_at.unwrap_or(created_at),
+        pid: None,
+        extra_args: Vec::new(),
+        env: Default::default(),
+        env_files: Vec::new(),
+        last_output_at,
+        limits: None,
+    })
+}
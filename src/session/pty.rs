@@ -1,12 +1,227 @@
-use anyhow::Result;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::Duration;
 
-use crate::config::Config;
+use anyhow::{Context, Result};
+
+use crate::config::{Config, LaunchMode};
+use crate::error::ForgeError;
 use crate::session::model::ToolKind;
 
-/// Resolve the tool binary path
+/// Marks the start of `env` output, so anything a profile prints is skipped.
+const ENV_MARKER: &str = "__LINEFORGE_ENV__";
+
+/// Carries the script a login shell hands to `/bin/sh`, so the command line
+/// the login shell itself parses is the same whatever shell it is.
+const SCRIPT_VAR: &str = "LINEFORGE_SCRIPT";
+
+/// Means the same in sh, bash, zsh and fish. The login shell only loads the
+/// profile; everything else runs in `/bin/sh`.
+const LOGIN_COMMAND: &str = r#"exec /bin/sh -c "$LINEFORGE_SCRIPT""#;
+
+/// How long a login shell gets to print its environment. A profile that
+/// prompts or hangs would otherwise block every launch.
+const LOGIN_ENV_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Set by the shell for itself rather than exported by the profile.
+const SHELL_STATE_VARS: [&str; 4] = ["PWD", "OLDPWD", "SHLVL", "_"];
+
+static LOGIN_ENV: OnceLock<HashMap<String, String>> = OnceLock::new();
+
+/// Program, arguments and environment used to launch a tool.
+pub struct LaunchSpec {
+    pub program: String,
+    pub args: Vec<String>,
+    /// Applied before the session's own environment
+    pub env: HashMap<String, String>,
+}
+
+/// Resolve the tool binary path, failing with the PATH that was searched if
+/// it can't be found.
+///
+/// In the login modes the PATH comes from the user's login shell rather than
+/// the server's own environment (which is minimal under systemd or launchd).
 pub fn resolve_tool_path(config: &Config, tool: &ToolKind) -> Result<String> {
-    if let Some(ref path) = config.tool_path {
-        return Ok(path.clone());
+    let name = config
+        .tool_path
+        .clone()
+        .unwrap_or_else(|| tool.command_name().to_string());
+
+    let path_var = match config.launch_mode(tool) {
+        LaunchMode::Direct => std::env::var("PATH").unwrap_or_default(),
+        LaunchMode::LoginShell | LaunchMode::LoginEnv => login_environment()?
+            .get("PATH")
+            .cloned()
+            .unwrap_or_default(),
+    };
+
+    match search_path(&name, &path_var) {
+        Some(path) => Ok(path.to_string_lossy().into_owned()),
+        None => Err(ForgeError::ToolNotFound {
+            tool: name,
+            searched: path_var,
+        }
+        .into()),
     }
-    Ok(tool.command_name().to_string())
+}
+
+/// Build the command for `tool` according to its launch mode.
+pub fn launch_spec(config: &Config, tool: &ToolKind, extra_args: &[String]) -> Result<LaunchSpec> {
+    let tool_path = resolve_tool_path(config, tool)?;
+
+    let spec = match config.launch_mode(tool) {
+        LaunchMode::Direct => LaunchSpec {
+            program: tool_path,
+            args: extra_args.to_vec(),
+            env: HashMap::new(),
+        },
+        LaunchMode::LoginEnv => LaunchSpec {
+            program: tool_path,
+            args: extra_args.to_vec(),
+            env: login_environment()?.clone(),
+        },
+        LaunchMode::LoginShell => {
+            let shell = user_shell();
+            let command = std::iter::once(&tool_path)
+                .chain(extra_args)
+                .map(|arg| sh_quote(arg))
+                .collect::<Vec<_>>()
+                .join(" ");
+            LaunchSpec {
+                args: login_shell_args(&shell)?,
+                program: shell,
+                env: HashMap::from([(
+                    SCRIPT_VAR.to_string(),
+                    format!("unset {SCRIPT_VAR}; exec {command}"),
+                )]),
+            }
+        }
+    };
+    Ok(spec)
+}
+
+/// Arguments that have the user's login shell load its profile, then run
+/// `$LINEFORGE_SCRIPT` with `/bin/sh`.
+fn login_shell_args(shell: &str) -> Result<Vec<String>> {
+    let name = Path::new(shell)
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or(shell);
+    if matches!(name, "csh" | "tcsh") {
+        anyhow::bail!(
+            "Login shell {shell} can't run a command as a login shell (csh only accepts -l on \
+             its own). Start the server with SHELL set to a POSIX shell or fish, or use \
+             launch_mode = \"direct\""
+        );
+    }
+    Ok(vec!["-l".into(), "-c".into(), LOGIN_COMMAND.into()])
+}
+
+/// Quote `arg` as a single word for `/bin/sh`.
+fn sh_quote(arg: &str) -> String {
+    format!("'{}'", arg.replace('\'', r"'\''"))
+}
+
+/// Find an executable by name in a `PATH`-style list. Names containing a
+/// slash are checked as-is.
+pub fn search_path(name: &str, path_var: &str) -> Option<PathBuf> {
+    if name.contains('/') {
+        let path = PathBuf::from(name);
+        return is_executable(&path).then_some(path);
+    }
+    std::env::split_paths(path_var)
+        .map(|dir| dir.join(name))
+        .find(|candidate| is_executable(candidate))
+}
+
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+
+    path.metadata()
+        .map(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+        .unwrap_or(false)
+}
+
+/// The user's login shell: `$SHELL`, then the passwd entry, then `/bin/sh`.
+pub fn user_shell() -> String {
+    if let Ok(shell) = std::env::var("SHELL")
+        && !shell.is_empty()
+    {
+        return shell;
+    }
+
+    // SAFETY: getpwuid returns a pointer into static storage or null; the
+    // shell string is copied out before any other passwd call.
+    unsafe {
+        let pw = libc::getpwuid(libc::getuid());
+        if !pw.is_null() && !(*pw).pw_shell.is_null() {
+            let shell = std::ffi::CStr::from_ptr((*pw).pw_shell).to_string_lossy();
+            if !shell.is_empty() {
+                return shell.into_owned();
+            }
+        }
+    }
+    "/bin/sh".into()
+}
+
+/// Environment of a login shell, resolved once and cached for the life of
+/// the server. Failures, including a timeout, aren't cached.
+pub fn login_environment() -> Result<&'static HashMap<String, String>> {
+    if let Some(env) = LOGIN_ENV.get() {
+        return Ok(env);
+    }
+
+    let shell = user_shell();
+    let child = {
+        use std::os::unix::process::CommandExt;
+        std::process::Command::new(&shell)
+            .args(login_shell_args(&shell)?)
+            .env(
+                SCRIPT_VAR,
+                format!("unset {SCRIPT_VAR}; printf '{ENV_MARKER}'; exec env -0"),
+            )
+            .stdin(std::process::Stdio::null())
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::piped())
+            // Its own process group, so anything the profile started is
+            // killed with it on timeout
+            .process_group(0)
+            .spawn()
+            .with_context(|| format!("Failed to run login shell {shell}"))?
+    };
+    let pgid = child.id() as libc::pid_t;
+    let (tx, rx) = std::sync::mpsc::channel();
+    std::thread::spawn(move || {
+        let _ = tx.send(child.wait_with_output());
+    });
+    let output = match rx.recv_timeout(LOGIN_ENV_TIMEOUT) {
+        Ok(output) => output.with_context(|| format!("Failed to run login shell {shell}"))?,
+        Err(_) => {
+            unsafe { libc::kill(-pgid, libc::SIGKILL) };
+            anyhow::bail!(
+                "Login shell {shell} didn't finish within {}s; check your profile for prompts",
+                LOGIN_ENV_TIMEOUT.as_secs()
+            );
+        }
+    };
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        anyhow::bail!("Login shell {shell} failed: {}", stderr.trim());
+    }
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let body = stdout
+        .split_once(ENV_MARKER)
+        .map(|(_, rest)| rest)
+        .unwrap_or(&stdout);
+    let env = body
+        .split('\0')
+        .filter_map(|entry| entry.split_once('='))
+        .filter(|(k, _)| !SHELL_STATE_VARS.contains(k))
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+
+    tracing::info!("Resolved login environment from {shell}");
+    Ok(LOGIN_ENV.get_or_init(|| env))
 }
@@ -0,0 +1,124 @@
+use std::path::PathBuf;
+
+use chrono::{DateTime, TimeDelta, Utc};
+use serde::Deserialize;
+
+use crate::error::ForgeError;
+use crate::session::model::ToolKind;
+
+/// Largest page `GET /api/sessions` returns.
+pub const MAX_LIMIT: usize = 500;
+
+/// Filters and paging for listing sessions. Every filter that is set must match.
+#[derive(Debug, Default, Deserialize)]
+pub struct SessionQuery {
+    /// `running`, `stopped` or `errored`
+    pub status: Option<String>,
+    pub tool: Option<String>,
+    /// Only sessions whose working dir is this directory or below it
+    pub cwd: Option<PathBuf>,
+    /// RFC 3339 timestamp or a relative age such as `30m`, `2h` or `7d`
+    pub since: Option<String>,
+    /// Only sessions carrying this tag
+    pub tag: Option<String>,
+    pub limit: Option<usize>,
+    #[serde(default)]
+    pub offset: usize,
+}
+
+/// A [`SessionQuery`] with its values parsed and checked.
+pub struct SessionFilter {
+    pub status: Option<String>,
+    pub tool: Option<ToolKind>,
+    pub cwd: Option<PathBuf>,
+    pub since: Option<DateTime<Utc>>,
+    pub tag: Option<String>,
+    pub limit: usize,
+    pub offset: usize,
+}
+
+impl SessionQuery {
+    pub fn parse(&self) -> Result<SessionFilter, ForgeError> {
+        let status = match self.status.as_deref().map(str::to_lowercase) {
+            None => None,
+            Some(s) if ["running", "stopped", "errored"].contains(&s.as_str()) => Some(s),
+            Some(s) => {
+                return Err(ForgeError::validation(
+                    "status",
+                    format!("unknown status '{s}', expected running, stopped or errored"),
+                ));
+            }
+        };
+        let tool = self
+            .tool
+            .as_deref()
+            .map(str::parse::<ToolKind>)
+            .transpose()
+            .map_err(|e| ForgeError::validation("tool", e))?;
+        let since = self
+            .since
+            .as_deref()
+            .map(|s| parse_since(s, Utc::now()))
+            .transpose()
+            .map_err(|e| ForgeError::validation("since", e))?;
+        let limit = self.limit.unwrap_or(MAX_LIMIT);
+        if limit == 0 || limit > MAX_LIMIT {
+            return Err(ForgeError::validation(
+                "limit",
+                format!("must be 1-{MAX_LIMIT}"),
+            ));
+        }
+
+        Ok(SessionFilter {
+            status,
+            tool,
+            cwd: self.cwd.clone(),
+            since,
+            tag: self.tag.clone(),
+            limit,
+            offset: self.offset,
+        })
+    }
+}
+
+impl SessionFilter {
+    /// Every session, unpaged.
+    pub fn all() -> Self {
+        Self {
+            status: None,
+            tool: None,
+            cwd: None,
+            since: None,
+            tag: None,
+            limit: usize::MAX,
+            offset: 0,
+        }
+    }
+}
+
+/// Parse an RFC 3339 timestamp, or an age like `90s`, `30m`, `2h`, `7d` or
+/// `1w` counted back from `now`.
+pub fn parse_since(value: &str, now: DateTime<Utc>) -> Result<DateTime<Utc>, String> {
+    if let Ok(t) = DateTime::parse_from_rfc3339(value) {
+        return Ok(t.with_timezone(&Utc));
+    }
+
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (amount, unit) = value.split_at(split);
+    let amount: i64 = amount
+        .parse()
+        .map_err(|_| format!("expected a timestamp or an age like 2h, got '{value}'"))?;
+    match unit {
+        "s" => TimeDelta::try_seconds(amount),
+        "m" => TimeDelta::try_minutes(amount),
+        "h" => TimeDelta::try_hours(amount),
+        "d" => TimeDelta::try_days(amount),
+        "w" => TimeDelta::try_weeks(amount),
+        _ => return Err(format!("expected an age like 30m, 2h or 7d, got '{value}'")),
+    }
+    .and_then(|age| now.checked_sub_signed(age))
+    .ok_or_else(|| format!("age '{value}' is out of range"))
+}
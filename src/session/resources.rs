@@ -0,0 +1,306 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::Instant;
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use crate::session::env::mask_args;
+
+/// Samples kept per session for the sparklines.
+const HISTORY_LEN: usize = 150;
+
+/// Deeper trees are cut off rather than walked.
+const MAX_DEPTH: usize = 32;
+
+/// Totals over a session's process tree at one point in time.
+#[derive(Debug, Clone, Serialize)]
+pub struct ResourceSample {
+    pub at: DateTime<Utc>,
+    /// Percent of one core; can exceed 100 with several busy threads
+    pub cpu_percent: f64,
+    pub rss_bytes: u64,
+    pub threads: u64,
+    pub open_files: u64,
+    pub processes: u64,
+}
+
+/// One process in a session's tree: the tool itself, or something it ran.
+#[derive(Debug, Clone, Serialize)]
+pub struct ProcessNode {
+    pub pid: u32,
+    pub name: String,
+    pub command: String,
+    pub cpu_percent: f64,
+    pub rss_bytes: u64,
+    pub threads: u64,
+    pub open_files: u64,
+    pub children: Vec<ProcessNode>,
+}
+
+impl ProcessNode {
+    fn total(&self, sample: &mut ResourceSample) {
+        sample.cpu_percent += self.cpu_percent;
+        sample.rss_bytes += self.rss_bytes;
+        sample.threads += self.threads;
+        sample.open_files += self.open_files;
+        sample.processes += 1;
+        for child in &self.children {
+            child.total(sample);
+        }
+    }
+}
+
+/// `GET /api/sessions/{id}/resources`
+#[derive(Debug, Serialize)]
+pub struct ResourceReport {
+    pub interval_secs: u64,
+    /// Latest sample, while the session is running
+    pub current: Option<ResourceSample>,
+    /// Oldest first
+    pub history: Vec<ResourceSample>,
+    /// The process tree as of the latest sample
+    pub processes: Option<ProcessNode>,
+}
+
+impl ResourceReport {
+    pub fn empty(interval_secs: u64) -> Self {
+        Self {
+            interval_secs,
+            current: None,
+            history: Vec::new(),
+            processes: None,
+        }
+    }
+}
+
+/// Recent samples of one session, filled in by the server's sampler.
+#[derive(Default)]
+pub struct SessionResources {
+    inner: Mutex<ResourcesInner>,
+}
+
+#[derive(Default)]
+struct ResourcesInner {
+    history: VecDeque<ResourceSample>,
+    tree: Option<ProcessNode>,
+}
+
+impl SessionResources {
+    /// Record the tree as sampled now; `None` once the process is gone.
+    pub fn record(&self, tree: Option<ProcessNode>) {
+        let mut inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(tree) = &tree {
+            let mut sample = ResourceSample {
+                at: Utc::now(),
+                cpu_percent: 0.0,
+                rss_bytes: 0,
+                threads: 0,
+                open_files: 0,
+                processes: 0,
+            };
+            tree.total(&mut sample);
+            if inner.history.len() == HISTORY_LEN {
+                inner.history.pop_front();
+            }
+            inner.history.push_back(sample);
+        }
+        inner.tree = tree;
+    }
+
+    pub fn report(&self, interval_secs: u64) -> ResourceReport {
+        let inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        ResourceReport {
+            interval_secs,
+            current: inner
+                .tree
+                .as_ref()
+                .and_then(|_| inner.history.back().cloned()),
+            history: inner.history.iter().cloned().collect(),
+            processes: inner.tree.clone(),
+        }
+    }
+}
+
+/// What `/proc/<pid>/stat` says about a process.
+struct ProcStat {
+    ppid: u32,
+    name: String,
+    /// utime + stime, in clock ticks
+    cpu_ticks: u64,
+    /// Distinguishes a reused pid
+    start_time: u64,
+    threads: u64,
+    rss_pages: u64,
+}
+
+/// Reads process trees from `/proc`, remembering CPU times between calls
+/// to turn them into usage. Linux only; elsewhere every tree is `None`.
+pub struct Sampler {
+    /// CPU ticks per (pid, start time) at the previous sample
+    cpu_ticks: HashMap<(u32, u64), u64>,
+    last: Option<Instant>,
+    ticks_per_sec: f64,
+    page_size: u64,
+}
+
+impl Default for Sampler {
+    fn default() -> Self {
+        let ticks = unsafe { libc::sysconf(libc::_SC_CLK_TCK) };
+        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) };
+        Self {
+            cpu_ticks: HashMap::new(),
+            last: None,
+            ticks_per_sec: if ticks > 0 { ticks as f64 } else { 100.0 },
+            page_size: if page_size > 0 {
+                page_size as u64
+            } else {
+                4096
+            },
+        }
+    }
+}
+
+impl Sampler {
+    /// The trees rooted at `roots`, in the same order. Scans `/proc` once
+    /// for all of them; blocking.
+    pub fn sample(&mut self, roots: &[u32]) -> Vec<Option<ProcessNode>> {
+        let procs = scan_proc();
+        let now = Instant::now();
+        let elapsed = self
+            .last
+            .map(|last| now.duration_since(last).as_secs_f64())
+            .filter(|secs| *secs > 0.0);
+        self.last = Some(now);
+
+        let mut children: HashMap<u32, Vec<u32>> = HashMap::new();
+        for (&pid, stat) in &procs {
+            children.entry(stat.ppid).or_default().push(pid);
+        }
+        for pids in children.values_mut() {
+            pids.sort_unstable();
+        }
+
+        let mut cpu_ticks = HashMap::new();
+        let trees = roots
+            .iter()
+            .map(|&root| self.node(root, 0, &procs, &children, elapsed, &mut cpu_ticks))
+            .collect();
+        // Forget processes that are gone
+        self.cpu_ticks = cpu_ticks;
+        trees
+    }
+
+    fn node(
+        &self,
+        pid: u32,
+        depth: usize,
+        procs: &HashMap<u32, ProcStat>,
+        children: &HashMap<u32, Vec<u32>>,
+        elapsed: Option<f64>,
+        cpu_ticks: &mut HashMap<(u32, u64), u64>,
+    ) -> Option<ProcessNode> {
+        let stat = procs.get(&pid)?;
+        let key = (pid, stat.start_time);
+        // A process first seen now has no baseline yet
+        let cpu_percent = match (self.cpu_ticks.get(&key), elapsed) {
+            (Some(&before), Some(secs)) => {
+                stat.cpu_ticks.saturating_sub(before) as f64 / self.ticks_per_sec / secs * 100.0
+            }
+            _ => 0.0,
+        };
+        cpu_ticks.insert(key, stat.cpu_ticks);
+
+        let children = if depth < MAX_DEPTH {
+            children
+                .get(&pid)
+                .into_iter()
+                .flatten()
+                .filter_map(|&child| {
+                    self.node(child, depth + 1, procs, children, elapsed, cpu_ticks)
+                })
+                .collect()
+        } else {
+            Vec::new()
+        };
+        Some(ProcessNode {
+            pid,
+            name: stat.name.clone(),
+            command: read_cmdline(pid).unwrap_or_else(|| format!("[{}]", stat.name)),
+            cpu_percent: (cpu_percent * 10.0).round() / 10.0,
+            rss_bytes: stat.rss_pages * self.page_size,
+            threads: stat.threads,
+            open_files: count_open_files(pid),
+            children,
+        })
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn scan_proc() -> HashMap<u32, ProcStat> {
+    let Ok(entries) = std::fs::read_dir("/proc") else {
+        return HashMap::new();
+    };
+    entries
+        .flatten()
+        .filter_map(|entry| entry.file_name().to_str()?.parse::<u32>().ok())
+        .filter_map(|pid| {
+            let stat = std::fs::read_to_string(format!("/proc/{pid}/stat")).ok()?;
+            Some((pid, parse_stat(&stat)?))
+        })
+        .collect()
+}
+
+#[cfg(not(target_os = "linux"))]
+fn scan_proc() -> HashMap<u32, ProcStat> {
+    HashMap::new()
+}
+
+/// When process `pid` started, in clock ticks since boot; tells it apart
+/// from a later process that reuses the pid.
+#[cfg(target_os = "linux")]
+pub fn start_time(pid: u32) -> Option<u64> {
+    let stat = std::fs::read_to_string(format!("/proc/{pid}/stat")).ok()?;
+    Some(parse_stat(&stat)?.start_time)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn start_time(_pid: u32) -> Option<u64> {
+    None
+}
+
+/// Parse `/proc/<pid>/stat`. The name is in parentheses and may itself
+/// contain spaces and parentheses, so fields are counted from the last `)`.
+#[cfg(target_os = "linux")]
+fn parse_stat(stat: &str) -> Option<ProcStat> {
+    let open = stat.find('(')?;
+    let close = stat.rfind(')')?;
+    let name = stat.get(open + 1..close)?.to_string();
+    // Fields from 3 (state) on, so field N is at index N - 3
+    let fields: Vec<&str> = stat.get(close + 1..)?.split_whitespace().collect();
+    let field = |n: usize| -> Option<u64> { fields.get(n - 3)?.parse().ok() };
+    Some(ProcStat {
+        ppid: field(4)? as u32,
+        name,
+        cpu_ticks: field(14)? + field(15)?,
+        start_time: field(22)?,
+        threads: field(20)?,
+        rss_pages: field(24)?,
+    })
+}
+
+/// The process's command line, with secrets in its arguments masked.
+fn read_cmdline(pid: u32) -> Option<String> {
+    let raw = std::fs::read(format!("/proc/{pid}/cmdline")).ok()?;
+    let args: Vec<String> = raw
+        .split(|&b| b == 0)
+        .filter(|arg| !arg.is_empty())
+        .map(|arg| String::from_utf8_lossy(arg).into_owned())
+        .collect();
+    let command = mask_args(&args).join(" ");
+    (!command.is_empty()).then_some(command)
+}
+
+fn count_open_files(pid: u32) -> u64 {
+    std::fs::read_dir(format!("/proc/{pid}/fd")).map_or(0, |fds| fds.count() as u64)
+}
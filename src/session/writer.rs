@@ -0,0 +1,369 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::{mpsc, watch};
+
+const LOG_NAME: &str = "output.log";
+const BUFFER_SIZE: usize = 64 * 1024;
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+/// Every this many flushes the file is also fsynced
+const FSYNC_EVERY: u32 = 5;
+/// Chunks queued for the writer, each at most one PTY read. Output arriving
+/// while the queue is full is dropped from the file (it stays in memory),
+/// and a note saying how much is written in its place.
+const QUEUE_LEN: usize = 1024;
+
+/// How rotated segments of `output.log` are compressed.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogCompression {
+    None,
+    #[default]
+    Gzip,
+    Zstd,
+}
+
+impl LogCompression {
+    fn extension(&self) -> &'static str {
+        match self {
+            LogCompression::None => "",
+            LogCompression::Gzip => ".gz",
+            LogCompression::Zstd => ".zst",
+        }
+    }
+}
+
+/// When to rotate `output.log` and how many rotated segments to keep.
+#[derive(Debug, Clone, Copy)]
+pub struct RotationPolicy {
+    pub max_bytes: u64,
+    pub keep: u32,
+    pub compression: LogCompression,
+}
+
+enum WriterMsg {
+    Data(Bytes),
+    /// This many bytes didn't fit in the queue
+    Dropped(u64),
+    Close,
+}
+
+/// Handle to a session's background log writer. Writes are queued and never
+/// block the caller; the task buffers them, flushes every second, fsyncs
+/// periodically and rotates `output.log` by size. When the disk can't keep
+/// up and the queue fills, output is dropped from the file rather than held
+/// in memory without bound.
+pub struct LogWriter {
+    tx: mpsc::Sender<WriterMsg>,
+    done: watch::Receiver<()>,
+    /// Bytes dropped since the queue last had room
+    dropped: u64,
+}
+
+impl LogWriter {
+    /// Spawn the writer task for `session_dir/output.log`.
+    pub fn spawn(session_dir: PathBuf, policy: RotationPolicy) -> Self {
+        let (tx, rx) = mpsc::channel(QUEUE_LEN);
+        let (done_tx, done) = watch::channel(());
+        tokio::spawn(async move {
+            if let Err(e) = run_writer(&session_dir, policy, rx).await {
+                tracing::error!("Log writer for {} failed: {e:#}", session_dir.display());
+            }
+            drop(done_tx);
+        });
+        Self {
+            tx,
+            done,
+            dropped: 0,
+        }
+    }
+
+    /// Closed once the task has stopped, after `close` has written
+    /// everything out.
+    pub fn done(&self) -> watch::Receiver<()> {
+        self.done.clone()
+    }
+
+    pub fn write(&mut self, data: Bytes) {
+        if self.dropped > 0 {
+            match self.tx.try_send(WriterMsg::Dropped(self.dropped)) {
+                Ok(()) => self.dropped = 0,
+                Err(_) => {
+                    self.dropped += data.len() as u64;
+                    return;
+                }
+            }
+        }
+        let len = data.len() as u64;
+        if let Err(mpsc::error::TrySendError::Full(_)) = self.tx.try_send(WriterMsg::Data(data)) {
+            tracing::warn!("Session log writer is behind; dropping output from the log file");
+            self.dropped = len;
+        }
+    }
+
+    /// Flush, fsync and stop once everything queued so far is written.
+    pub fn close(&self) {
+        let tx = self.tx.clone();
+        let dropped = self.dropped;
+        tokio::spawn(async move {
+            if dropped > 0 {
+                let _ = tx.send(WriterMsg::Dropped(dropped)).await;
+            }
+            let _ = tx.send(WriterMsg::Close).await;
+        });
+    }
+}
+
+async fn run_writer(
+    dir: &Path,
+    policy: RotationPolicy,
+    mut rx: mpsc::Receiver<WriterMsg>,
+) -> Result<()> {
+    let path = dir.join(LOG_NAME);
+    let mut file = open_append(&path).await?;
+    let mut size = file
+        .get_ref()
+        .metadata()
+        .await
+        .map(|m| m.len())
+        .unwrap_or(0);
+    let mut ticker = tokio::time::interval(FLUSH_INTERVAL);
+    let mut ticks = 0u32;
+    let mut dirty = false;
+    // Compression of the last rotated segment, which runs in the background
+    // so output keeps being written meanwhile
+    let mut compressing: Option<tokio::task::JoinHandle<()>> = None;
+
+    loop {
+        tokio::select! {
+            msg = rx.recv() => {
+                let data = match msg {
+                    Some(WriterMsg::Data(data)) => data,
+                    Some(WriterMsg::Dropped(bytes)) => Bytes::from(format!(
+                        "\r\n[lineforge: {bytes} bytes of output not saved, the disk fell behind]\r\n"
+                    )),
+                    Some(WriterMsg::Close) | None => break,
+                };
+                file.write_all(&data).await?;
+                size += data.len() as u64;
+                dirty = true;
+                if size >= policy.max_bytes {
+                    file.flush().await?;
+                    file.get_ref().sync_data().await?;
+                    drop(file);
+                    rotate(dir, policy, &mut compressing).await?;
+                    file = open_append(&path).await?;
+                    size = 0;
+                    dirty = false;
+                }
+            }
+            _ = ticker.tick() => {
+                if dirty {
+                    file.flush().await?;
+                    ticks += 1;
+                    if ticks.is_multiple_of(FSYNC_EVERY) {
+                        file.get_ref().sync_data().await?;
+                        dirty = false;
+                    }
+                }
+            }
+        }
+    }
+
+    file.flush().await?;
+    file.get_ref().sync_all().await?;
+    Ok(())
+}
+
+async fn open_append(path: &Path) -> Result<tokio::io::BufWriter<tokio::fs::File>> {
+    let file = tokio::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .await
+        .with_context(|| format!("Failed to open {}", path.display()))?;
+    Ok(tokio::io::BufWriter::with_capacity(BUFFER_SIZE, file))
+}
+
+/// Shift `output.log.N*` up by one, dropping the oldest beyond `keep`, then
+/// move `output.log` to `output.log.1` and start compressing it in the
+/// background.
+async fn rotate(
+    dir: &Path,
+    policy: RotationPolicy,
+    compressing: &mut Option<tokio::task::JoinHandle<()>>,
+) -> Result<()> {
+    // The segment being compressed is about to be renamed. This only waits
+    // when output filled a whole segment faster than the last one compressed.
+    if let Some(previous) = compressing.take() {
+        let _ = previous.await;
+    }
+    let dir = dir.to_path_buf();
+    let first = dir.join(format!("{LOG_NAME}.1"));
+    let rotated = {
+        let dir = dir.clone();
+        let first = first.clone();
+        tokio::task::spawn_blocking(move || -> Result<bool> {
+            remove_leftovers(&dir)?;
+            let mut segments = rotated_segments(&dir);
+            // Newest first, so renames don't overwrite each other
+            segments.sort_by_key(|(n, _)| std::cmp::Reverse(*n));
+            for (n, path) in segments {
+                if n >= policy.keep {
+                    std::fs::remove_file(&path)?;
+                    continue;
+                }
+                let name = path.file_name().unwrap_or_default().to_string_lossy();
+                let suffix = name
+                    .strip_prefix(&format!("{LOG_NAME}.{n}"))
+                    .unwrap_or_default();
+                std::fs::rename(&path, dir.join(format!("{LOG_NAME}.{}{suffix}", n + 1)))?;
+            }
+            if policy.keep == 0 {
+                std::fs::remove_file(dir.join(LOG_NAME))?;
+                return Ok(false);
+            }
+            std::fs::rename(dir.join(LOG_NAME), &first)?;
+            Ok(true)
+        })
+        .await??
+    };
+
+    if rotated && policy.compression != LogCompression::None {
+        *compressing = Some(tokio::task::spawn_blocking(move || {
+            if let Err(e) = compress(&first, policy.compression) {
+                tracing::warn!("Failed to compress {}: {e:#}", first.display());
+            }
+        }));
+    }
+    Ok(())
+}
+
+fn compress(path: &Path, compression: LogCompression) -> Result<()> {
+    if compression == LogCompression::None {
+        return Ok(());
+    }
+    // Written under a temporary name and renamed into place once complete,
+    // so a crash never leaves a partial segment that looks whole
+    let target = PathBuf::from(format!("{}{}", path.display(), compression.extension()));
+    let partial = PathBuf::from(format!("{}.tmp", target.display()));
+    let mut input = std::fs::File::open(path)?;
+    let output = std::fs::File::create(&partial)?;
+    match compression {
+        LogCompression::Gzip => {
+            let mut encoder = flate2::write::GzEncoder::new(output, flate2::Compression::default());
+            std::io::copy(&mut input, &mut encoder)?;
+            encoder.finish()?.sync_all()?;
+        }
+        LogCompression::Zstd => {
+            let mut encoder = zstd::Encoder::new(output, 0)?;
+            std::io::copy(&mut input, &mut encoder)?;
+            encoder.finish()?.sync_all()?;
+        }
+        LogCompression::None => unreachable!(),
+    }
+    std::fs::rename(&partial, &target)?;
+    std::fs::remove_file(path)?;
+    Ok(())
+}
+
+/// `(N, path)` of every `output.log.N[.gz|.zst]` in `dir`. A rotation
+/// interrupted while compressing leaves `output.log.N` next to its complete
+/// compressed copy; only the copy counts.
+fn rotated_segments(dir: &Path) -> Vec<(u32, PathBuf)> {
+    let mut segments: BTreeMap<u32, PathBuf> = BTreeMap::new();
+    for (n, compressed, path) in segment_files(dir) {
+        if compressed || !segments.contains_key(&n) {
+            segments.insert(n, path);
+        }
+    }
+    segments.into_iter().collect()
+}
+
+/// `(N, compressed, path)` of the files that look like rotated segments.
+fn segment_files(dir: &Path) -> Vec<(u32, bool, PathBuf)> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    entries
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let rest = name.strip_prefix(&format!("{LOG_NAME}."))?;
+            let (n, extension) = rest.split_once('.').unwrap_or((rest, ""));
+            let compressed = match extension {
+                "" => false,
+                "gz" | "zst" => true,
+                _ => return None,
+            };
+            Some((n.parse().ok()?, compressed, entry.path()))
+        })
+        .collect()
+}
+
+/// Delete what an interrupted rotation left behind: partial compressed
+/// segments, and uncompressed ones that were already compressed.
+fn remove_leftovers(dir: &Path) -> std::io::Result<()> {
+    let kept: Vec<PathBuf> = rotated_segments(dir).into_iter().map(|(_, p)| p).collect();
+    for (_, _, path) in segment_files(dir) {
+        if !kept.contains(&path) {
+            std::fs::remove_file(path)?;
+        }
+    }
+    if let Ok(entries) = std::fs::read_dir(dir) {
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if name.starts_with(LOG_NAME) && name.ends_with(".tmp") {
+                std::fs::remove_file(entry.path())?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// All of a session's log files, oldest first, ending with `output.log`.
+pub fn log_segments(dir: &Path) -> Vec<PathBuf> {
+    let mut segments = rotated_segments(dir);
+    segments.sort_by_key(|(n, _)| std::cmp::Reverse(*n));
+    let mut paths: Vec<PathBuf> = segments.into_iter().map(|(_, p)| p).collect();
+    let current = dir.join(LOG_NAME);
+    if current.exists() {
+        paths.push(current);
+    }
+    paths
+}
+
+/// Read one segment, decompressing it if needed.
+pub fn read_segment(path: &Path) -> Result<Vec<u8>> {
+    use std::io::Read;
+
+    let file =
+        std::fs::File::open(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let mut data = Vec::new();
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("gz") => {
+            flate2::read::GzDecoder::new(file).read_to_end(&mut data)?;
+        }
+        Some("zst") => {
+            zstd::Decoder::new(file)?.read_to_end(&mut data)?;
+        }
+        _ => {
+            std::io::BufReader::new(file).read_to_end(&mut data)?;
+        }
+    }
+    Ok(data)
+}
+
+/// A session's whole saved output, across rotated segments.
+pub fn read_log(dir: &Path) -> Result<Vec<u8>> {
+    let mut data = Vec::new();
+    for segment in log_segments(dir) {
+        data.extend(read_segment(&segment)?);
+    }
+    Ok(data)
+}
@@ -0,0 +1,72 @@
+pub mod sqlite;
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::session::model::{SessionMeta, SessionStatus};
+use crate::session::query::SessionFilter;
+
+pub use sqlite::SqliteStore;
+
+/// A change of a session's status, as recorded by [`Store::save_session`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StatusTransition {
+    pub status: SessionStatus,
+    pub at: DateTime<Utc>,
+}
+
+/// Something that happened in a session, such as a permission prompt or an
+/// approval decision.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionEvent {
+    pub kind: String,
+    pub at: DateTime<Utc>,
+    #[serde(default)]
+    pub data: serde_json::Value,
+}
+
+impl SessionEvent {
+    pub fn new(kind: &str, data: serde_json::Value) -> Self {
+        Self {
+            kind: kind.to_string(),
+            at: Utc::now(),
+            data,
+        }
+    }
+}
+
+/// Durable session metadata and history.
+pub trait Store: Send + Sync {
+    /// Insert or update a session, recording a transition when its status
+    /// differs from the stored one.
+    fn save_session(&self, meta: &SessionMeta) -> Result<()>;
+
+    fn session(&self, id: Uuid) -> Result<Option<SessionMeta>>;
+
+    /// Sessions matching `filter`, newest first, with the total number of
+    /// matches before paging.
+    fn list_sessions(&self, filter: &SessionFilter) -> Result<(Vec<SessionMeta>, usize)>;
+
+    /// Sessions named exactly `query`, or else those whose id starts with it.
+    fn find_sessions(&self, query: &str) -> Result<Vec<SessionMeta>>;
+
+    /// Number of sessions per `(status, tool)`; errored sessions count as
+    /// `errored` whatever the error.
+    fn session_counts(&self) -> Result<Vec<(String, String, u64)>>;
+
+    /// Status changes, oldest first.
+    fn transitions(&self, id: Uuid) -> Result<Vec<StatusTransition>>;
+
+    fn tags(&self, id: Uuid) -> Result<Vec<String>>;
+
+    /// Replace a session's tags.
+    fn set_tags(&self, id: Uuid, tags: &[String]) -> Result<()>;
+
+    /// Append events, in one transaction.
+    fn record_events(&self, id: Uuid, events: &[SessionEvent]) -> Result<()>;
+
+    /// The most recent `limit` events, oldest first.
+    fn events(&self, id: Uuid, limit: usize) -> Result<Vec<SessionEvent>>;
+}
@@ -0,0 +1,405 @@
+use std::path::Path;
+use std::sync::{Mutex, MutexGuard};
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, SecondsFormat, Utc};
+use rusqlite::{Connection, OptionalExtension, Transaction, params, params_from_iter};
+use uuid::Uuid;
+
+use crate::session::model::{SessionMeta, SessionStatus};
+use crate::session::persist::{self, MetaError};
+use crate::session::query::SessionFilter;
+use crate::store::{SessionEvent, StatusTransition, Store};
+
+/// Schema changes, applied in order; `PRAGMA user_version` counts how many
+/// have run.
+enum Migration {
+    Sql(&'static str),
+    /// Import the `meta.json` of every session directory
+    ImportMetaFiles,
+}
+
+const MIGRATIONS: &[Migration] = &[
+    Migration::Sql(
+        "CREATE TABLE sessions (
+            id TEXT PRIMARY KEY,
+            name TEXT NOT NULL,
+            tool TEXT NOT NULL,
+            status TEXT NOT NULL,
+            working_dir TEXT NOT NULL,
+            created_at TEXT NOT NULL,
+            updated_at TEXT NOT NULL,
+            meta TEXT NOT NULL
+        );
+        CREATE INDEX sessions_created_at ON sessions (created_at);
+        CREATE INDEX sessions_status ON sessions (status);
+        CREATE INDEX sessions_name ON sessions (name);
+
+        CREATE TABLE status_transitions (
+            id INTEGER PRIMARY KEY,
+            session_id TEXT NOT NULL REFERENCES sessions (id) ON DELETE CASCADE,
+            status TEXT NOT NULL,
+            detail TEXT,
+            at TEXT NOT NULL
+        );
+        CREATE INDEX status_transitions_session ON status_transitions (session_id);
+
+        CREATE TABLE tags (
+            session_id TEXT NOT NULL REFERENCES sessions (id) ON DELETE CASCADE,
+            tag TEXT NOT NULL,
+            PRIMARY KEY (session_id, tag)
+        );
+        CREATE INDEX tags_tag ON tags (tag);
+
+        CREATE TABLE events (
+            id INTEGER PRIMARY KEY,
+            session_id TEXT NOT NULL REFERENCES sessions (id) ON DELETE CASCADE,
+            kind TEXT NOT NULL,
+            data TEXT NOT NULL,
+            at TEXT NOT NULL
+        );
+        CREATE INDEX events_session ON events (session_id, id);",
+    ),
+    Migration::ImportMetaFiles,
+];
+
+/// [`Store`] backed by a SQLite database, shared by the server and the CLI.
+pub struct SqliteStore {
+    conn: Mutex<Connection>,
+}
+
+impl SqliteStore {
+    /// Open (or create) the database at `path` and bring its schema up to
+    /// date. `sessions_dir` is where existing `meta.json` files are imported
+    /// from.
+    pub fn open(path: &Path, sessions_dir: &Path) -> Result<Self> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let mut conn =
+            Connection::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+        conn.busy_timeout(std::time::Duration::from_secs(5))?;
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        conn.pragma_update(None, "synchronous", "NORMAL")?;
+        conn.pragma_update(None, "foreign_keys", "ON")?;
+        migrate(&mut conn, sessions_dir)
+            .with_context(|| format!("Failed to migrate {}", path.display()))?;
+        Ok(Self {
+            conn: Mutex::new(conn),
+        })
+    }
+
+    fn conn(&self) -> MutexGuard<'_, Connection> {
+        self.conn.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+fn migrate(conn: &mut Connection, sessions_dir: &Path) -> Result<()> {
+    let version: usize = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+    for (i, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        let tx = conn.transaction()?;
+        match migration {
+            Migration::Sql(sql) => tx.execute_batch(sql)?,
+            Migration::ImportMetaFiles => {
+                let imported = import_meta_files(&tx, sessions_dir)?;
+                if imported > 0 {
+                    tracing::info!("Imported {imported} sessions from meta.json files");
+                }
+            }
+        }
+        tx.pragma_update(None, "user_version", i + 1)?;
+        tx.commit()?;
+    }
+    Ok(())
+}
+
+fn import_meta_files(tx: &Transaction, sessions_dir: &Path) -> Result<usize> {
+    let Ok(entries) = std::fs::read_dir(sessions_dir) else {
+        return Ok(0);
+    };
+    let mut imported = 0;
+    for entry in entries.flatten() {
+        let dir = entry.path();
+        let meta = match persist::load(&dir) {
+            Ok(meta) => meta,
+            Err(MetaError::Missing | MetaError::Corrupt(_)) => match persist::recover(&dir) {
+                Some(meta) => meta,
+                None => continue,
+            },
+            Err(e) => {
+                tracing::warn!("Skipping {}: {e}", dir.display());
+                continue;
+            }
+        };
+
+        upsert_session(tx, &meta)?;
+        // The history is lost; keep where the session started and ended up
+        insert_transition(tx, meta.id, &SessionStatus::Running, meta.created_at)?;
+        if meta.status != SessionStatus::Running {
+            insert_transition(tx, meta.id, &meta.status, meta.updated_at)?;
+        }
+        imported += 1;
+    }
+    Ok(imported)
+}
+
+/// Timestamps are stored as fixed-width RFC 3339 so they sort as text.
+fn timestamp(t: DateTime<Utc>) -> String {
+    t.to_rfc3339_opts(SecondsFormat::Micros, true)
+}
+
+fn parse_timestamp(s: &str) -> rusqlite::Result<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(s)
+        .map(|t| t.with_timezone(&Utc))
+        .map_err(|e| rusqlite::Error::ToSqlConversionFailure(e.into()))
+}
+
+fn status_parts(status: &SessionStatus) -> (&'static str, Option<&str>) {
+    match status {
+        SessionStatus::Running => ("running", None),
+        SessionStatus::Stopped => ("stopped", None),
+        SessionStatus::Errored(detail) => ("errored", Some(detail)),
+    }
+}
+
+fn status_from_parts(status: &str, detail: Option<String>) -> SessionStatus {
+    match status {
+        "running" => SessionStatus::Running,
+        "stopped" => SessionStatus::Stopped,
+        _ => SessionStatus::Errored(detail.unwrap_or_default()),
+    }
+}
+
+fn upsert_session(conn: &Connection, meta: &SessionMeta) -> Result<()> {
+    conn.execute(
+        "INSERT INTO sessions (id, name, tool, status, working_dir, created_at, updated_at, meta)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
+         ON CONFLICT (id) DO UPDATE SET
+             name = excluded.name,
+             tool = excluded.tool,
+             status = excluded.status,
+             working_dir = excluded.working_dir,
+             updated_at = excluded.updated_at,
+             meta = excluded.meta",
+        params![
+            meta.id.to_string(),
+            meta.name,
+            meta.tool.to_string(),
+            status_parts(&meta.status).0,
+            meta.working_dir.to_string_lossy(),
+            timestamp(meta.created_at),
+            timestamp(meta.updated_at),
+            serde_json::to_string(meta)?,
+        ],
+    )?;
+    Ok(())
+}
+
+fn insert_transition(
+    conn: &Connection,
+    id: Uuid,
+    status: &SessionStatus,
+    at: DateTime<Utc>,
+) -> Result<()> {
+    let (status, detail) = status_parts(status);
+    conn.execute(
+        "INSERT INTO status_transitions (session_id, status, detail, at) VALUES (?1, ?2, ?3, ?4)",
+        params![id.to_string(), status, detail, timestamp(at)],
+    )?;
+    Ok(())
+}
+
+fn metas(conn: &Connection, sql: &str, params: impl rusqlite::Params) -> Result<Vec<SessionMeta>> {
+    let mut stmt = conn.prepare(sql)?;
+    let rows = stmt.query_map(params, |row| row.get::<_, String>(0))?;
+    let mut metas = Vec::new();
+    for json in rows {
+        metas.push(persist::from_value(serde_json::from_str(&json?)?)?);
+    }
+    Ok(metas)
+}
+
+impl Store for SqliteStore {
+    fn save_session(&self, meta: &SessionMeta) -> Result<()> {
+        let mut conn = self.conn();
+        let tx = conn.transaction()?;
+        let previous: Option<String> = tx
+            .query_row(
+                "SELECT meta FROM sessions WHERE id = ?1",
+                [meta.id.to_string()],
+                |row| row.get(0),
+            )
+            .optional()?;
+        let previous = previous
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .and_then(|value| persist::from_value(value).ok())
+            .map(|m| m.status);
+        upsert_session(&tx, meta)?;
+        if previous.as_ref() != Some(&meta.status) {
+            let at = if previous.is_none() {
+                meta.created_at
+            } else {
+                meta.updated_at
+            };
+            insert_transition(&tx, meta.id, &meta.status, at)?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn session(&self, id: Uuid) -> Result<Option<SessionMeta>> {
+        let conn = self.conn();
+        Ok(metas(
+            &conn,
+            "SELECT meta FROM sessions WHERE id = ?1",
+            [id.to_string()],
+        )?
+        .pop())
+    }
+
+    fn list_sessions(&self, filter: &SessionFilter) -> Result<(Vec<SessionMeta>, usize)> {
+        let mut clauses = Vec::new();
+        let mut args: Vec<String> = Vec::new();
+        if let Some(ref status) = filter.status {
+            clauses.push("status = ?");
+            args.push(status.clone());
+        }
+        if let Some(ref tool) = filter.tool {
+            clauses.push("tool = ?");
+            args.push(tool.to_string());
+        }
+        // Same as Path::starts_with: the directory itself or anything below it
+        if let Some(ref cwd) = filter.cwd {
+            let cwd = cwd.to_string_lossy();
+            let cwd = cwd.trim_end_matches('/');
+            if !cwd.is_empty() {
+                clauses
+                    .push("(working_dir = ? OR substr(working_dir, 1, length(?) + 1) = ? || '/')");
+                args.extend([cwd.to_string(), cwd.to_string(), cwd.to_string()]);
+            }
+        }
+        if let Some(since) = filter.since {
+            clauses.push("created_at >= ?");
+            args.push(timestamp(since));
+        }
+        if let Some(ref tag) = filter.tag {
+            clauses.push("id IN (SELECT session_id FROM tags WHERE tag = ?)");
+            args.push(tag.clone());
+        }
+        let condition = if clauses.is_empty() {
+            String::new()
+        } else {
+            format!("WHERE {}", clauses.join(" AND "))
+        };
+
+        let conn = self.conn();
+        let total: i64 = conn.query_row(
+            &format!("SELECT COUNT(*) FROM sessions {condition}"),
+            params_from_iter(&args),
+            |row| row.get(0),
+        )?;
+        args.push(filter.limit.to_string());
+        args.push(filter.offset.to_string());
+        let page = metas(
+            &conn,
+            &format!(
+                "SELECT meta FROM sessions {condition}
+                 ORDER BY created_at DESC LIMIT CAST(? AS INTEGER) OFFSET CAST(? AS INTEGER)"
+            ),
+            params_from_iter(&args),
+        )?;
+        Ok((page, total as usize))
+    }
+
+    fn find_sessions(&self, query: &str) -> Result<Vec<SessionMeta>> {
+        let conn = self.conn();
+        let by_name = metas(&conn, "SELECT meta FROM sessions WHERE name = ?1", [query])?;
+        if !by_name.is_empty() {
+            return Ok(by_name);
+        }
+        metas(
+            &conn,
+            "SELECT meta FROM sessions WHERE substr(id, 1, length(?1)) = ?1",
+            [query.to_lowercase()],
+        )
+    }
+
+    fn session_counts(&self) -> Result<Vec<(String, String, u64)>> {
+        let conn = self.conn();
+        let mut stmt =
+            conn.prepare("SELECT status, tool, COUNT(*) FROM sessions GROUP BY status, tool")?;
+        let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    fn transitions(&self, id: Uuid) -> Result<Vec<StatusTransition>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare(
+            "SELECT status, detail, at FROM status_transitions WHERE session_id = ?1 ORDER BY id",
+        )?;
+        let rows = stmt.query_map([id.to_string()], |row| {
+            Ok(StatusTransition {
+                status: status_from_parts(&row.get::<_, String>(0)?, row.get(1)?),
+                at: parse_timestamp(&row.get::<_, String>(2)?)?,
+            })
+        })?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    fn tags(&self, id: Uuid) -> Result<Vec<String>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare("SELECT tag FROM tags WHERE session_id = ?1 ORDER BY tag")?;
+        let rows = stmt.query_map([id.to_string()], |row| row.get(0))?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    fn set_tags(&self, id: Uuid, tags: &[String]) -> Result<()> {
+        let mut conn = self.conn();
+        let tx = conn.transaction()?;
+        tx.execute("DELETE FROM tags WHERE session_id = ?1", [id.to_string()])?;
+        for tag in tags {
+            tx.execute(
+                "INSERT OR IGNORE INTO tags (session_id, tag) VALUES (?1, ?2)",
+                params![id.to_string(), tag],
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn record_events(&self, id: Uuid, events: &[SessionEvent]) -> Result<()> {
+        let mut conn = self.conn();
+        let tx = conn.transaction()?;
+        for event in events {
+            tx.execute(
+                "INSERT INTO events (session_id, kind, data, at) VALUES (?1, ?2, ?3, ?4)",
+                params![
+                    id.to_string(),
+                    event.kind,
+                    event.data.to_string(),
+                    timestamp(event.at)
+                ],
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn events(&self, id: Uuid, limit: usize) -> Result<Vec<SessionEvent>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare(
+            "SELECT kind, data, at FROM events WHERE session_id = ?1 ORDER BY id DESC LIMIT ?2",
+        )?;
+        let rows = stmt.query_map(params![id.to_string(), limit as i64], |row| {
+            Ok(SessionEvent {
+                kind: row.get(0)?,
+                data: serde_json::from_str(&row.get::<_, String>(1)?)
+                    .unwrap_or(serde_json::Value::Null),
+                at: parse_timestamp(&row.get::<_, String>(2)?)?,
+            })
+        })?;
+        let mut events = rows.collect::<rusqlite::Result<Vec<_>>>()?;
+        events.reverse();
+        Ok(events)
+    }
+}
@@ -0,0 +1,136 @@
+use anyhow::{Context, Result};
+use opentelemetry::trace::TracerProvider;
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{EnvFilter, Layer, Registry};
+
+use crate::config::{Config, LogFormat, LoggingConfig};
+
+type BoxedLayer = Box<dyn Layer<Registry> + Send + Sync>;
+
+/// Logging for CLI commands: plain text on stderr.
+pub fn init_cli() {
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")),
+        )
+        .with_writer(std::io::stderr)
+        .with_ansi(std::io::IsTerminal::is_terminal(&std::io::stderr()))
+        .init();
+}
+
+/// Keeps the server's log file writer and span exporter running; dropping
+/// it flushes both.
+pub struct Telemetry {
+    _file: Option<WorkerGuard>,
+    tracer: Option<SdkTracerProvider>,
+}
+
+impl Drop for Telemetry {
+    fn drop(&mut self) {
+        if let Some(tracer) = self.tracer.take()
+            && let Err(e) = tracer.shutdown()
+        {
+            eprintln!("Failed to flush spans: {e}");
+        }
+    }
+}
+
+/// Logging for `forge serve`, as configured in `[logging]`.
+pub fn init_server(config: &LoggingConfig) -> Result<Telemetry> {
+    let filter = EnvFilter::try_from_default_env()
+        .or_else(|_| EnvFilter::try_new(&config.level))
+        .with_context(|| format!("Invalid logging.level: {}", config.level))?;
+
+    let mut layers: Vec<BoxedLayer> = vec![fmt_layer(
+        config.format,
+        std::io::stderr,
+        std::io::IsTerminal::is_terminal(&std::io::stderr()),
+    )];
+
+    let file = if config.file {
+        let dir = Config::data_dir().join("logs");
+        std::fs::create_dir_all(&dir)
+            .with_context(|| format!("Failed to create {}", dir.display()))?;
+        let appender = RollingFileAppender::builder()
+            .rotation(Rotation::DAILY)
+            .filename_prefix("server")
+            .filename_suffix("log")
+            .max_log_files(config.keep_files.max(1))
+            .build(&dir)
+            .with_context(|| format!("Failed to open a log file in {}", dir.display()))?;
+        let (writer, guard) = tracing_appender::non_blocking(appender);
+        layers.push(fmt_layer(config.format, writer, false));
+        Some(guard)
+    } else {
+        None
+    };
+
+    let tracer = match &config.otlp_endpoint {
+        Some(endpoint) => {
+            let provider = otlp_provider(endpoint)?;
+            let tracer = provider.tracer("lineforge");
+            layers.push(tracing_opentelemetry::layer().with_tracer(tracer).boxed());
+            Some(provider)
+        }
+        None => None,
+    };
+
+    tracing_subscriber::registry()
+        .with(layers.with_filter(filter))
+        .init();
+    if let Some(endpoint) = &config.otlp_endpoint {
+        tracing::info!("Sending spans to {endpoint}");
+    }
+    Ok(Telemetry {
+        _file: file,
+        tracer,
+    })
+}
+
+fn fmt_layer<W>(format: LogFormat, writer: W, ansi: bool) -> BoxedLayer
+where
+    W: for<'w> tracing_subscriber::fmt::MakeWriter<'w> + Send + Sync + 'static,
+{
+    let layer = tracing_subscriber::fmt::layer()
+        .with_writer(writer)
+        .with_ansi(ansi);
+    match format {
+        LogFormat::Text => layer.boxed(),
+        LogFormat::Json => layer.json().boxed(),
+    }
+}
+
+/// Batch spans to an OTLP/HTTP collector.
+fn otlp_provider(endpoint: &str) -> Result<SdkTracerProvider> {
+    let endpoint = endpoint.trim_end_matches('/');
+    let url = if endpoint.ends_with("/v1/traces") {
+        endpoint.to_string()
+    } else {
+        format!("{endpoint}/v1/traces")
+    };
+    // The exporter's blocking HTTP client can't be created on an async
+    // thread; it is only used from the batch processor's own thread
+    let exporter = std::thread::spawn(move || {
+        opentelemetry_otlp::SpanExporter::builder()
+            .with_http()
+            .with_endpoint(url)
+            .with_timeout(std::time::Duration::from_secs(5))
+            .build()
+    })
+    .join()
+    .map_err(|_| anyhow::anyhow!("OTLP exporter setup panicked"))?
+    .context("Failed to set up the OTLP exporter")?;
+
+    let resource = opentelemetry_sdk::Resource::builder()
+        .with_service_name("lineforge")
+        .build();
+    Ok(SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(resource)
+        .build())
+}
@@ -0,0 +1,210 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::CryptoProvider;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime};
+use rustls::{DigitallySignedStruct, SignatureScheme};
+
+use crate::config::Config;
+
+fn provider() -> Arc<CryptoProvider> {
+    Arc::new(rustls::crypto::ring::default_provider())
+}
+
+/// Where a generated self-signed certificate and its key are kept.
+pub fn self_signed_paths() -> (PathBuf, PathBuf) {
+    let dir = Config::data_dir().join("tls");
+    (dir.join("cert.pem"), dir.join("key.pem"))
+}
+
+/// Create a self-signed certificate for `hosts` (names or IPs) unless one
+/// was already generated. Kept across restarts so clients can pin it.
+pub fn ensure_self_signed(hosts: &[String]) -> Result<()> {
+    let (cert_path, key_path) = self_signed_paths();
+    if cert_path.exists() && key_path.exists() {
+        return Ok(());
+    }
+
+    let generated = rcgen::generate_simple_self_signed(hosts.to_vec())
+        .context("Failed to generate a self-signed certificate")?;
+    if let Some(dir) = cert_path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    write_private(&key_path, generated.key_pair.serialize_pem().as_bytes())?;
+    std::fs::write(&cert_path, generated.cert.pem())
+        .with_context(|| format!("Failed to write {}", cert_path.display()))?;
+    tracing::info!(
+        "Generated a self-signed certificate for {} at {} (SHA-256 {})",
+        hosts.join(", "),
+        cert_path.display(),
+        fingerprint(generated.cert.der()),
+    );
+    Ok(())
+}
+
+fn write_private(path: &Path, data: &[u8]) -> Result<()> {
+    use std::io::Write;
+    use std::os::unix::fs::OpenOptionsExt;
+
+    let mut file = std::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+    file.write_all(data)?;
+    Ok(())
+}
+
+fn load_certs(path: &Path) -> Result<Vec<CertificateDer<'static>>> {
+    let pem = std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let certs = rustls_pemfile::certs(&mut pem.as_slice())
+        .collect::<Result<Vec<_>, _>>()
+        .with_context(|| format!("Invalid certificate in {}", path.display()))?;
+    anyhow::ensure!(!certs.is_empty(), "No certificate in {}", path.display());
+    Ok(certs)
+}
+
+fn load_key(path: &Path) -> Result<PrivateKeyDer<'static>> {
+    let pem = std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    rustls_pemfile::private_key(&mut pem.as_slice())
+        .with_context(|| format!("Invalid private key in {}", path.display()))?
+        .with_context(|| format!("No private key in {}", path.display()))
+}
+
+/// Server-side TLS settings for the configured certificate and key.
+pub fn server_config(cert: &Path, key: &Path) -> Result<rustls::ServerConfig> {
+    let mut config = rustls::ServerConfig::builder_with_provider(provider())
+        .with_safe_default_protocol_versions()?
+        .with_no_client_auth()
+        .with_single_cert(load_certs(cert)?, load_key(key)?)
+        .context("Certificate and key don't match")?;
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    Ok(config)
+}
+
+/// SHA-256 of a DER certificate, as colon-separated hex.
+pub fn fingerprint(der: &[u8]) -> String {
+    ring::digest::digest(&ring::digest::SHA256, der)
+        .as_ref()
+        .iter()
+        .map(|b| format!("{b:02X}"))
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
+/// Normalize a fingerprint as written in the config: case and separators
+/// don't matter.
+fn normalize_fingerprint(value: &str) -> String {
+    value
+        .chars()
+        .filter(char::is_ascii_hexdigit)
+        .map(|c| c.to_ascii_uppercase())
+        .collect()
+}
+
+/// Client-side TLS settings for talking to our own server. The server's
+/// certificate is pinned: either `tls_fingerprint`, or the certificate file
+/// the server is configured with.
+pub fn client_config(config: &Config) -> Result<rustls::ClientConfig> {
+    let pinned = match (&config.tls_fingerprint, config.tls_files()) {
+        (Some(fp), _) => normalize_fingerprint(fp),
+        (None, Some((cert, _))) => {
+            let certs = load_certs(&cert)?;
+            normalize_fingerprint(&fingerprint(&certs[0]))
+        }
+        (None, None) => anyhow::bail!("TLS is not configured"),
+    };
+    let verifier = PinnedCert {
+        fingerprint: pinned,
+        provider: provider(),
+    };
+    Ok(rustls::ClientConfig::builder_with_provider(provider())
+        .with_safe_default_protocol_versions()?
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(verifier))
+        .with_no_client_auth())
+}
+
+/// Accepts exactly one certificate, by fingerprint. Host names aren't
+/// checked: the server may be reached by any of its addresses.
+#[derive(Debug)]
+struct PinnedCert {
+    fingerprint: String,
+    provider: Arc<CryptoProvider>,
+}
+
+impl ServerCertVerifier for PinnedCert {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        if normalize_fingerprint(&fingerprint(end_entity)) == self.fingerprint {
+            Ok(ServerCertVerified::assertion())
+        } else {
+            Err(rustls::Error::General(format!(
+                "server certificate {} doesn't match the pinned fingerprint",
+                fingerprint(end_entity)
+            )))
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
+
+/// Whether the server would serve a self-signed certificate that it hasn't
+/// generated yet, so there is nothing to pin.
+pub fn self_signed_pending(config: &Config) -> bool {
+    config.tls_fingerprint.is_none()
+        && config.tls_cert.is_none()
+        && config.tls_self_signed
+        && !self_signed_paths().0.exists()
+}
+
+/// An HTTP client for the CLI that trusts the configured server certificate.
+pub fn client_builder(config: &Config) -> Result<reqwest::ClientBuilder> {
+    let builder = reqwest::Client::builder();
+    if config.tls_fingerprint.is_none() && config.tls_files().is_none() {
+        return Ok(builder);
+    }
+    Ok(builder.use_preconfigured_tls(client_config(config)?))
+}
@@ -62,23 +62,34 @@
       });
     });
 
-    // Connect SSE for log streaming
+    // Connect SSE for log streaming. On reconnect the browser sends
+    // Last-Event-ID and the server replays only what was missed.
     var evtSource = new EventSource('/api/sessions/' + SESSION_ID + '/logs');
-    var connected = false;
 
     evtSource.onopen = function() {
-      if (connected) {
-        term.reset();
-      }
-      connected = true;
+      container.classList.remove('disconnected');
     };
 
+    // The server no longer has the log we were following; redraw from scratch
+    evtSource.addEventListener('reset', function() {
+      term.reset();
+    });
+
+    // Entries carry raw PTY bytes as base64; xterm.js decodes UTF-8 itself,
+    // including characters split across entries
     evtSource.addEventListener('log', function(e) {
       try {
         var entry = JSON.parse(e.data);
-        term.write(entry.data);
+        term.write(base64ToBytes(entry.data));
+      } catch (err) {}
+    });
+
+    // Permission prompt detected by the server -> big approve/deny buttons
+    evtSource.addEventListener('prompt', function(e) {
+      try {
+        renderPrompt(JSON.parse(e.data));
       } catch (err) {
-        term.write(e.data);
+        renderPrompt(null);
       }
     });
 
@@ -95,7 +106,7 @@
     });
 
     evtSource.onerror = function() {
-      term.write('\r\n--- Connection lost, reconnecting... ---\r\n');
+      container.classList.add('disconnected');
     };
 
     // Line-buffered input form as fallback
@@ -118,6 +129,158 @@
     container.addEventListener('click', function() {
       term.focus();
     });
+
+    pollResources();
+  }
+
+  // CPU, memory, threads and open files of the session's process tree,
+  // sampled by the server
+  function pollResources() {
+    fetch('/api/sessions/' + SESSION_ID + '/resources')
+      .then(function(resp) {
+        if (!resp.ok) throw new Error(resp.statusText);
+        return resp.json();
+      })
+      .then(function(report) {
+        renderResources(report);
+        // Nothing more to sample once the process is gone
+        if (report.current && report.interval_secs > 0) {
+          setTimeout(pollResources, report.interval_secs * 1000);
+        }
+      })
+      .catch(function() {
+        setTimeout(pollResources, 10000);
+      });
+  }
+
+  function renderResources(report) {
+    var panel = document.getElementById('resources');
+    var history = report.history;
+    panel.hidden = history.length === 0;
+    var latest = report.current || history[history.length - 1];
+    panel.querySelectorAll('.resource').forEach(function(el) {
+      var metric = el.dataset.metric;
+      var values = history.map(function(s) { return s[metric]; });
+      el.querySelector('.resource-value').textContent =
+        latest ? formatResource(metric, latest[metric]) : '';
+      el.querySelector('polyline').setAttribute('points', sparkline(values));
+    });
+
+    var processes = document.getElementById('process-panel');
+    var tree = document.getElementById('process-tree');
+    processes.hidden = !report.processes;
+    tree.innerHTML = '';
+    if (report.processes) {
+      document.getElementById('process-count').textContent = latest.processes;
+      tree.appendChild(processItem(report.processes));
+    }
+  }
+
+  // Points for an SVG polyline in a 100x20 viewBox, scaled to the maximum
+  function sparkline(values) {
+    if (values.length === 0) return '';
+    var max = Math.max.apply(null, values) || 1;
+    var step = values.length > 1 ? 100 / (values.length - 1) : 0;
+    return values.map(function(v, i) {
+      return (i * step).toFixed(1) + ',' + (19 - (v / max) * 18).toFixed(1);
+    }).join(' ');
+  }
+
+  function formatResource(metric, value) {
+    if (metric === 'cpu_percent') return value.toFixed(1) + '%';
+    if (metric === 'rss_bytes') return formatBytes(value);
+    return String(value);
+  }
+
+  function formatBytes(bytes) {
+    var units = ['B', 'KB', 'MB', 'GB'];
+    var i = 0;
+    while (bytes >= 1024 && i < units.length - 1) {
+      bytes /= 1024;
+      i++;
+    }
+    return bytes.toFixed(i === 0 ? 0 : 1) + ' ' + units[i];
+  }
+
+  function processItem(node) {
+    var li = document.createElement('li');
+    var row = document.createElement('div');
+    row.className = 'process-row';
+    var command = document.createElement('span');
+    command.className = 'process-command';
+    command.textContent = node.command;
+    command.title = node.command;
+    var stats = document.createElement('span');
+    stats.className = 'process-stats';
+    stats.textContent = 'pid ' + node.pid + ' \u00b7 ' + node.cpu_percent.toFixed(1) + '% \u00b7 ' +
+      formatBytes(node.rss_bytes) + ' \u00b7 ' + node.threads + ' threads \u00b7 ' +
+      node.open_files + ' files';
+    row.appendChild(command);
+    row.appendChild(stats);
+    li.appendChild(row);
+    if (node.children.length > 0) {
+      var ul = document.createElement('ul');
+      node.children.forEach(function(child) {
+        ul.appendChild(processItem(child));
+      });
+      li.appendChild(ul);
+    }
+    return li;
+  }
+
+  function refreshPrompt() {
+    fetch('/api/sessions/' + SESSION_ID + '/prompt')
+      .then(function(resp) { return resp.ok ? resp.json() : null; })
+      .then(renderPrompt)
+      .catch(function() {});
+  }
+
+  function renderPrompt(prompt) {
+    var panel = document.getElementById('prompt-panel');
+    var options = document.getElementById('prompt-options');
+    if (!prompt) {
+      panel.hidden = true;
+      options.innerHTML = '';
+      return;
+    }
+
+    document.getElementById('prompt-question').textContent = prompt.question;
+    var action = [prompt.tool, prompt.command || prompt.path].filter(Boolean).join(': ');
+    var actionEl = document.getElementById('prompt-action');
+    actionEl.textContent = action;
+    actionEl.hidden = !action;
+
+    options.innerHTML = '';
+    prompt.options.forEach(function(opt, idx) {
+      var btn = document.createElement('button');
+      var label = opt.label.toLowerCase();
+      btn.className = 'btn prompt-option';
+      if (label.indexOf('yes') === 0) btn.className += ' btn-approve';
+      if (label.indexOf('no') === 0) btn.className += ' btn-danger';
+      btn.textContent = opt.label;
+      btn.addEventListener('click', function() {
+        panel.hidden = true;
+        fetch('/api/sessions/' + SESSION_ID + '/prompt', {
+          method: 'POST',
+          headers: { 'Content-Type': 'application/json' },
+          body: JSON.stringify({ option: idx, prompt_id: prompt.id })
+        }).then(function(resp) {
+          // Answered a prompt that has since been replaced; show the new one
+          if (resp.status === 409) refreshPrompt();
+        });
+      });
+      options.appendChild(btn);
+    });
+    panel.hidden = false;
+  }
+
+  function base64ToBytes(b64) {
+    var bin = atob(b64);
+    var bytes = new Uint8Array(bin.length);
+    for (var i = 0; i < bin.length; i++) {
+      bytes[i] = bin.charCodeAt(i);
+    }
+    return bytes;
   }
 
   function initNewForm(form) {